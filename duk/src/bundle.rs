@@ -0,0 +1,34 @@
+use clap::ArgMatches;
+use duktape::prelude::*;
+use duktape_modules::bundle;
+use duktape_modules::error::Result;
+use std::fs;
+
+/// Duktape bytecode starts with a byte, which is never valid in UTF-8 source
+const BYTECODE_MARKER: u8 = 0xBF;
+
+/// Bundle the entry of `matches` into the output file
+pub fn run(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+    let script = bundle::bundle(ctx, matches.value_of("input").unwrap())?;
+    let output = matches.value_of("output").unwrap();
+
+    if matches.is_present("bytecode") {
+        fs::write(output, bundle::bytecode(ctx, &script, output)?)?;
+    } else {
+        fs::write(output, script)?;
+    }
+
+    Ok(())
+}
+
+/// Whether the payload of an executable built by `duk compile` is bytecode.
+/// Bytecode is not validated when loaded, so files given to duk are never sniffed
+pub fn is_bytecode(content: &[u8]) -> bool {
+    content.first() == Some(&BYTECODE_MARKER)
+}
+
+/// Run a bundle compiled to bytecode, which must be trusted
+pub fn eval_bytecode(ctx: &Context, bytecode: &[u8]) -> Result<()> {
+    ctx.push_bytes(bytecode).load_function()?.call(0)?.pop(1);
+    Ok(())
+}
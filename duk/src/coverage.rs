@@ -0,0 +1,108 @@
+use colored::*;
+use duktape::coverage::{Coverage, FileCoverage};
+use duktape_modules::error::Result;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The lcov report of `duk test --coverage`, unless given by `--lcov`
+pub static DEFAULT_LCOV: &'static str = "coverage/lcov.info";
+
+/// Leave out builtin modules, vendored modules and the tests themselves
+pub fn sources(coverage: Coverage, tests: &[PathBuf]) -> Coverage {
+    let tests: Vec<PathBuf> = tests.iter().filter_map(|t| t.canonicalize().ok()).collect();
+    let files = coverage
+        .files
+        .into_iter()
+        .filter(|(file, _)| {
+            let path = Path::new(file);
+            let vendored = path
+                .components()
+                .any(|c| c == Component::Normal("node_modules".as_ref()));
+            let test = path.canonicalize().map(|p| tests.contains(&p)).unwrap_or(false);
+            path.is_file() && !vendored && !test
+        })
+        .collect();
+    Coverage { files }
+}
+
+pub fn write_lcov(coverage: &Coverage, path: &str) -> Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, coverage.lcov())?;
+    Ok(())
+}
+
+/// Lines as ranges, e.g. "3-5, 9"
+fn ranges(lines: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for line in lines {
+        match ranges.last_mut() {
+            Some(range) if range.1 + 1 == *line => range.1 = *line,
+            _ => ranges.push((*line, *line)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn percent(found: usize, hit: usize) -> ColoredString {
+    let percent = if found == 0 {
+        100.0
+    } else {
+        hit as f64 * 100.0 / found as f64
+    };
+    let text = format!("{:6.2} %", percent);
+    if percent >= 80.0 {
+        text.green()
+    } else if percent >= 50.0 {
+        text.yellow()
+    } else {
+        text.red()
+    }
+}
+
+/// A table of the covered lines of each file, with the lines not run
+pub fn summary(coverage: &Coverage) -> String {
+    let cwd = env::current_dir().unwrap_or_default();
+    let names: Vec<String> = coverage
+        .files
+        .keys()
+        .map(|file| match Path::new(file).strip_prefix(&cwd) {
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => file.clone(),
+        })
+        .collect();
+    let width = names.iter().map(|n| n.len()).chain(Some(9)).max().unwrap();
+
+    let mut out = format!("{:width$}  {:>8}  {}\n", "File", "Lines", "Uncovered", width = width);
+    for (name, file) in names.iter().zip(coverage.files.values()) {
+        out += &format!(
+            "{:width$}  {}  {}\n",
+            name,
+            percent(file.lines_found(), file.lines_hit()),
+            ranges(&file.uncovered()).dimmed(),
+            width = width
+        );
+    }
+
+    let found: usize = coverage.files.values().map(FileCoverage::lines_found).sum();
+    let hit: usize = coverage.files.values().map(FileCoverage::lines_hit).sum();
+    out += &format!(
+        "{}  {}  {}",
+        format!("{:width$}", "All files", width = width).bold(),
+        percent(found, hit),
+        format!("{}/{} lines", hit, found).dimmed()
+    );
+    out
+}
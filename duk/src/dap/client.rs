@@ -0,0 +1,385 @@
+//! A client for the Duktape debug protocol, see `debugger.rst` in the Duktape sources
+use duktape::debugger::{Pipe, PipeReader, PipeWriter};
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub const NFY_STATUS: i64 = 0x01;
+pub const NFY_THROW: i64 = 0x05;
+
+pub const CMD_PAUSE: i64 = 0x12;
+pub const CMD_RESUME: i64 = 0x13;
+pub const CMD_STEP_INTO: i64 = 0x14;
+pub const CMD_STEP_OVER: i64 = 0x15;
+pub const CMD_STEP_OUT: i64 = 0x16;
+pub const CMD_LIST_BREAK: i64 = 0x17;
+pub const CMD_ADD_BREAK: i64 = 0x18;
+pub const CMD_DEL_BREAK: i64 = 0x19;
+pub const CMD_GET_CALL_STACK: i64 = 0x1c;
+pub const CMD_GET_LOCALS: i64 = 0x1d;
+pub const CMD_EVAL: i64 = 0x1e;
+pub const CMD_GET_OBJ_PROP_DESC_RANGE: i64 = 0x25;
+
+/// Property flags in GetObjPropDescRange replies
+pub const PROP_ACCESSOR: i64 = 1 << 3;
+pub const PROP_SYMBOL: i64 = 1 << 8;
+pub const PROP_HIDDEN: i64 = 1 << 9;
+
+const EOM: u8 = 0x00;
+const REQ: u8 = 0x01;
+const REP: u8 = 0x02;
+const ERR: u8 = 0x03;
+const NFY: u8 = 0x04;
+
+/// A value in a debug message
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unused,
+    Undefined,
+    Null,
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    String(String),
+    Buffer(Vec<u8>),
+    /// An object with its class number and heap pointer
+    Object(u8, Vec<u8>),
+    Pointer(Vec<u8>),
+    LightFunc(u16, Vec<u8>),
+    HeapPtr(Vec<u8>),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Number(n) => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Unused => out.push(0x15),
+            Value::Undefined => out.push(0x16),
+            Value::Null => out.push(0x17),
+            Value::Bool(true) => out.push(0x18),
+            Value::Bool(false) => out.push(0x19),
+            Value::Int(i) if *i >= 0 && *i < 0x40 => out.push(0x80 + *i as u8),
+            Value::Int(i) if *i >= 0 && *i < 0x4000 => {
+                out.push(0xc0 + (*i >> 8) as u8);
+                out.push(*i as u8);
+            }
+            Value::Int(i) if *i >= i64::from(i32::MIN) && *i <= i64::from(i32::MAX) => {
+                out.push(0x10);
+                out.extend_from_slice(&(*i as i32).to_be_bytes());
+            }
+            // Integers in the protocol are 32-bit, larger ones are sent as numbers
+            Value::Int(i) => Value::Number(*i as f64).encode(out),
+            Value::Number(n) => {
+                out.push(0x1a);
+                out.extend_from_slice(&n.to_bits().to_be_bytes());
+            }
+            Value::String(s) if s.len() < 0x20 => {
+                out.push(0x60 + s.len() as u8);
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::String(s) => {
+                out.push(0x11);
+                out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::Buffer(b) => {
+                out.push(0x13);
+                out.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                out.extend_from_slice(b);
+            }
+            Value::Object(class, ptr) => {
+                out.push(0x1b);
+                out.push(*class);
+                out.push(ptr.len() as u8);
+                out.extend_from_slice(ptr);
+            }
+            Value::Pointer(ptr) => {
+                out.push(0x1c);
+                out.push(ptr.len() as u8);
+                out.extend_from_slice(ptr);
+            }
+            Value::LightFunc(flags, ptr) => {
+                out.push(0x1d);
+                out.extend_from_slice(&flags.to_be_bytes());
+                out.push(ptr.len() as u8);
+                out.extend_from_slice(ptr);
+            }
+            Value::HeapPtr(ptr) => {
+                out.push(0x1e);
+                out.push(ptr.len() as u8);
+                out.extend_from_slice(ptr);
+            }
+        }
+    }
+}
+
+fn read_bytes<R: Read>(r: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_string<R: Read>(r: &mut R, len: usize) -> io::Result<Value> {
+    let bytes = read_bytes(r, len)?;
+    Ok(Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// Read a value, or `None` at the end of a message
+fn read_value<R: Read>(r: &mut R, first: u8) -> io::Result<Option<Value>> {
+    let value = match first {
+        EOM => return Ok(None),
+        0x10 => Value::Int(read_u32(r)? as i32 as i64),
+        0x11 => {
+            let len = read_u32(r)? as usize;
+            read_string(r, len)?
+        }
+        0x12 => {
+            let len = read_u16(r)? as usize;
+            read_string(r, len)?
+        }
+        0x13 => {
+            let len = read_u32(r)? as usize;
+            Value::Buffer(read_bytes(r, len)?)
+        }
+        0x14 => {
+            let len = read_u16(r)? as usize;
+            Value::Buffer(read_bytes(r, len)?)
+        }
+        0x15 => Value::Unused,
+        0x16 => Value::Undefined,
+        0x17 => Value::Null,
+        0x18 => Value::Bool(true),
+        0x19 => Value::Bool(false),
+        0x1a => {
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf)?;
+            Value::Number(f64::from_bits(u64::from_be_bytes(buf)))
+        }
+        0x1b => {
+            let class = read_u8(r)?;
+            let len = read_u8(r)? as usize;
+            Value::Object(class, read_bytes(r, len)?)
+        }
+        0x1c => {
+            let len = read_u8(r)? as usize;
+            Value::Pointer(read_bytes(r, len)?)
+        }
+        0x1d => {
+            let flags = read_u16(r)?;
+            let len = read_u8(r)? as usize;
+            Value::LightFunc(flags, read_bytes(r, len)?)
+        }
+        0x1e => {
+            let len = read_u8(r)? as usize;
+            Value::HeapPtr(read_bytes(r, len)?)
+        }
+        0x60..=0x7f => read_string(r, (first - 0x60) as usize)?,
+        0x80..=0xbf => Value::Int((first - 0x80) as i64),
+        0xc0..=0xff => Value::Int((((first - 0xc0) as i64) << 8) + read_u8(r)? as i64),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid dvalue: {:#x}", first),
+            ))
+        }
+    };
+    Ok(Some(value))
+}
+
+enum Message {
+    Reply(Vec<Value>),
+    Error(Vec<Value>),
+    Notify(Vec<Value>),
+}
+
+fn read_message<R: Read>(r: &mut R) -> io::Result<Message> {
+    let kind = read_u8(r)?;
+    let mut values = Vec::new();
+    loop {
+        let first = read_u8(r)?;
+        match read_value(r, first)? {
+            Some(value) => values.push(value),
+            None => break,
+        }
+    }
+    match kind {
+        REP => Ok(Message::Reply(values)),
+        ERR => Ok(Message::Error(values)),
+        NFY => Ok(Message::Notify(values)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid message: {:#x}", kind),
+        )),
+    }
+}
+
+fn read_line(r: &mut PipeReader) -> io::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        match read_u8(r)? {
+            b'\n' => return Ok(String::from_utf8_lossy(&line).into_owned()),
+            b => line.push(b),
+        }
+    }
+}
+
+/// The client end of a debugger attached to a context
+pub struct Client {
+    writer: PipeWriter,
+    replies: Receiver<Result<Vec<Value>, String>>,
+}
+
+impl Client {
+    /// Start reading messages from `pipe` on a thread of its own.
+    /// Notifications are passed to `notify`, which gets `None` once the debugger detaches
+    pub fn connect<F>(pipe: Pipe, mut notify: F) -> Client
+    where
+        F: FnMut(Option<Vec<Value>>) + Send + 'static,
+    {
+        let (mut reader, writer) = pipe.split();
+        let (tx, replies) = mpsc::channel();
+
+        thread::spawn(move || {
+            // The version identification line precedes the messages
+            if read_line(&mut reader).is_ok() {
+                while let Ok(message) = read_message(&mut reader) {
+                    let sent = match message {
+                        Message::Reply(values) => tx.send(Ok(values)),
+                        Message::Error(values) => {
+                            let message = values
+                                .get(1)
+                                .and_then(|v| v.as_str())
+                                .unwrap_or("debugger error")
+                                .to_owned();
+                            tx.send(Err(message))
+                        }
+                        Message::Notify(values) => {
+                            notify(Some(values));
+                            Ok(())
+                        }
+                    };
+                    if sent.is_err() {
+                        break;
+                    }
+                }
+            }
+            notify(None);
+        });
+
+        Client { writer, replies }
+    }
+
+    /// Send a request and wait for its reply
+    pub fn request(&mut self, command: i64, args: &[Value]) -> Result<Vec<Value>, String> {
+        let mut message = vec![REQ];
+        Value::Int(command).encode(&mut message);
+        for arg in args {
+            arg.encode(&mut message);
+        }
+        message.push(EOM);
+
+        self.writer.write_all(&message).map_err(|e| e.to_string())?;
+        match self.replies.recv() {
+            Ok(reply) => reply,
+            Err(_) => Err("debugger detached".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, read_value, Message, Value, EOM, NFY};
+
+    fn round_trip(value: Value) -> Value {
+        let mut out = Vec::new();
+        value.encode(&mut out);
+        let mut input = &out[1..];
+        let value = read_value(&mut input, out[0]).unwrap().unwrap();
+        assert!(input.is_empty());
+        value
+    }
+
+    #[test]
+    fn values() {
+        let values = vec![
+            Value::Unused,
+            Value::Undefined,
+            Value::Null,
+            Value::Bool(true),
+            Value::Bool(false),
+            Value::Int(0),
+            Value::Int(0x3f),
+            Value::Int(0x40),
+            Value::Int(0x3fff),
+            Value::Int(0x4000),
+            Value::Int(-1),
+            Value::Int(i64::from(i32::MIN)),
+            Value::Number(1.5),
+            Value::String("short".to_owned()),
+            Value::String("x".repeat(100)),
+            Value::Buffer(vec![1, 2, 3]),
+            Value::Object(2, vec![0, 1, 2, 3, 4, 5, 6, 7]),
+            Value::Pointer(vec![1; 8]),
+            Value::LightFunc(0x1234, vec![2; 8]),
+            Value::HeapPtr(vec![3; 8]),
+        ];
+        for value in values {
+            assert_eq!(round_trip(value.clone()), value);
+        }
+    }
+
+    #[test]
+    fn large_ints() {
+        let large = i64::from(i32::MAX) + 1;
+        assert_eq!(round_trip(Value::Int(large)), Value::Number(large as f64));
+        assert_eq!(round_trip(Value::Int(large)).as_int(), Some(large));
+    }
+
+    #[test]
+    fn messages() {
+        let mut out = vec![NFY];
+        Value::Int(1).encode(&mut out);
+        Value::String("status".to_owned()).encode(&mut out);
+        out.push(EOM);
+
+        match read_message(&mut &out[..]).unwrap() {
+            Message::Notify(values) => {
+                assert_eq!(values, vec![Value::Int(1), Value::String("status".to_owned())])
+            }
+            _ => panic!("expected a notification"),
+        }
+        assert!(read_message(&mut &[0x7f, EOM][..]).is_err());
+    }
+}
@@ -0,0 +1,839 @@
+//! A Debug Adapter Protocol server on stdin and stdout, so editors like VS Code can debug
+//! scripts. Requests are translated to the Duktape debug protocol of a context
+//! running the script on the main thread.
+mod client;
+
+use self::client::*;
+use duktape::debugger;
+use duktape::prelude::*;
+use duktape_modules::error::Result;
+use duktape_modules::CJSContext;
+use serde_json::{json, Value as Json};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const THREAD_ID: i64 = 1;
+/// Properties listed for an object in the variables view
+const MAX_PROPERTIES: i64 = 1000;
+
+enum Event {
+    /// A request from the editor
+    Request(Json),
+    /// The editor closed the connection
+    Closed,
+    Attached(Client),
+    Notify(Vec<Value>),
+    Detached,
+    Exited(i32),
+}
+
+struct Launch {
+    program: PathBuf,
+    cwd: Option<PathBuf>,
+}
+
+/// Writes protocol messages, shared by the session and the forwarding of script output
+#[derive(Clone)]
+struct Output {
+    inner: Arc<Mutex<(Box<dyn Write + Send>, i64)>>,
+}
+
+impl Output {
+    fn new(writer: Box<dyn Write + Send>) -> Output {
+        Output {
+            inner: Arc::new(Mutex::new((writer, 0))),
+        }
+    }
+
+    fn send(&self, mut message: Json) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.1 += 1;
+        message["seq"] = json!(inner.1);
+        let body = message.to_string();
+        let _ = write!(inner.0, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = inner.0.flush();
+    }
+
+    fn event(&self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn response(&self, request: &Json, result: std::result::Result<Json, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn print(&self, category: &str, text: &str) {
+        self.event("output", json!({ "category": category, "output": text }));
+    }
+}
+
+/// Write protocol messages to stdout and send what the script prints as output events
+#[cfg(unix)]
+fn output() -> io::Result<Output> {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    let (stdout, reader) = unsafe {
+        let stdout = libc::dup(1);
+        let mut fds = [0; 2];
+        if stdout < 0 || libc::pipe(fds.as_mut_ptr()) < 0 || libc::dup2(fds[1], 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::close(fds[1]);
+        (File::from_raw_fd(stdout), File::from_raw_fd(fds[0]))
+    };
+
+    let output = Output::new(Box::new(stdout));
+    let forward = output.clone();
+    thread::spawn(move || {
+        let mut reader = reader;
+        let mut buf = [0; 4096];
+        while let Ok(len) = reader.read(&mut buf) {
+            if len == 0 {
+                break;
+            }
+            forward.print("stdout", &String::from_utf8_lossy(&buf[..len]));
+        }
+    });
+    Ok(output)
+}
+
+#[cfg(not(unix))]
+fn output() -> io::Result<Output> {
+    Ok(Output::new(Box::new(io::stdout())))
+}
+
+/// Read requests from `input`, framed by `Content-Length` headers
+fn read_requests<R: BufRead>(mut input: R, events: Sender<Event>) {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    let _ = events.send(Event::Closed);
+                    return;
+                }
+                Ok(_) => {}
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            let mut parts = line.splitn(2, ':');
+            if parts.next().map(|n| n.trim().eq_ignore_ascii_case("content-length")) == Some(true) {
+                length = parts.next().and_then(|l| l.trim().parse::<usize>().ok());
+            }
+        }
+
+        let length = match length {
+            Some(length) => length,
+            None => continue,
+        };
+        let mut body = vec![0; length];
+        if input.read_exact(&mut body).is_err() {
+            let _ = events.send(Event::Closed);
+            return;
+        }
+        if let Ok(request) = serde_json::from_slice(&body) {
+            if events.send(Event::Request(request)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn class_name(class: u8) -> &'static str {
+    match class {
+        1 => "Object",
+        2 => "Array",
+        3 => "Function",
+        4 => "Arguments",
+        5 => "Boolean",
+        6 => "Date",
+        7 => "Error",
+        8 => "JSON",
+        9 => "Math",
+        10 => "Number",
+        11 => "RegExp",
+        12 => "String",
+        13 => "global",
+        14 => "Symbol",
+        17 => "Pointer",
+        18 => "Thread",
+        19 => "ArrayBuffer",
+        20 => "DataView",
+        21..=29 => "TypedArray",
+        _ => "Object",
+    }
+}
+
+/// Format `value` for the variables view, like a JavaScript literal
+fn display(value: &Value) -> String {
+    match value {
+        Value::Unused | Value::Undefined => "undefined".to_owned(),
+        Value::Null => "null".to_owned(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Number(n) if n.is_nan() => "NaN".to_owned(),
+        Value::Number(n) if n.is_infinite() => {
+            if *n > 0.0 { "Infinity" } else { "-Infinity" }.to_owned()
+        }
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e21 => format!("{}", *n as i64),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => json!(s).to_string(),
+        Value::Buffer(b) => format!("<Buffer {} bytes>", b.len()),
+        Value::Object(3, _) | Value::LightFunc(..) => "[Function]".to_owned(),
+        Value::Object(class, _) => class_name(*class).to_owned(),
+        Value::Pointer(_) => "[Pointer]".to_owned(),
+        Value::HeapPtr(_) => "[Object]".to_owned(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Unused | Value::Undefined => "undefined",
+        Value::Null => "object",
+        Value::Bool(_) => "boolean",
+        Value::Int(_) | Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Buffer(_) => "buffer",
+        Value::Object(3, _) | Value::LightFunc(..) => "function",
+        Value::Object(..) | Value::HeapPtr(_) => "object",
+        Value::Pointer(_) => "pointer",
+    }
+}
+
+/// Breakpoints must name files like the module loader does, which uses canonical paths
+fn canonical(path: &str) -> String {
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|p| p.to_str().map(|p| p.to_owned()))
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// What a `variablesReference` refers to. References are valid while the script is paused
+enum Handle {
+    Locals(i64),
+    Global,
+    Object(Value),
+}
+
+struct Session {
+    output: Output,
+    launch: Option<Sender<Launch>>,
+    client: Option<Client>,
+    /// Lines by file
+    breakpoints: BTreeMap<String, Vec<i64>>,
+    stop_on_entry: bool,
+    configured: bool,
+    /// Whether the script has started, paused on entry until the editor is configured
+    started: bool,
+    paused: bool,
+    /// The reason reported, when the script pauses next
+    reason: Option<&'static str>,
+    exception: Option<String>,
+    exited: bool,
+    handles: Vec<Handle>,
+}
+
+impl Session {
+    fn new(output: Output, launch: Sender<Launch>) -> Session {
+        Session {
+            output,
+            launch: Some(launch),
+            client: None,
+            breakpoints: BTreeMap::new(),
+            stop_on_entry: false,
+            configured: false,
+            started: false,
+            paused: false,
+            reason: None,
+            exception: None,
+            exited: false,
+            handles: Vec::new(),
+        }
+    }
+
+    fn run(mut self, events: Receiver<Event>) {
+        while let Ok(event) = events.recv() {
+            match event {
+                Event::Request(request) => {
+                    let command = request["command"].as_str().unwrap_or("").to_owned();
+                    let result = self.handle(&command, &request["arguments"]);
+                    self.output.response(&request, result);
+                    match command.as_str() {
+                        "initialize" => self.output.event("initialized", json!({})),
+                        "disconnect" | "terminate" => process::exit(0),
+                        "configurationDone" => self.start(),
+                        _ => {}
+                    }
+                }
+                Event::Closed => process::exit(0),
+                Event::Attached(client) => self.client = Some(client),
+                Event::Notify(values) => self.notify(values),
+                Event::Detached => self.client = None,
+                Event::Exited(code) => {
+                    self.exited = true;
+                    self.output.event("exited", json!({ "exitCode": code }));
+                    self.output.event("terminated", json!({}));
+                }
+            }
+        }
+    }
+
+    fn request(&mut self, command: i64, args: &[Value]) -> std::result::Result<Vec<Value>, String> {
+        match self.client {
+            Some(ref mut client) => client.request(command, args),
+            None => Err("the script is not running".to_owned()),
+        }
+    }
+
+    fn handle(&mut self, command: &str, args: &Json) -> std::result::Result<Json, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => {
+                let program = args["program"].as_str().ok_or("missing program")?;
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                let launch = Launch {
+                    program: PathBuf::from(program),
+                    cwd: args["cwd"].as_str().map(PathBuf::from),
+                };
+                match self.launch.take() {
+                    Some(sender) => sender.send(launch).map_err(|e| e.to_string())?,
+                    None => return Err("already launched".to_owned()),
+                }
+                Ok(json!({}))
+            }
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => {
+                let level = -(args["frameId"].as_i64().unwrap_or(0) + 1);
+                let locals = self.handle_for(Handle::Locals(level));
+                let global = self.handle_for(Handle::Global);
+                Ok(json!({ "scopes": [
+                    { "name": "Locals", "variablesReference": locals, "expensive": false },
+                    { "name": "Global", "variablesReference": global, "expensive": true },
+                ]}))
+            }
+            "variables" => self.variables(args["variablesReference"].as_i64().unwrap_or(0)),
+            "evaluate" => self.evaluate(args),
+            "continue" => self
+                .resume(CMD_RESUME, None)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.resume(CMD_STEP_OVER, Some("step")),
+            "stepIn" => self.resume(CMD_STEP_INTO, Some("step")),
+            "stepOut" => self.resume(CMD_STEP_OUT, Some("step")),
+            "pause" => {
+                self.reason = Some("pause");
+                self.request(CMD_PAUSE, &[]).map(|_| json!({}))
+            }
+            "disconnect" | "terminate" => Ok(json!({})),
+            _ => Err(format!("unsupported request: {}", command)),
+        }
+    }
+
+    /// Continue a paused script with `command`
+    fn resume(&mut self, command: i64, reason: Option<&'static str>) -> std::result::Result<Json, String> {
+        self.paused = false;
+        self.reason = reason;
+        self.handles.clear();
+        self.request(command, &[])?;
+        Ok(json!({}))
+    }
+
+    /// Run the script, once it is paused on entry and the editor has set its breakpoints
+    fn start(&mut self) {
+        if !self.started || !self.configured {
+            return;
+        }
+        if self.stop_on_entry {
+            self.stopped("entry");
+        } else if let Err(e) = self.resume(CMD_RESUME, None) {
+            self.output.print("stderr", &format!("{}\n", e));
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) {
+        self.paused = true;
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = self.exception.take() {
+            body["description"] = json!("Paused on exception");
+            body["text"] = json!(text);
+        }
+        self.output.event("stopped", body);
+    }
+
+    fn notify(&mut self, values: Vec<Value>) {
+        match values.get(0).and_then(|v| v.as_int()) {
+            Some(NFY_STATUS) => {
+                let paused = values.get(1).and_then(|v| v.as_int()) == Some(1);
+                if !paused {
+                    self.paused = false;
+                } else if !self.started {
+                    // Attaching pauses the script before its first statement
+                    self.started = true;
+                    self.sync_breakpoints();
+                    self.start();
+                } else if !self.paused {
+                    let reason = self.reason.take().unwrap_or("breakpoint");
+                    self.stopped(reason);
+                }
+            }
+            Some(NFY_THROW) => {
+                if values.get(1).and_then(|v| v.as_int()) == Some(1) {
+                    let message = values.get(2).map(display_message).unwrap_or_default();
+                    self.reason = Some("exception");
+                    self.exception = Some(message);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Replace the breakpoints of the context with the ones set by the editor.
+    /// Returns the breakpoints, which could not be added
+    fn sync_breakpoints(&mut self) -> Vec<(String, i64)> {
+        let mut failed = Vec::new();
+        let existing = match self.request(CMD_LIST_BREAK, &[]) {
+            Ok(existing) => existing.len() / 2,
+            Err(_) => return failed,
+        };
+        // Indices of later breakpoints shift on delete
+        for index in (0..existing).rev() {
+            let _ = self.request(CMD_DEL_BREAK, &[Value::Int(index as i64)]);
+        }
+
+        let breakpoints: Vec<(String, i64)> = self
+            .breakpoints
+            .iter()
+            .flat_map(|(file, lines)| lines.iter().map(move |line| (file.clone(), *line)))
+            .collect();
+        for (file, line) in breakpoints {
+            let args = [Value::String(file.clone()), Value::Int(line)];
+            if self.request(CMD_ADD_BREAK, &args).is_err() {
+                failed.push((file, line));
+            }
+        }
+        failed
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> std::result::Result<Json, String> {
+        let path = args["source"]["path"].as_str().ok_or("missing source path")?;
+        let file = canonical(path);
+        let lines: Vec<i64> = match args["breakpoints"].as_array() {
+            Some(breakpoints) => breakpoints.iter().filter_map(|b| b["line"].as_i64()).collect(),
+            None => Vec::new(),
+        };
+        self.breakpoints.insert(file.clone(), lines.clone());
+
+        let failed = if self.started && self.client.is_some() {
+            self.sync_breakpoints()
+        } else {
+            Vec::new()
+        };
+        let breakpoints: Vec<Json> = lines
+            .iter()
+            .map(|line| {
+                let verified = !failed.iter().any(|(f, l)| *f == file && l == line);
+                json!({ "verified": verified, "line": line })
+            })
+            .collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&mut self) -> std::result::Result<Json, String> {
+        let values = self.request(CMD_GET_CALL_STACK, &[])?;
+        // Entries of file name, function name, line and pc, innermost first
+        let frames: Vec<Json> = values
+            .chunks(4)
+            .enumerate()
+            .map(|(id, entry)| {
+                let file = entry.get(0).and_then(|v| v.as_str()).unwrap_or("");
+                let name = match entry.get(1).and_then(|v| v.as_str()) {
+                    Some(name) if !name.is_empty() => name,
+                    _ => "(anonymous)",
+                };
+                let line = entry.get(2).and_then(|v| v.as_int()).unwrap_or(0);
+                let mut frame = json!({ "id": id, "name": name, "line": line, "column": 1 });
+                if Path::new(file).is_absolute() {
+                    let base = Path::new(file).file_name().and_then(|n| n.to_str()).unwrap_or(file);
+                    frame["source"] = json!({ "name": base, "path": file });
+                } else {
+                    frame["presentationHint"] = json!("subtle");
+                }
+                frame
+            })
+            .collect();
+        Ok(json!({ "totalFrames": frames.len(), "stackFrames": frames }))
+    }
+
+    fn handle_for(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    /// A variable in the variables view, expandable if `value` is an object
+    fn variable(&mut self, name: String, value: Value) -> Json {
+        let reference = match value {
+            Value::Object(..) | Value::HeapPtr(_) => self.handle_for(Handle::Object(value.clone())),
+            _ => 0,
+        };
+        json!({
+            "name": name,
+            "value": display(&value),
+            "type": type_name(&value),
+            "variablesReference": reference,
+        })
+    }
+
+    fn variables(&mut self, reference: i64) -> std::result::Result<Json, String> {
+        let handle = (reference as usize).checked_sub(1).and_then(|i| self.handles.get(i));
+        let properties = match handle {
+            Some(Handle::Locals(level)) => {
+                let level = *level;
+                let values = self.request(CMD_GET_LOCALS, &[Value::Int(level)])?;
+                values
+                    .chunks(2)
+                    .filter(|pair| pair.len() == 2)
+                    .map(|pair| (display_message(&pair[0]), pair[1].clone()))
+                    .collect()
+            }
+            Some(Handle::Global) => {
+                let global = self.eval(None, "this")?;
+                self.properties(global)?
+            }
+            Some(Handle::Object(object)) => {
+                let object = object.clone();
+                self.properties(object)?
+            }
+            None => return Err("invalid variables reference".to_owned()),
+        };
+
+        let variables: Vec<Json> = properties
+            .into_iter()
+            .map(|(name, value)| self.variable(name, value))
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// The own properties of `object`, without hidden and symbol keys
+    fn properties(&mut self, object: Value) -> std::result::Result<Vec<(String, Value)>, String> {
+        let pointer = match object {
+            Value::Object(_, pointer) | Value::HeapPtr(pointer) => pointer,
+            _ => return Ok(Vec::new()),
+        };
+        let args = [Value::HeapPtr(pointer), Value::Int(0), Value::Int(MAX_PROPERTIES)];
+        let mut values = self.request(CMD_GET_OBJ_PROP_DESC_RANGE, &args)?.into_iter();
+
+        let mut properties = Vec::new();
+        while let (Some(flags), Some(key)) = (values.next(), values.next()) {
+            let flags = flags.as_int().unwrap_or(0);
+            let value = if flags & PROP_ACCESSOR != 0 {
+                values.next();
+                values.next();
+                Value::String("[Getter/Setter]".to_owned())
+            } else {
+                values.next().unwrap_or(Value::Unused)
+            };
+            let visible = flags & (PROP_HIDDEN | PROP_SYMBOL) == 0;
+            match (key, value) {
+                (_, Value::Unused) | (Value::Null, _) => {}
+                (key, value) if visible => properties.push((display_message(&key), value)),
+                _ => {}
+            }
+        }
+        Ok(properties)
+    }
+
+    /// Evaluate `expression` in the frame at `level`, or globally
+    fn eval(&mut self, level: Option<i64>, expression: &str) -> std::result::Result<Value, String> {
+        let level = level.map(Value::Int).unwrap_or(Value::Null);
+        let reply = self.request(CMD_EVAL, &[level, Value::String(expression.to_owned())])?;
+        match (reply.get(0).and_then(|v| v.as_int()), reply.get(1)) {
+            (Some(0), Some(value)) => Ok(value.clone()),
+            (_, Some(error)) => Err(display_message(error)),
+            _ => Err("invalid eval reply".to_owned()),
+        }
+    }
+
+    fn evaluate(&mut self, args: &Json) -> std::result::Result<Json, String> {
+        let expression = args["expression"].as_str().ok_or("missing expression")?;
+        let level = args["frameId"].as_i64().map(|id| -(id + 1));
+        let value = self.eval(level, expression)?;
+        let variable = self.variable(String::new(), value);
+        Ok(json!({
+            "result": variable["value"],
+            "type": variable["type"],
+            "variablesReference": variable["variablesReference"],
+        }))
+    }
+}
+
+/// Strings as they are, other values formatted by `display`
+fn display_message(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => display(value),
+    }
+}
+
+/// Serve the Debug Adapter Protocol until the editor disconnects.
+/// The script named by the launch request runs in a context from `create`
+pub fn run<F: Fn(&Path) -> Result<Context>>(create: F) -> Result<()> {
+    let output = output()?;
+    let (events, received) = mpsc::channel();
+    let (launch, launched) = mpsc::channel();
+
+    let requests = events.clone();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        read_requests(stdin.lock(), requests)
+    });
+    let session = Session::new(output.clone(), launch);
+    let session = thread::spawn(move || session.run(received));
+
+    let launch: Launch = match launched.recv() {
+        Ok(launch) => launch,
+        Err(_) => {
+            let _ = session.join();
+            return Ok(());
+        }
+    };
+
+    let code = match launch_script(&create, launch, &events) {
+        Ok(_) => 0,
+        Err(e) => {
+            output.print("stderr", &format!("{}\n", e));
+            1
+        }
+    };
+    io::stdout().flush()?;
+    let _ = events.send(Event::Exited(code));
+    let _ = session.join();
+    Ok(())
+}
+
+fn launch_script<F: Fn(&Path) -> Result<Context>>(create: &F, launch: Launch, events: &Sender<Event>) -> Result<()> {
+    if let Some(cwd) = launch.cwd {
+        env::set_current_dir(cwd)?;
+    }
+    let program = fs::canonicalize(&launch.program)?;
+    let ctx = create(&program)?;
+
+    let (engine, pipe) = debugger::pipe();
+    let notifications = events.clone();
+    let client = Client::connect(pipe, move |values| {
+        let _ = notifications.send(match values {
+            Some(values) => Event::Notify(values),
+            None => Event::Detached,
+        });
+    });
+    let _ = events.send(Event::Attached(client));
+    ctx.attach_debugger(engine);
+
+    let content = fs::read(&program)?;
+    let result = ctx.eval_main_script(&program, content).map(|_| ());
+    ctx.detach_debugger();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client::{Client, Value};
+    use super::{display, read_requests, Event, Output, Session};
+    use duktape::debugger;
+    use duktape::prelude::*;
+    use duktape_modules::CJSContext;
+    use serde_json::{json, Value as Json};
+    use std::env;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Collects what the session writes
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        /// The messages written so far
+        fn messages(&self) -> Vec<Json> {
+            let input = self.0.lock().unwrap().clone();
+            let (events, received) = mpsc::channel();
+            read_requests(&input[..], events);
+            received
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::Request(message) => Some(message),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn wait_for(&self, predicate: impl Fn(&Json) -> bool) -> Json {
+            let start = Instant::now();
+            loop {
+                if let Some(message) = self.messages().into_iter().find(|m| predicate(m)) {
+                    return message;
+                }
+                assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        fn response(&self, command: &str) -> Json {
+            self.wait_for(|m| m["type"] == "response" && m["command"] == command)
+        }
+    }
+
+    fn request(command: &str, arguments: Json) -> Event {
+        Event::Request(json!({ "type": "request", "seq": 1, "command": command, "arguments": arguments }))
+    }
+
+    #[test]
+    fn framing() {
+        let input = b"Content-Length: 13\r\n\r\n{\"seq\":1}    \
+                      X-Other: 1\r\ncontent-length: 9\r\n\r\n{\"seq\":2}\
+                      \r\n\r\nContent-Length: 20\r\n\r\n{\"seq\":";
+        let (events, received) = mpsc::channel();
+        read_requests(&input[..], events);
+
+        let events: Vec<Event> = received.into_iter().collect();
+        assert_eq!(events.len(), 3);
+        match (&events[0], &events[1], &events[2]) {
+            (Event::Request(first), Event::Request(second), Event::Closed) => {
+                assert_eq!(first["seq"], 1);
+                assert_eq!(second["seq"], 2);
+            }
+            _ => panic!("expected two requests and the end of input"),
+        }
+    }
+
+    #[test]
+    fn display_values() {
+        assert_eq!(display(&Value::Undefined), "undefined");
+        assert_eq!(display(&Value::Number(f64::NAN)), "NaN");
+        assert_eq!(display(&Value::Number(-f64::INFINITY)), "-Infinity");
+        assert_eq!(display(&Value::Number(3.0)), "3");
+        assert_eq!(display(&Value::Number(0.5)), "0.5");
+        assert_eq!(display(&Value::String("a\"b".to_owned())), "\"a\\\"b\"");
+        assert_eq!(display(&Value::Object(3, vec![0; 8])), "[Function]");
+        assert_eq!(display(&Value::Object(2, vec![0; 8])), "Array");
+    }
+
+    #[test]
+    fn session() {
+        let dir = env::temp_dir().join("duk_dap_session");
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("main.js");
+        fs::write(&program, "function add(x) {\n    var y = x + 1;\n    return y;\n}\nadd(41);\n").unwrap();
+        let program = fs::canonicalize(program).unwrap();
+
+        let buffer = Buffer::default();
+        let (events, received) = mpsc::channel();
+        let (launch, _launched) = mpsc::channel();
+        let session = Session::new(Output::new(Box::new(buffer.clone())), launch);
+        let session = thread::spawn(move || session.run(received));
+
+        events.send(request("initialize", json!({}))).unwrap();
+        let source = json!({ "source": { "path": program }, "breakpoints": [{ "line": 2 }] });
+        events.send(request("setBreakpoints", source)).unwrap();
+        events.send(request("configurationDone", json!({}))).unwrap();
+
+        let (engine, pipe) = debugger::pipe();
+        let notifications = events.clone();
+        let client = Client::connect(pipe, move |values| {
+            let _ = notifications.send(match values {
+                Some(values) => Event::Notify(values),
+                None => Event::Detached,
+            });
+        });
+        events.send(Event::Attached(client)).unwrap();
+
+        let driver = {
+            let (events, buffer) = (events.clone(), buffer.clone());
+            thread::spawn(move || {
+                buffer.wait_for(|m| m["event"] == "stopped" && m["body"]["reason"] == "breakpoint");
+                events.send(request("stackTrace", json!({}))).unwrap();
+                events.send(request("scopes", json!({ "frameId": 0 }))).unwrap();
+                events.send(request("variables", json!({ "variablesReference": 1 }))).unwrap();
+                events.send(request("variables", json!({ "variablesReference": -1 }))).unwrap();
+                events.send(request("evaluate", json!({ "expression": "x + 1", "frameId": 0 }))).unwrap();
+                buffer.response("evaluate");
+                events.send(request("continue", json!({}))).unwrap();
+            })
+        };
+
+        let ctx = Context::new().unwrap();
+        duktape_modules::register(&ctx, duktape_modules::Builder::new()).unwrap();
+        ctx.attach_debugger(engine);
+        let content = fs::read(&program).unwrap();
+        ctx.eval_main_script(&program, content).unwrap();
+        ctx.detach_debugger();
+
+        driver.join().unwrap();
+        events.send(Event::Exited(0)).unwrap();
+        drop(events);
+        session.join().unwrap();
+
+        let frame = &buffer.response("stackTrace")["body"]["stackFrames"][0];
+        assert_eq!(frame["name"], "add");
+        assert_eq!(frame["line"], 2);
+        assert_eq!(frame["source"]["path"], json!(program));
+
+        let variables = buffer.wait_for(|m| m["command"] == "variables" && m["success"] == true);
+        let x = variables["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|v| v["name"] == "x")
+            .cloned();
+        assert_eq!(x.map(|x| x["value"].clone()), Some(json!("41")));
+        let invalid = buffer.wait_for(|m| m["command"] == "variables" && m["success"] == false);
+        assert_eq!(invalid["message"], "invalid variables reference");
+
+        assert_eq!(buffer.response("evaluate")["body"]["result"], "42");
+        buffer.wait_for(|m| m["event"] == "exited" && m["body"]["exitCode"] == 0);
+    }
+}
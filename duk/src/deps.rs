@@ -0,0 +1,71 @@
+use duktape::prelude::*;
+use duktape_modules::bundle;
+use duktape_modules::error::Result;
+use duktape_modules::ModuleRecord;
+use std::collections::HashSet;
+
+fn describe(record: &ModuleRecord) -> String {
+    if record.loader == "builtin" {
+        format!("{} (builtin)", record.id)
+    } else {
+        format!("{} ({}, {} bytes)", record.id, record.loader, record.size)
+    }
+}
+
+fn print_tree<'a>(
+    graph: &'a [ModuleRecord],
+    record: &'a ModuleRecord,
+    prefix: &str,
+    ancestors: &mut Vec<&'a str>,
+    printed: &mut HashSet<&'a str>,
+) {
+    let count = record.dependencies.len();
+    for (i, id) in record.dependencies.iter().enumerate() {
+        let last = i + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+
+        let dependency = match graph.iter().find(|m| &m.id == id) {
+            Some(dependency) => dependency,
+            None => continue,
+        };
+
+        if ancestors.contains(&id.as_str()) {
+            println!("{}{}{} (circular)", prefix, branch, id);
+            continue;
+        }
+
+        if !printed.insert(id.as_str()) {
+            println!("{}{}{} (deduped)", prefix, branch, id);
+            continue;
+        }
+
+        println!("{}{}{}", prefix, branch, describe(dependency));
+
+        let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        ancestors.push(&dependency.id);
+        print_tree(graph, dependency, &prefix, ancestors, printed);
+        ancestors.pop();
+    }
+}
+
+/// Print the tree of modules required by the script `path`.
+///
+/// Requires are found in the source like the bundler does, so the modules are
+/// not run, and requires with computed ids are left out
+pub fn run(ctx: &Context, path: &str) -> Result<()> {
+    let graph = bundle::graph(ctx, path)?;
+    let root = match graph.first() {
+        Some(root) => root,
+        None => return Ok(()),
+    };
+
+    println!("{}", describe(root));
+    let mut printed = HashSet::new();
+    printed.insert(root.id.as_str());
+    print_tree(&graph, root, "", &mut vec![root.id.as_str()], &mut printed);
+
+    let size: usize = graph.iter().map(|m| m.size).sum();
+    println!("\n{} modules, {} bytes", graph.len(), size);
+
+    Ok(())
+}
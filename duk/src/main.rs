@@ -4,49 +4,258 @@ extern crate duktape_es2015;
 extern crate duktape_modules;
 extern crate duktape_stdlib;
 extern crate env_logger;
+extern crate glob;
+#[cfg(unix)]
+extern crate libc;
 extern crate log;
 extern crate rustyline;
+extern crate serde_json;
 #[macro_use]
 extern crate clap;
+mod bundle;
+mod coverage;
+mod dap;
+mod deps;
+mod permissions;
+mod pretty;
 mod repl;
+mod standalone;
+mod test;
 
+use clap::Arg;
+use duktape::debugger;
 use duktape::prelude::*;
+use duktape::profiler::Profile;
+use duktape_modules::resolvers::HttpResolver;
 use duktape_modules::CJSContext;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-fn main() -> duktape_modules::error::Result<()> {
-    env_logger::init();
+fn cache_dir(matches: &clap::ArgMatches) -> PathBuf {
+    if let Some(dir) = matches.value_of("cache_dir") {
+        return PathBuf::from(dir);
+    }
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".cache").join("duk"),
+        None => env::temp_dir().join("duk"),
+    }
+}
 
-    let matches = clap_app!(duk =>
+fn app<'a, 'b>() -> clap::App<'a, 'b> {
+    let app = clap_app!(duk =>
         (@arg execute: -e --execute "")
-        (@arg es2015: -z --es2015 "")
+        (@arg es2015: -z --es2015 +global "")
+        (@arg import_map: --("import-map") +takes_value +global "Import map aliasing module ids")
+        (@arg cache_dir: --("cache-dir") +takes_value +global "Cache of remote modules, defaults to ~/.cache/duk")
+        (@arg lock: --lock +takes_value +global "Lockfile with the hashes of remote modules")
+        (@arg offline: --offline +global "Only load remote modules from the cache")
+        (@arg prof: --prof +takes_value "Profile the script, writing a Chrome .cpuprofile for .json and .cpuprofile files, otherwise folded stacks for flamegraphs")
+        (@arg dap: --dap "Serve the Debug Adapter Protocol on stdin and stdout, for editors like VS Code")
         (@arg input: "Input file")
-    )
-    .get_matches();
+        (@subcommand deps =>
+            (about: "Print the tree of modules a script requires, without running it. Requires with computed ids are left out")
+            (@arg input: +required "Input file")
+        )
+        (@subcommand bundle =>
+            (about: "Bundle a script and the modules it requires into a single file")
+            (@arg input: +required "Entry file")
+            (@arg output: -o --output +takes_value +required "Output file")
+            (@arg bytecode: -b --bytecode "Output Duktape bytecode, which duk runs with --bytecode")
+        )
+        (@subcommand compile =>
+            (about: "Compile a script and the modules it requires into a standalone executable")
+            (@arg input: +required "Entry file")
+            (@arg output: -o --output +takes_value +required "Output executable")
+            (@arg source: -s --source "Embed the script instead of bytecode")
+        )
+        (@subcommand test =>
+            (about: "Run tests, each file in a context of its own")
+            (@arg paths: ... "Test files, directories or glob patterns, defaults to the working directory")
+            (@arg filter: -t --filter +takes_value "Only run tests, whose name contains the filter")
+            (@arg reporter: -r --reporter +takes_value possible_value[spec tap junit] "Output format, defaults to spec")
+            (@arg coverage: --coverage "Record the lines run, printing a summary and writing an lcov report")
+            (@arg lcov: --lcov +takes_value "Path of the lcov report, defaults to coverage/lcov.info")
+        )
+    );
+
+    let app = app.arg(
+        Arg::with_name("inspect")
+            .long("inspect")
+            .help("Wait for a debug client on 127.0.0.1 before running the script or the REPL, port defaults to 9091")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true),
+    );
+
+    let app = app.arg(
+        Arg::with_name("bytecode")
+            .long("bytecode")
+            .help("Run the input file as bytecode written by `duk bundle --bytecode`. Bytecode is not validated, only run bytecode you trust")
+            .requires("input")
+            .conflicts_with("execute"),
+    );
 
-    let es6 = matches.is_present("es2015");
+    permissions::args(app)
+}
 
+/// Wait for a debug client, when duk is run with `--inspect`
+fn inspect(ctx: &Context, matches: &clap::ArgMatches) -> duktape_modules::error::Result<()> {
+    if !matches.is_present("inspect") {
+        return Ok(());
+    }
+    let port = match matches.value_of("inspect") {
+        Some(port) => port
+            .parse()
+            .map_err(|_| format!("invalid debugger port: {}", port))?,
+        None => debugger::DEFAULT_PORT,
+    };
+    eprintln!("waiting for debugger on 127.0.0.1:{}", port);
+    let stream = debugger::listen(port)?;
+    ctx.attach_debugger(stream);
+    Ok(())
+}
+
+/// Create a context with the modules and permissions given by `matches`.
+/// Modules in the working directory and the project of the `entry` script may be required
+/// without a read grant
+fn create_context(matches: &clap::ArgMatches, entry: Option<&Path>) -> duktape_modules::error::Result<Context> {
     let ctx = Context::new().unwrap();
 
     let mut require = duktape_modules::Builder::new();
 
-    duktape_stdlib::register(&ctx, &mut require, duktape_stdlib::Modules::all());
+    let permissions = permissions::from_matches(&matches);
+    let loader = permissions::loader_policy(&matches, &permissions, entry);
+    duktape_stdlib::register(&ctx, &mut require, duktape_stdlib::Modules::all(), permissions);
+    require.fs_policy(loader);
+
+    if let Some(path) = matches.value_of("import_map") {
+        require.import_map(path)?;
+    }
 
-    if es6 {
+    let mut remote = HttpResolver::new(Box::new(duktape_stdlib::HttpFetcher::new()), cache_dir(&matches))
+        .offline(matches.is_present("offline"));
+    if let Some(lock) = matches.value_of("lock") {
+        remote = remote.lockfile(lock);
+    }
+    require.resolver("https", Box::new(remote));
+
+    if matches.is_present("es2015") {
         duktape_es2015::register(&ctx, &mut require);
     }
 
     duktape_modules::register(&ctx, require)?;
     duktape_stdlib::init_runtime(&ctx);
 
-    if let Some(script) = matches.value_of("input") {
-        if matches.is_present("execute") {
-            ctx.eval_main_script("", script)
+    Ok(ctx)
+}
+
+fn run_script(ctx: &Context, matches: &clap::ArgMatches, script: &str) -> duktape_modules::error::Result<()> {
+    if matches.is_present("execute") {
+        ctx.eval_main_script("[eval].js", script)?;
+    } else {
+        let content = fs::read(script)?;
+        if matches.is_present("bytecode") {
+            bundle::eval_bytecode(&ctx, &content)?;
         } else {
-            ctx.eval_main(script)
+            ctx.eval_main_script(script, content)?;
         }
+    }
+    Ok(())
+}
+
+/// Write `profile` in the format given by the extension of `path`
+fn write_profile(profile: &Profile, path: &str) -> duktape_modules::error::Result<()> {
+    let output = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("json") | Some("cpuprofile") => profile.chrome(),
+        _ => profile.folded(),
+    };
+    fs::write(path, output)?;
+    Ok(())
+}
+
+fn main() -> duktape_modules::error::Result<()> {
+    env_logger::init();
+
+    // Executables built with `duk compile` runs the embedded script
+    if let Some(payload) = standalone::payload()? {
+        let matches = app().get_matches_from(payload.args.iter().map(|a| a.as_str()));
+        let ctx = create_context(&matches, None)?;
+        return standalone::run(&ctx, &payload);
+    }
+
+    let app_matches = app().get_matches();
+
+    // Global arguments are propagated to the matches of the subcommand
+    let (command, matches) = match app_matches.subcommand() {
+        (name, Some(sub)) => (name, sub),
+        _ => ("", &app_matches),
+    };
+
+    if matches.is_present("dap") {
+        return dap::run(|program| create_context(matches, Some(program)));
+    }
+
+    if command == "test" {
+        return test::run(matches, |path| create_context(matches, Some(path)));
+    }
+
+    let entry = match matches.value_of("input") {
+        Some(input) if !matches.is_present("execute") => Some(Path::new(input)),
+        _ => None,
+    };
+    let ctx = create_context(matches, entry)?;
+
+    match command {
+        "deps" => return deps::run(&ctx, matches.value_of("input").unwrap()),
+        "bundle" => return bundle::run(&ctx, matches),
+        "compile" => return standalone::compile(&ctx, matches),
+        _ => {}
+    }
+
+    inspect(&ctx, matches)?;
+
+    if let Some(script) = matches.value_of("input") {
+        if matches.is_present("prof") {
+            ctx.start_profiling();
+        }
+        let result = run_script(&ctx, matches, script);
+        if let Some(path) = matches.value_of("prof") {
+            write_profile(&ctx.stop_profiling(), path)?;
+        }
+        result?;
     } else {
-        return repl::run(&ctx, es6);
-    }?;
+        return repl::run(ctx, || create_context(matches, None), matches.is_present("es2015"));
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::app;
+
+    #[test]
+    fn bytecode_needs_a_file() {
+        assert!(app().get_matches_from_safe(vec!["duk", "--bytecode", "out.bin"]).is_ok());
+        assert!(app().get_matches_from_safe(vec!["duk", "--bytecode"]).is_err());
+        assert!(app().get_matches_from_safe(vec!["duk", "--bytecode", "-e", "1"]).is_err());
+    }
+
+    #[test]
+    fn inspect_only_runs_scripts() {
+        assert!(app().get_matches_from_safe(vec!["duk", "--inspect=9000", "main.js"]).is_ok());
+        let commands: &[&[&str]] = &[
+            &["test"],
+            &["deps", "main.js"],
+            &["bundle", "main.js", "-o", "out.js"],
+            &["compile", "main.js", "-o", "out"],
+        ];
+        for command in commands {
+            let args = [&["duk"], *command].concat();
+            assert!(app().get_matches_from_safe(&args).is_ok(), "{:?}", command);
+            let args = [&args[..], &["--inspect"]].concat();
+            assert!(app().get_matches_from_safe(&args).is_err(), "{:?}", command);
+        }
+    }
+}
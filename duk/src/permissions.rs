@@ -0,0 +1,234 @@
+use clap::{App, Arg, ArgMatches};
+use duktape_stdlib::{FsPolicy, Permissions};
+use std::path::{Path, PathBuf};
+
+fn grant<'a, 'b>(name: &'a str, help: &'a str, values: bool) -> Arg<'a, 'b> {
+    let arg = Arg::with_name(name).long(name).help(help).global(true);
+    if values {
+        arg.takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .use_delimiter(true)
+    } else {
+        arg
+    }
+}
+
+pub fn args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("allow-all")
+            .short("A")
+            .long("allow-all")
+            .help("Allow everything")
+            .global(true),
+    )
+    .arg(grant(
+        "allow-read",
+        "Allow file system reads, optionally limited to the given paths",
+        true,
+    ))
+    .arg(grant(
+        "allow-write",
+        "Allow file system writes, optionally limited to the given paths",
+        true,
+    ))
+    .arg(grant(
+        "allow-import",
+        "Allow loading modules from anywhere, or from the given paths besides the working directory and the project of the script",
+        true,
+    ))
+    .arg(grant(
+        "allow-net",
+        "Allow network access, optionally limited to the given hosts",
+        true,
+    ))
+    .arg(grant("allow-run", "Allow running sub processes", false))
+    .arg(grant("allow-env", "Allow reading environment variables", false))
+}
+
+static GRANTS: &[&str] = &[
+    "allow-all",
+    "allow-read",
+    "allow-write",
+    "allow-import",
+    "allow-net",
+    "allow-run",
+    "allow-env",
+];
+
+/// The command line arguments granting the permissions of `matches`
+pub fn to_args(matches: &ArgMatches) -> Vec<String> {
+    let mut args = Vec::new();
+    for name in GRANTS.iter().filter(|name| matches.is_present(name)) {
+        let values = values(matches, name);
+        if values.is_empty() {
+            args.push(format!("--{}", name));
+        } else {
+            args.push(format!("--{}={}", name, values.join(",")));
+        }
+    }
+    args
+}
+
+/// The values of a grant, which are empty when given without values
+fn values<'a>(matches: &'a ArgMatches, name: &str) -> Vec<&'a str> {
+    matches
+        .values_of(name)
+        .map(|values| values.collect())
+        .unwrap_or_default()
+}
+
+/// Build the permission set granted on the command line.
+/// Nothing is granted unless asked for
+pub fn from_matches(matches: &ArgMatches) -> Permissions {
+    if matches.is_present("allow-all") {
+        return Permissions::new();
+    }
+
+    let mut permissions = Permissions::none();
+
+    let mut fs = FsPolicy::restricted();
+    if matches.is_present("allow-read") {
+        let paths = values(matches, "allow-read");
+        if paths.is_empty() {
+            fs.allow_read_all();
+        }
+        for path in paths {
+            fs.allow_read(path);
+        }
+    }
+    if matches.is_present("allow-write") {
+        let paths = values(matches, "allow-write");
+        if paths.is_empty() {
+            fs.allow_write_all();
+        }
+        for path in paths {
+            fs.allow_write(path);
+        }
+    } else {
+        fs.read_only(true);
+    }
+    permissions.fs(fs);
+
+    if matches.is_present("allow-net") {
+        let hosts = values(matches, "allow-net");
+        if hosts.is_empty() {
+            permissions.allow_net_all();
+        }
+        for host in hosts {
+            permissions.allow_net(host);
+        }
+    }
+
+    permissions
+        .allow_run(matches.is_present("allow-run"))
+        .allow_env(matches.is_present("allow-env"));
+
+    permissions
+}
+
+/// The directory of the nearest package.json above `dir`, or `dir` itself
+fn project_root(dir: &Path) -> PathBuf {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors()
+        .find(|d| d.join("package.json").is_file())
+        .unwrap_or(&dir)
+        .to_path_buf()
+}
+
+/// The policy of the module loader, which reads modules from the working directory, the directory
+/// of the `entry` script and its project, and the paths given by `--allow-import` or `--allow-read`.
+/// The read grant of the fs module is left as it is
+pub fn loader_policy(matches: &ArgMatches, permissions: &Permissions, entry: Option<&Path>) -> FsPolicy {
+    let mut policy = FsPolicy::restricted();
+    policy.read_only(true);
+
+    let imports = values(matches, "allow-import");
+    if !permissions.fs_policy().is_restricted() || (matches.is_present("allow-import") && imports.is_empty()) {
+        policy.allow_read_all();
+        return policy;
+    }
+
+    policy.allow_read(".");
+    if let Some(entry) = entry {
+        let dir = match entry.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        policy.allow_read(dir).allow_read(project_root(dir));
+    }
+    for path in imports {
+        policy.allow_read(path);
+    }
+    for root in permissions.fs_policy().roots() {
+        policy.allow_read(root);
+    }
+    policy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{args, from_matches, loader_policy};
+    use clap::{App, ArgMatches};
+    use std::env;
+    use std::fs;
+
+    fn app_args(argv: &[&str]) -> ArgMatches<'static> {
+        args(App::new("duk")).get_matches_from(argv)
+    }
+
+    #[test]
+    fn loader_allows_project() {
+        let root = env::temp_dir().join("duk_loader_policy");
+        fs::create_dir_all(root.join("app/src")).unwrap();
+        fs::create_dir_all(root.join("app/node_modules/dep")).unwrap();
+        fs::write(root.join("app/package.json"), "{}").unwrap();
+        fs::write(root.join("app/src/main.js"), "").unwrap();
+        fs::write(root.join("secret.js"), "").unwrap();
+        let entry = root.join("app/src/main.js");
+
+        let matches = app_args(&["duk"]);
+        let permissions = from_matches(&matches);
+        let policy = loader_policy(&matches, &permissions, Some(&entry));
+        assert!(policy.check_read(root.join("app/lib/util.js")).is_ok());
+        assert!(policy.check_read(root.join("app/node_modules/dep/index.js")).is_ok());
+        assert!(policy.check_read(root.join("secret.js")).is_err());
+        assert!(policy.check_write(root.join("app/src/main.js")).is_err());
+        // The fs module is not granted anything
+        assert!(permissions.fs_policy().check_read(&entry).is_err());
+
+        // Scripts without a file load modules from the working directory
+        let policy = loader_policy(&matches, &permissions, None);
+        assert!(policy.check_read(env::current_dir().unwrap().join("lib.js")).is_ok());
+        assert!(policy.check_read(root.join("secret.js")).is_err());
+
+        let matches = app_args(&["duk", &format!("--allow-import={}", root.display())]);
+        let policy = loader_policy(&matches, &from_matches(&matches), None);
+        assert!(policy.check_read(root.join("secret.js")).is_ok());
+
+        for argv in &[&["duk", "--allow-import"], &["duk", "--allow-read"]] {
+            let matches = app_args(*argv);
+            let policy = loader_policy(&matches, &from_matches(&matches), None);
+            assert!(!policy.is_restricted());
+        }
+    }
+
+    #[test]
+    fn write_grants() {
+        let root = env::temp_dir().join("duk_write_grants");
+        let file = root.join("out.txt");
+
+        let permissions = from_matches(&app_args(&["duk", "--allow-write"]));
+        assert!(permissions.fs_policy().check_write(&file).is_ok());
+        assert!(permissions.fs_policy().check_read(&file).is_err());
+
+        let out = format!("--allow-write={}", root.join("out").display());
+        let permissions = from_matches(&app_args(&["duk", "--allow-read", &out]));
+        assert!(permissions.fs_policy().check_read(&file).is_ok());
+        assert!(permissions.fs_policy().check_write(&file).is_err());
+        assert!(permissions.fs_policy().check_write(root.join("out/a.txt")).is_ok());
+
+        let permissions = from_matches(&app_args(&["duk", "--allow-read"]));
+        assert!(permissions.fs_policy().check_write(&file).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use colored::*;
+use duktape::prelude::*;
+use duktape_stdlib::inspect::{class_of, is_identifier, own_keys, own_property, prop, quote, Property};
+
+/// Objects nested deeper than this are printed as `[Object]`
+const MAX_DEPTH: usize = 2;
+/// Arrays print at most this many items
+const MAX_ITEMS: usize = 100;
+/// Objects, which print wider than this, are broken over multiple lines
+const LINE_WIDTH: usize = 72;
+
+fn same(ctx: &Context, a: &Ref, b: &Ref) -> bool {
+    a.push();
+    b.push();
+    let ret = ctx.strict_equals(-2, -1);
+    ctx.pop(2);
+    ret
+}
+
+/// The printed width of `s`, without color codes
+fn width(s: &str) -> usize {
+    let mut width = 0;
+    let mut escape = false;
+    for c in s.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if !escape => width += 1,
+            _ => {}
+        }
+    }
+    width
+}
+
+struct Printer<'a> {
+    ctx: &'a Context,
+    parents: Vec<Ref<'a>>,
+}
+
+impl<'a> Printer<'a> {
+    fn print(&mut self, value: &Ref<'a>, depth: usize) -> String {
+        let ctx = self.ctx;
+        match value.get_type() {
+            Type::Undefined => return "undefined".bright_black().to_string(),
+            Type::Null => return "null".bold().to_string(),
+            Type::Boolean | Type::Number => return value.to_string().yellow().to_string(),
+            Type::String => {
+                let s = value.get::<String>().unwrap_or_default();
+                return quote(&s).green().to_string();
+            }
+            Type::Buffer => {
+                let bytes = value.get::<&[u8]>().unwrap_or(&[]);
+                let hex: Vec<String> = bytes.iter().take(MAX_ITEMS).map(|b| format!("{:02x}", b)).collect();
+                return format!("<Buffer {}>", hex.join(" ")).cyan().to_string();
+            }
+            Type::Function => {
+                let name = prop(ctx, value, "name").map(|n| n.to_string()).unwrap_or_default();
+                let label = if name.is_empty() {
+                    "[Function]".to_owned()
+                } else {
+                    format!("[Function: {}]", name)
+                };
+                return label.cyan().to_string();
+            }
+            Type::Object | Type::Array => {}
+        }
+
+        if self.parents.iter().any(|p| same(ctx, p, value)) {
+            return "[Circular]".cyan().to_string();
+        }
+
+        let class = class_of(ctx, value).unwrap_or_default();
+        match class.as_str() {
+            "[object Date]" => return value.to_string().magenta().to_string(),
+            "[object RegExp]" => return value.to_string().red().to_string(),
+            "[object Error]" => {
+                return prop(ctx, value, "stack")
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|_| value.to_string())
+            }
+            _ => {}
+        }
+
+        let array = value.is(Type::Array);
+        if depth > MAX_DEPTH {
+            let label = if array { "[Array]" } else { "[Object]" };
+            return label.cyan().to_string();
+        }
+
+        let keys = own_keys(ctx, value).unwrap_or_default();
+        let (open, close) = if array { ("[", "]") } else { ("{", "}") };
+        if keys.is_empty() {
+            return format!("{}{}", open, close);
+        }
+
+        self.parents.push(value.clone());
+        let mut items = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            if array && i == MAX_ITEMS {
+                items.push(format!("... {} more items", keys.len() - MAX_ITEMS));
+                break;
+            }
+            let item = match own_property(ctx, value, key) {
+                Ok(Property::Value(item)) => self.print(&item, depth + 1),
+                Ok(Property::Accessor(getter, setter)) => match (getter, setter) {
+                    (true, true) => "[Getter/Setter]".cyan().to_string(),
+                    (true, false) => "[Getter]".cyan().to_string(),
+                    _ => "[Setter]".cyan().to_string(),
+                },
+                Err(_) => "undefined".bright_black().to_string(),
+            };
+            if array && key.parse::<u32>().is_ok() {
+                items.push(item);
+            } else if is_identifier(key) {
+                items.push(format!("{}: {}", key, item));
+            } else {
+                items.push(format!("{}: {}", quote(key), item));
+            }
+        }
+        self.parents.pop();
+
+        let line: usize = items.iter().map(|i| width(i) + 2).sum();
+        if line <= LINE_WIDTH && !items.iter().any(|i| i.contains('\n')) {
+            format!("{} {} {}", open, items.join(", "), close)
+        } else {
+            let indent = "  ";
+            let items: Vec<String> = items
+                .iter()
+                .map(|i| format!("{}{}", indent, i.replace('\n', &format!("\n{}", indent))))
+                .collect();
+            format!("{}\n{}\n{}", open, items.join(",\n"), close)
+        }
+    }
+}
+
+/// Format `value` for the REPL, like Node's `util.inspect`,
+/// with nested objects below a depth printed as `[Object]`
+pub fn pretty<'a>(ctx: &'a Context, value: &Ref<'a>) -> String {
+    let mut printer = Printer {
+        ctx,
+        parents: Vec::new(),
+    };
+    printer.print(value, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pretty;
+    use duktape::prelude::*;
+
+    fn print(ctx: &Context, script: &str) -> String {
+        colored::control::set_override(false);
+        let value: Ref = ctx.eval(script).unwrap().getp().unwrap();
+        pretty(ctx, &value)
+    }
+
+    #[test]
+    fn values() {
+        let ctx = Context::new().unwrap();
+        assert_eq!(print(&ctx, "undefined"), "undefined");
+        assert_eq!(print(&ctx, "'it\\'s\\n'"), "'it\\'s\\n'");
+        assert_eq!(print(&ctx, "(function named() {})"), "[Function: named]");
+        assert_eq!(print(&ctx, "[]"), "[]");
+        assert_eq!(print(&ctx, "({ a: 1, 'b-c': [1, 'x'] })"), "{ a: 1, 'b-c': [ 1, 'x' ] }");
+        assert_eq!(print(&ctx, "({ a: { b: { c: { d: 1 } } } })"), "{ a: { b: { c: [Object] } } }");
+        assert_eq!(print(&ctx, "var o = { name: 'o' }; o.self = o; o"), "{ name: 'o', self: [Circular] }");
+    }
+
+    #[test]
+    fn long_values() {
+        let ctx = Context::new().unwrap();
+        let printed = print(&ctx, "var a = []; for (var i = 0; i < 101; i++) a.push(i); a");
+        assert!(printed.starts_with("[\n  0,\n  1,"), "{}", printed);
+        assert!(printed.ends_with("  99,\n  ... 1 more items\n]"), "{}", printed);
+    }
+
+    #[test]
+    fn accessors() {
+        let ctx = Context::new().unwrap();
+        let printed = print(
+            &ctx,
+            "var calls = 0;
+             ({ get a() { calls++; return 1; }, get b() { return 2; }, set b(v) {}, set c(v) {}, d: 4 })",
+        );
+        assert_eq!(printed, "{ a: [Getter], b: [Getter/Setter], c: [Setter], d: 4 }");
+        let calls: i32 = ctx.eval("calls").unwrap().getp().unwrap();
+        assert_eq!(calls, 0);
+
+        // Traps are not run or their errors are caught
+        let printed = print(&ctx, "new Proxy({ a: 1 }, { get: function () { throw new Error('trap'); } })");
+        assert_eq!(printed, "{ a: undefined }");
+        let printed = print(&ctx, "new Proxy({ a: 1 }, { ownKeys: function () { throw new Error('trap'); } })");
+        assert_eq!(printed, "{}");
+        let printed = print(
+            &ctx,
+            "var f = function () {}; Object.defineProperty(f, 'name', { get: function () { throw 1; } }); f",
+        );
+        assert_eq!(printed, "[Function]");
+    }
+}
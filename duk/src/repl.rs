@@ -1,20 +1,271 @@
+use super::pretty::pretty;
 use colored::*;
+use duktape::error::ErrorKind;
 use duktape::prelude::*;
 use duktape_modules::error::Result;
 use duktape_modules::CJSContext;
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
-use rustyline::{ColorMode, CompletionType, Config, EditMode, Editor};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::{ColorMode, CompletionType, Config, EditMode, Editor, Helper};
+use std::cell::RefCell;
 use std::env;
+use std::fs;
+use std::mem;
 
-fn print_help() {}
+static COMMANDS: &'static [(&'static str, &'static str)] = &[
+    (".break", "Abort the current multi-line input"),
+    (".clear", "Reset the context and abort the current input"),
+    (".exit", "Exit the REPL"),
+    (".help", "Print this help message"),
+    (".load", "Load a file into the session: .load <file>"),
+    (".save", "Save the evaluated input of the session to a file: .save <file>"),
+    (".stack", "Print the value stack of the context"),
+];
 
-pub fn run(ctx: &Context, es6: bool) -> Result<()> {
-    let require: Object = ctx.get_global_string("require").getp()?;
+fn print_help() {
+    for (name, help) in COMMANDS {
+        println!("{:8} {}", name, help);
+    }
+    println!("\nPress ^C to abort the current input, ^D or ^C twice to exit");
+}
+
+/// The first line of the message of `err`, when it is a SyntaxError
+fn syntax_error(err: &duktape::error::Error) -> Option<&str> {
+    match err.kind() {
+        ErrorKind::TypeError(message) => message.lines().next().filter(|m| m.starts_with("SyntaxError:")),
+        _ => None,
+    }
+}
+
+/// The line a message like `parse error (line 4)` or `Unexpected token (4:0)` points at
+fn error_line(message: &str) -> Option<usize> {
+    let start = message.rfind('(')? + 1;
+    let end = start + message[start..].find(')')?;
+    message[start..end].trim_start_matches("line ").split(':').next()?.parse().ok()
+}
+
+/// Whether `source` is cut short, that is compiling it fails with a SyntaxError at the end of the input.
+/// With `es6` the source is compiled by the es2015 transform, which also reads template literals
+fn is_incomplete(ctx: &Context, source: &str, es6: bool) -> bool {
+    // Errors are reported at the line of the next token, so blank lines tell
+    // a token missing at the end apart from an error on the last line of `source`.
+    // A stray closing bracket ending the input is then taken for a missing token,
+    // and is reported once the next line is read
+    let padded = format!("{}\n\n", source);
+    let end = padded.matches('\n').count() + 1;
+
+    let result = if es6 {
+        match ctx.require("es2015") {
+            Ok(es2015) => es2015.call::<_, _, Object>("transform", padded.as_str()).map(|_| ()),
+            Err(_) => return false,
+        }
+    } else {
+        ctx.compile_string(&padded, Compile::EVAL).map(|_| {
+            ctx.pop(1);
+        })
+    };
+
+    let err = match result {
+        Ok(_) => return false,
+        Err(err) => err,
+    };
+    match syntax_error(&err) {
+        Some(message) => {
+            let message = message.trim_start_matches("SyntaxError: ").to_lowercase();
+            // Both are reported where they start
+            message.starts_with("unterminated comment")
+                || message.starts_with("unterminated template")
+                || error_line(&message) == Some(end)
+        }
+        None => false,
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Replace the object on top of the stack with the value of its property `key`,
+/// found on it or its prototypes. Accessors are not called, so getters with
+/// side effects do not run while completing. Returns false and pops the object,
+/// when there is no such data property
+fn data_property(ctx: &Context, key: &str) -> bool {
+    loop {
+        match ctx.get_type(-1) {
+            Type::Object | Type::Array | Type::Function => {}
+            _ => {
+                ctx.pop(1);
+                return false;
+            }
+        }
+
+        ctx.get_global_string("Object")
+            .get_prop_string(-1, "getOwnPropertyDescriptor")
+            .remove(-2)
+            .dup(-2)
+            .push_string(key);
+        if ctx.call(2).is_err() {
+            ctx.pop(1);
+            return false;
+        }
+
+        if ctx.get_type(-1) == Type::Undefined {
+            ctx.pop(1);
+            ctx.get_prototype(-1).remove(-2);
+            continue;
+        }
+
+        let data = ctx.has_prop_string(-1, "value");
+        if data {
+            ctx.get_prop_string(-1, "value").remove(-2).remove(-2);
+        } else {
+            ctx.pop(2);
+        }
+        return data;
+    }
+}
+
+/// The names of the properties of the value of `expr`, a path like `console` or
+/// `a.b`, or of the global object
+fn property_names(ctx: &Context, expr: Option<&str>) -> Vec<String> {
+    ctx.push_global_object();
+    for key in expr.into_iter().flat_map(|expr| expr.split('.')) {
+        if key.is_empty() || !data_property(ctx, key) {
+            return Vec::new();
+        }
+    }
+
+    let mut names = Vec::new();
+    match ctx.get_type(-1) {
+        Type::Object | Type::Array | Type::Function => {
+            if ctx.enumerator(-1, Enumerate::INCLUDE_NONENUMERABLE).is_ok() {
+                while let Ok(true) = ctx.next(-1, false) {
+                    if let Ok(name) = ctx.get_string(-1) {
+                        names.push(name.to_owned());
+                    }
+                    ctx.pop(1);
+                }
+                ctx.pop(1);
+            }
+        }
+        _ => {}
+    }
+    ctx.pop(1);
+
+    names.retain(|n| !n.is_empty() && !n.starts_with(|c: char| c.is_ascii_digit()) && n.chars().all(is_identifier_char));
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Complete the expression before `pos`, like `cons` or `console.lo`
+fn complete(ctx: &Context, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+
+    if before.starts_with('.') && !before.contains(' ') {
+        let commands = COMMANDS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .filter(|name| name.starts_with(before))
+            .collect();
+        return (0, commands);
+    }
+
+    let start = before
+        .rfind(|c: char| !(is_identifier_char(c) || c == '.'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let word = &before[start..];
+
+    let (expr, prefix, offset) = match word.rfind('.') {
+        Some(0) => return (pos, Vec::new()),
+        Some(i) => (Some(&word[..i]), &word[i + 1..], start + i + 1),
+        None => (None, word, start),
+    };
+
+    let names = property_names(ctx, expr)
+        .into_iter()
+        .filter(|n| n.starts_with(prefix))
+        .collect();
+    (offset, names)
+}
+
+struct ReplHelper<'a> {
+    ctx: &'a RefCell<Context>,
+}
+
+impl<'a> Completer for ReplHelper<'a> {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&self.ctx.borrow(), line, pos))
+    }
+}
+
+impl<'a> Hinter for ReplHelper<'a> {
+    fn hint(&self, _line: &str, _pos: usize) -> Option<String> {
+        None
+    }
+}
 
+impl<'a> Highlighter for ReplHelper<'a> {}
+
+impl<'a> Helper for ReplHelper<'a> {}
+
+/// Make `require` resolve relative to the working directory
+fn prepare(ctx: &Context) -> Result<()> {
+    let require: Object = ctx.get_global_string("require").getp()?;
     require.set(
         b"\xFFmoduleId",
         format!("{}/___repl.js", env::current_dir()?.to_str().unwrap()),
     );
+    Ok(())
+}
+
+/// Evaluate `source` and print the result or the error.
+/// Returns whether the evaluation succeeded
+fn evaluate(ctx: &Context, source: &str, es6: bool) -> bool {
+    let transformed;
+    let source = if es6 {
+        let transform = ctx.require("es2015").and_then(|es2015| {
+            let result = es2015.call::<_, _, Object>("transform", source)?;
+            Ok(result.get::<_, String>("code")?)
+        });
+        match transform {
+            Ok(code) => {
+                transformed = code;
+                transformed.as_str()
+            }
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                return false;
+            }
+        }
+    } else {
+        source
+    };
+
+    match ctx.eval(source.replace("\'use strict\';", "").trim()) {
+        Err(e) => {
+            println!("{}", e.to_string().red());
+            false
+        }
+        Ok(_) => {
+            match ctx.getp::<Ref>() {
+                Ok(value) => println!("{}", pretty(ctx, &value)),
+                Err(e) => println!("{}", e.to_string().red()),
+            }
+            true
+        }
+    }
+}
+
+/// Run the REPL in `ctx`. `.clear` replaces the context with one from `create`
+pub fn run<F: Fn() -> Result<Context>>(ctx: Context, create: F, es6: bool) -> Result<()> {
+    prepare(&ctx)?;
+    let ctx = RefCell::new(ctx);
 
     let config = Config::builder()
         .edit_mode(EditMode::Vi)
@@ -23,81 +274,167 @@ pub fn run(ctx: &Context, es6: bool) -> Result<()> {
         .history_ignore_dups(true)
         .build();
 
-    let mut rl = Editor::<()>::with_config(config);
+    let mut rl = Editor::<ReplHelper>::with_config(config);
+    rl.set_helper(Some(ReplHelper { ctx: &ctx }));
 
     if rl.load_history("duk_history.txt").is_err() {
         println!("No previous history.");
     }
 
     let mut next_should_close = false;
+    // Unfinished multi-line input
+    let mut buffer = String::new();
+    // Input evaluated without errors, for .save
+    let mut session: Vec<String> = Vec::new();
 
     loop {
-        let readline = rl.readline("duk> ");
-
-        match readline {
-            Ok(line) => {
-                next_should_close = false;
-
-                match line.as_str() {
-                    ".exit" => break,
-                    ".stack" => {
-                        println!("{:?}", ctx);
-                        continue;
-                    }
-                    ".refs" => {
-                        // ctx.push_global_stash().;
-
-                        continue;
-                    }
-                    ".help" => {
-                        print_help();
-                        continue;
-                    }
-                    _ => {}
-                }
-
-                rl.add_history_entry(line.as_ref());
-
-                let source = if es6 {
-                    ctx.require("es2015")?
-                        .call::<_, _, Object>("transform", line.as_str())?
-                        .get::<_, &str>("code")?
-                } else {
-                    line.as_str()
-                };
-
-                match ctx.eval(source.replace("\'use strict\';", "").trim()) {
-                    Err(e) => println!("  {:?}", e),
-                    Ok(_) => {
-                        let re = ctx.getp::<Ref>()?;
-                        let s = match re.get_type() {
-                            Type::Boolean | Type::Number => format!("{}", re.to_string().yellow()),
-                            Type::Null => format!("{}", re.to_string().white()),
-                            Type::String => format!("'{}'", re.to_string().green()),
-                            _ => format!("{}", re.to_string().bright_black()),
-                        };
-                        println!("{}", s);
-                    }
-                };
-            }
+        let prompt = if buffer.is_empty() { "duk> " } else { "...  " };
+        let line = match rl.readline(prompt) {
+            Ok(line) => line,
             Err(ReadlineError::Interrupted) => {
+                if !buffer.is_empty() {
+                    buffer.clear();
+                    continue;
+                }
                 if next_should_close {
                     break;
                 }
                 next_should_close = true;
                 println!("(To exit, press ^C again or type .exit)");
+                continue;
             }
-            Err(ReadlineError::Eof) => {
-                //println!("CTRL-D");
-                break;
-            }
+            Err(ReadlineError::Eof) => break,
             Err(err) => {
                 println!("Error: {:?}", err);
                 break;
             }
+        };
+        next_should_close = false;
+
+        let trimmed = line.trim();
+        let mut parts = trimmed.splitn(2, ' ');
+        let command = parts.next().unwrap_or("");
+        let argument = parts.next().map(|a| a.trim()).filter(|a| !a.is_empty());
+
+        // Lines in multi-line input may start with a `.`, like chained calls
+        if COMMANDS.iter().any(|(name, _)| *name == command) {
+            rl.add_history_entry(trimmed);
+            buffer.clear();
+            match command {
+                ".exit" => break,
+                ".help" => print_help(),
+                ".stack" => println!("{:?}", ctx.borrow()),
+                ".clear" => {
+                    let fresh = create()?;
+                    prepare(&fresh)?;
+                    *ctx.borrow_mut() = fresh;
+                    session.clear();
+                    println!("Clearing context...");
+                }
+                ".load" => match argument {
+                    Some(path) => match fs::read_to_string(path) {
+                        Ok(source) => {
+                            if evaluate(&ctx.borrow(), &source, es6) {
+                                session.push(source.trim_end().to_owned());
+                            }
+                        }
+                        Err(e) => println!("Failed to load {}: {}", path, e),
+                    },
+                    None => println!("Usage: .load <file>"),
+                },
+                ".save" => match argument {
+                    Some(path) => match fs::write(path, session.join("\n") + "\n") {
+                        Ok(_) => println!("Session saved to: {}", path),
+                        Err(e) => println!("Failed to save {}: {}", path, e),
+                    },
+                    None => println!("Usage: .save <file>"),
+                },
+                _ => {}
+            }
+            continue;
+        } else if buffer.is_empty() && command.starts_with('.') && command.len() > 1 {
+            let numeric = command[1..].starts_with(|c: char| c.is_ascii_digit());
+            if !numeric {
+                println!("Invalid REPL keyword, see .help");
+                continue;
+            }
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if is_incomplete(&ctx.borrow(), &buffer, es6) {
+            continue;
+        }
+
+        let source = mem::replace(&mut buffer, String::new());
+        let source = source.trim_end();
+        if source.trim().is_empty() {
+            continue;
+        }
+
+        rl.add_history_entry(source);
+        if evaluate(&ctx.borrow(), source, es6) {
+            session.push(source.to_owned());
         }
     }
     rl.save_history("duk_history.txt").unwrap();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{complete, is_incomplete};
+    use duktape::prelude::*;
+
+    fn context() -> Context {
+        let ctx = Context::new().unwrap();
+        let mut require = duktape_modules::Builder::new();
+        duktape_es2015::register(&ctx, &mut require);
+        duktape_modules::register(&ctx, require).unwrap();
+        ctx
+    }
+
+    #[test]
+    fn incomplete_input() {
+        let ctx = context();
+        for source in &["function f() {\n", "foo(1,\n", "var a = {\n  b: 1,\n", "x = 1 +\n", "/* a\n"] {
+            assert!(is_incomplete(&ctx, source, false), "{:?}", source);
+        }
+        for source in &["1 + 1\n", "a)\n", "\"abc\n", "var a = 'a(';\n", "}\nfoo()\n", "a b\n"] {
+            assert!(!is_incomplete(&ctx, source, false), "{:?}", source);
+        }
+        assert_eq!(ctx.top(), 0);
+    }
+
+    #[test]
+    fn incomplete_es6_input() {
+        let ctx = context();
+        for source in &["const f = () => {\n", "`a\n", "class A {\n"] {
+            assert!(is_incomplete(&ctx, source, true), "{:?}", source);
+        }
+        for source in &["const a = `${1}`\n", "a)\n"] {
+            assert!(!is_incomplete(&ctx, source, true), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn completions() {
+        let ctx = context();
+        ctx.eval("var value = { alpha: 1, also: { beta: 2 } }; Object.defineProperty(value, 'allGetter', { get: function () { throw new Error('called'); }, enumerable: true });")
+            .unwrap()
+            .pop(1);
+
+        assert_eq!(complete(&ctx, ".he", 3), (0, vec![".help".to_owned()]));
+        assert_eq!(
+            complete(&ctx, "value.al", 8),
+            (6, vec!["allGetter".to_owned(), "alpha".to_owned(), "also".to_owned()])
+        );
+        assert_eq!(complete(&ctx, "1 + value.also.b", 16), (15, vec!["beta".to_owned()]));
+        // Getters are not called to complete their properties
+        assert_eq!(complete(&ctx, "value.allGetter.", 16), (16, Vec::<String>::new()));
+        let (_, names) = complete(&ctx, "value.also.hasOwn", 17);
+        assert_eq!(names, vec!["hasOwnProperty".to_owned()]);
+        assert_eq!(ctx.top(), 0);
+    }
+}
@@ -0,0 +1,241 @@
+//! Executables built by `duk compile` are a copy of duk, with a script appended:
+//! the payload, the arguments duk runs it with, and a trailer of the two lengths
+//! and a magic number.
+use super::bundle;
+use super::permissions;
+use clap::ArgMatches;
+use duktape::prelude::*;
+use duktape_modules::bundle as bundler;
+use duktape_modules::error::Result;
+use duktape_modules::CJSContext;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"duk\0pkg\0";
+const TRAILER_LEN: usize = 8 + 8 + 8;
+
+/// A script embedded in an executable
+pub struct Payload {
+    /// The arguments duk runs the script with, including the program name
+    pub args: Vec<String>,
+    /// Bytecode or source
+    pub script: Vec<u8>,
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut n = [0u8; 8];
+    n.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(n)
+}
+
+/// Parse the trailer, returning the lengths of the script and the arguments
+fn trailer(trailer: &[u8]) -> Option<(usize, usize)> {
+    if trailer.len() != TRAILER_LEN || &trailer[16..] != MAGIC {
+        return None;
+    }
+    Some((read_u64(&trailer[..8]) as usize, read_u64(&trailer[8..16]) as usize))
+}
+
+/// The length of the script, the arguments and the trailer, if it does not overflow
+fn payload_len(script: usize, args: usize) -> Option<usize> {
+    script.checked_add(args)?.checked_add(TRAILER_LEN)
+}
+
+/// The length of the executable in `exe`, without an appended payload
+fn executable_len(exe: &[u8]) -> usize {
+    if exe.len() < TRAILER_LEN {
+        return exe.len();
+    }
+    match trailer(&exe[exe.len() - TRAILER_LEN..]).and_then(|(script, args)| payload_len(script, args)) {
+        Some(payload) if payload <= exe.len() => exe.len() - payload,
+        _ => exe.len(),
+    }
+}
+
+/// The executable `exe` with `script` and `args` appended, replacing a payload appended before
+fn append(exe: &[u8], script: &[u8], args: &[String]) -> Vec<u8> {
+    let args = args.join("\0").into_bytes();
+    let mut out = exe[..executable_len(exe)].to_vec();
+    out.extend_from_slice(script);
+    out.extend_from_slice(&args);
+    out.extend_from_slice(&(script.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(args.len() as u64).to_le_bytes());
+    out.extend_from_slice(MAGIC);
+    out
+}
+
+/// Read the payload appended to `file` of `len` bytes
+fn read_payload<R: Read + Seek>(file: &mut R, len: u64) -> Result<Option<Payload>> {
+    if len < TRAILER_LEN as u64 {
+        return Ok(None);
+    }
+
+    let mut buf = [0u8; TRAILER_LEN];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut buf)?;
+    let (script_len, args_len) = match trailer(&buf) {
+        Some((script, args)) => match payload_len(script, args) {
+            Some(payload) if payload as u64 <= len => (script, args),
+            _ => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+
+    let mut script = vec![0u8; script_len];
+    let mut args = vec![0u8; args_len];
+    file.seek(SeekFrom::End(-((TRAILER_LEN + script_len + args_len) as i64)))?;
+    file.read_exact(&mut script)?;
+    file.read_exact(&mut args)?;
+
+    let mut all = vec!["duk".to_string()];
+    all.extend(
+        String::from_utf8_lossy(&args)
+            .split('\0')
+            .filter(|a| !a.is_empty())
+            .map(|a| a.to_owned()),
+    );
+
+    Ok(Some(Payload { args: all, script }))
+}
+
+/// The payload embedded in the running executable
+pub fn payload() -> Result<Option<Payload>> {
+    let mut file = File::open(env::current_exe()?)?;
+    let len = file.metadata()?.len();
+    read_payload(&mut file, len)
+}
+
+/// Run the embedded script. The arguments of the executable are available in `process.argv`
+pub fn run(ctx: &Context, payload: &Payload) -> Result<()> {
+    if bundle::is_bytecode(&payload.script) {
+        bundle::eval_bytecode(ctx, &payload.script)
+    } else {
+        let name = env::current_exe()?.with_extension("js");
+        ctx.eval_main_script(name, &payload.script)?;
+        Ok(())
+    }
+}
+
+/// Build an executable of the entry of `matches`, running with the granted permissions
+pub fn compile(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+    let input = matches.value_of("input").unwrap();
+    let output = matches.value_of("output").unwrap();
+
+    let script = bundler::bundle(ctx, input)?;
+    let script = if matches.is_present("source") {
+        script.into_bytes()
+    } else {
+        bundler::bytecode(ctx, &script, input)?
+    };
+
+    let mut args = permissions::to_args(matches);
+    if matches.is_present("es2015") {
+        // The bundle is transformed already, but may depend on the es2015 runtime
+        args.push("--es2015".to_string());
+    }
+
+    let exe = fs::read(env::current_exe()?)?;
+    fs::write(output, append(&exe, &script, &args))?;
+    make_executable(Path::new(output))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(0o755);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append, executable_len, read_payload, run, trailer, MAGIC, TRAILER_LEN};
+    use duktape::prelude::*;
+    use duktape_modules::bundle as bundler;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn trailer_of(script: u64, args: u64) -> Vec<u8> {
+        let mut bytes = script.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&args.to_le_bytes());
+        bytes.extend_from_slice(MAGIC);
+        bytes
+    }
+
+    #[test]
+    fn trailers() {
+        assert_eq!(trailer(&trailer_of(3, 4)), Some((3, 4)));
+        assert_eq!(trailer(&trailer_of(3, 4)[1..]), None);
+
+        let mut bad_magic = trailer_of(3, 4);
+        bad_magic[TRAILER_LEN - 1] = b'x';
+        assert_eq!(trailer(&bad_magic), None);
+    }
+
+    #[test]
+    fn executable_lens() {
+        assert_eq!(executable_len(b"exe"), 3);
+
+        let mut exe = b"exe".to_vec();
+        exe.extend_from_slice(b"script");
+        exe.extend_from_slice(&trailer_of(6, 0));
+        assert_eq!(executable_len(&exe), 3);
+
+        // Lengths beyond the file or overflowing are not a payload
+        let mut exe = b"exe".to_vec();
+        exe.extend_from_slice(&trailer_of(100, 0));
+        assert_eq!(executable_len(&exe), exe.len());
+        let mut exe = b"exe".to_vec();
+        exe.extend_from_slice(&trailer_of(u64::MAX, 2));
+        assert_eq!(executable_len(&exe), exe.len());
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let args = vec!["--allow-read=/tmp".to_string(), "--es2015".to_string()];
+        let exe = append(b"\x7fELF binary", b"print('hello')", &args);
+        let replaced = append(&exe, b"print('replaced')", &args[..1]);
+        assert_eq!(&replaced[..executable_len(&replaced)], b"\x7fELF binary");
+
+        let len = replaced.len() as u64;
+        let payload = read_payload(&mut Cursor::new(replaced), len).unwrap().unwrap();
+        assert_eq!(payload.script, b"print('replaced')");
+        assert_eq!(payload.args, vec!["duk", "--allow-read=/tmp"]);
+
+        let plain = b"\x7fELF binary".to_vec();
+        assert!(read_payload(&mut Cursor::new(plain), 12).unwrap().is_none());
+    }
+
+    #[test]
+    fn compile_and_run() {
+        let dir = env::temp_dir().join("duk_standalone");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.js"), "global.answer = require('./answer');").unwrap();
+        fs::write(dir.join("answer.js"), "module.exports = 42;").unwrap();
+        let main = dir.join("main.js");
+        let main = main.to_str().unwrap();
+
+        let ctx = Context::new().unwrap();
+        duktape_modules::register(&ctx, duktape_modules::Builder::new()).unwrap();
+        let script = bundler::bundle(&ctx, main).unwrap();
+        let bytecode = bundler::bytecode(&ctx, &script, main).unwrap();
+        let exe = append(b"exe", &bytecode, &[]);
+
+        let len = exe.len() as u64;
+        let payload = read_payload(&mut Cursor::new(exe), len).unwrap().unwrap();
+        let ctx = Context::new().unwrap();
+        duktape_modules::register(&ctx, duktape_modules::Builder::new()).unwrap();
+        run(&ctx, &payload).unwrap();
+        let answer: i32 = ctx.eval("answer").unwrap().getp().unwrap();
+        assert_eq!(answer, 42);
+    }
+}
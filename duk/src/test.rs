@@ -0,0 +1,370 @@
+use super::coverage;
+use clap::ArgMatches;
+use colored::*;
+use duktape::coverage::Coverage;
+use duktape::prelude::*;
+use duktape_modules::error::Result;
+use duktape_modules::CJSContext;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+/// Files found in directories given to `duk test`
+static PATTERNS: &'static [&'static str] = &["**/*.test.js", "**/*.spec.js", "**/*_test.js"];
+
+/// Makes the functions of the test module globals, like in mocha and jest
+static GLOBALS: &'static str =
+    "(function (t) { for (var k in t) { if (k !== 'run') global[k] = t[k]; } })(require('test'))";
+
+struct TestResult {
+    name: String,
+    ok: bool,
+    skipped: bool,
+    message: String,
+    stack: String,
+    duration: u32,
+}
+
+impl TestResult {
+    fn failed(name: &str, message: String) -> TestResult {
+        TestResult {
+            name: name.to_owned(),
+            ok: false,
+            skipped: false,
+            stack: message.clone(),
+            message,
+            duration: 0,
+        }
+    }
+}
+
+struct FileResult {
+    path: String,
+    tests: Vec<TestResult>,
+}
+
+impl FileResult {
+    fn count<F: Fn(&TestResult) -> bool>(&self, predicate: F) -> usize {
+        self.tests.iter().filter(|t| predicate(t)).count()
+    }
+}
+
+fn glob_files(pattern: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+    let paths = match glob::glob(pattern) {
+        Ok(paths) => paths,
+        Err(e) => return Err(format!("invalid pattern '{}': {}", pattern, e).into()),
+    };
+    for path in paths.filter_map(|p| p.ok()) {
+        let vendored = path
+            .components()
+            .any(|c| c == Component::Normal("node_modules".as_ref()));
+        if path.is_file() && !vendored {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Find the test files of `paths`, which are files, directories or glob patterns
+fn discover(paths: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.contains(|c| c == '*' || c == '?' || c == '[') {
+            glob_files(path, &mut files)?;
+        } else if Path::new(path).is_dir() {
+            for pattern in PATTERNS {
+                let pattern = Path::new(path).join(pattern);
+                glob_files(&pattern.to_string_lossy(), &mut files)?;
+            }
+        } else {
+            files.push(PathBuf::from(path));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Run the tests of `path` in `ctx`
+fn run_file(ctx: &Context, path: &Path, filter: &str) -> Result<Vec<TestResult>> {
+    ctx.eval(GLOBALS)?;
+    ctx.eval_main(path)?;
+
+    let test = ctx.require("test")?;
+    let results = test.call::<_, _, Array>("run", filter)?;
+
+    let mut tests = Vec::new();
+    for result in results.iter() {
+        let result: Object = result.get()?;
+        tests.push(TestResult {
+            name: result.get("name")?,
+            ok: result.get("ok")?,
+            skipped: result.get("skipped")?,
+            message: result.get("message")?,
+            stack: result.get("stack")?,
+            duration: result.get("duration")?,
+        });
+    }
+    Ok(tests)
+}
+
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|l| format!("{}{}", prefix, l))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn report_spec(files: &[FileResult]) {
+    for file in files {
+        println!("{}", file.path.bold());
+        for test in &file.tests {
+            if test.skipped {
+                println!("  {} {}", "-".yellow(), test.name.dimmed());
+            } else if test.ok {
+                println!("  {} {} {}", "✓".green(), test.name, format!("({} ms)", test.duration).dimmed());
+            } else {
+                println!("  {} {}", "✗".red(), test.name.red());
+                println!("{}", indent(&test.stack, "      "));
+            }
+        }
+    }
+
+    let count = |predicate: &dyn Fn(&TestResult) -> bool| -> usize {
+        files.iter().map(|f| f.count(|t| predicate(t))).sum()
+    };
+    println!(
+        "\n{} passed, {} failed, {} skipped",
+        count(&|t| t.ok && !t.skipped).to_string().green(),
+        count(&|t| !t.ok).to_string().red(),
+        count(&|t| t.skipped).to_string().yellow()
+    );
+}
+
+fn report_tap<W: Write>(out: &mut W, files: &[FileResult]) -> io::Result<()> {
+    writeln!(out, "TAP version 13")?;
+    let mut n = 0;
+    for file in files {
+        for test in &file.tests {
+            n += 1;
+            let name = format!("{} > {}", file.path, test.name);
+            if test.skipped {
+                writeln!(out, "ok {} - {} # SKIP", n, name)?;
+            } else if test.ok {
+                writeln!(out, "ok {} - {}", n, name)?;
+            } else {
+                writeln!(out, "not ok {} - {}", n, name)?;
+                writeln!(out, "  ---")?;
+                writeln!(out, "  message: {:?}", test.message)?;
+                writeln!(out, "  stack: |-")?;
+                writeln!(out, "{}", indent(&test.stack, "    "))?;
+                writeln!(out, "  ...")?;
+            }
+        }
+    }
+    writeln!(out, "1..{}", n)
+}
+
+/// Escape `text` for XML. Control characters, like the escape codes of
+/// colored output, can not be represented in XML 1.0 and are left out
+fn escape_xml(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || *c == '\t' || *c == '\n' || *c == '\r')
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn report_junit<W: Write>(out: &mut W, files: &[FileResult]) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<testsuites>")?;
+    for file in files {
+        let time: u32 = file.tests.iter().map(|t| t.duration).sum();
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+            escape_xml(&file.path),
+            file.tests.len(),
+            file.count(|t| !t.ok),
+            file.count(|t| t.skipped),
+            f64::from(time) / 1000.0
+        )?;
+        for test in &file.tests {
+            write!(
+                out,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                escape_xml(&test.name),
+                escape_xml(&file.path),
+                f64::from(test.duration) / 1000.0
+            )?;
+            if test.skipped {
+                writeln!(out, "><skipped/></testcase>")?;
+            } else if test.ok {
+                writeln!(out, "/>")?;
+            } else {
+                writeln!(
+                    out,
+                    r#"><failure message="{}">{}</failure></testcase>"#,
+                    escape_xml(&test.message),
+                    escape_xml(&test.stack)
+                )?;
+            }
+        }
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")
+}
+
+/// Run the test files given by `matches`, each in a context of its own from `create`.
+/// Exits with a non-zero code, when a test fails or no tests are found
+pub fn run<F: Fn(&Path) -> Result<Context>>(matches: &ArgMatches, create: F) -> Result<()> {
+    let paths: Vec<&str> = match matches.values_of("paths") {
+        Some(paths) => paths.collect(),
+        None => vec!["."],
+    };
+    let filter = matches.value_of("filter").unwrap_or("");
+    let cover = matches.is_present("coverage");
+
+    let paths = discover(&paths)?;
+    let mut files = Vec::new();
+    let mut covered = Coverage::default();
+    for path in &paths {
+        let display = path.to_string_lossy().into_owned();
+        let ctx = create(path)?;
+        if cover {
+            ctx.start_coverage();
+        }
+        let tests = match run_file(&ctx, path, filter) {
+            Ok(tests) => tests,
+            // A file which fails to load is reported as a failed test
+            Err(e) => vec![TestResult::failed(&display, e.to_string())],
+        };
+        if cover {
+            covered.merge(ctx.stop_coverage());
+        }
+        files.push(FileResult {
+            path: display,
+            tests,
+        });
+    }
+
+    let reporter = matches.value_of("reporter").unwrap_or("spec");
+    let stdout = io::stdout();
+    match reporter {
+        "tap" => report_tap(&mut stdout.lock(), &files)?,
+        "junit" => report_junit(&mut stdout.lock(), &files)?,
+        _ => report_spec(&files),
+    }
+
+    if cover {
+        let covered = coverage::sources(covered, &paths);
+        coverage::write_lcov(&covered, matches.value_of("lcov").unwrap_or(coverage::DEFAULT_LCOV))?;
+        // Keep the output of machine readable reporters parseable
+        if reporter == "spec" {
+            println!("\n{}", coverage::summary(&covered));
+        } else {
+            eprintln!("{}", coverage::summary(&covered));
+        }
+    }
+
+    let total: usize = files.iter().map(|f| f.tests.len()).sum();
+    let failed: usize = files.iter().map(|f| f.count(|t| !t.ok)).sum();
+    if total == 0 {
+        eprintln!("no tests found");
+        process::exit(1);
+    } else if failed > 0 {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover, escape_xml, report_junit, report_tap, FileResult, TestResult};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn result(name: &str, ok: bool, skipped: bool) -> TestResult {
+        TestResult {
+            name: name.to_owned(),
+            ok,
+            skipped,
+            message: if ok { String::new() } else { "expected <1>".to_owned() },
+            stack: if ok { String::new() } else { "AssertionError\n    at a.test.js:3".to_owned() },
+            duration: 1500,
+        }
+    }
+
+    fn files() -> Vec<FileResult> {
+        vec![FileResult {
+            path: "a.test.js".to_owned(),
+            tests: vec![result("adds", true, false), result("fails", false, false), result("later", true, true)],
+        }]
+    }
+
+    #[test]
+    fn discovers_files() {
+        let dir = env::temp_dir().join("duk_test_discover");
+        for file in &["a.test.js", "sub/b.spec.js", "c_test.js", "other.js", "node_modules/d.test.js"] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let root = dir.to_str().unwrap();
+        let relative = |paths: Vec<PathBuf>| -> Vec<String> {
+            paths
+                .iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            relative(discover(&[root]).unwrap()),
+            vec!["a.test.js", "c_test.js", "sub/b.spec.js"]
+        );
+
+        let other = dir.join("other.js");
+        let pattern = dir.join("*.js");
+        assert_eq!(
+            relative(discover(&[other.to_str().unwrap(), pattern.to_str().unwrap()]).unwrap()),
+            vec!["a.test.js", "c_test.js", "other.js"]
+        );
+    }
+
+    #[test]
+    fn tap() {
+        let mut out = Vec::new();
+        report_tap(&mut out, &files()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "TAP version 13\n\
+             ok 1 - a.test.js > adds\n\
+             not ok 2 - a.test.js > fails\n  ---\n  message: \"expected <1>\"\n  stack: |-\n\
+             \x20   AssertionError\n        at a.test.js:3\n  ...\n\
+             ok 3 - a.test.js > later # SKIP\n\
+             1..3\n"
+        );
+    }
+
+    #[test]
+    fn junit() {
+        let mut out = Vec::new();
+        report_junit(&mut out, &files()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#"<testsuite name="a.test.js" tests="3" failures="1" skipped="1" time="4.500">"#));
+        assert!(out.contains(r#"<testcase name="adds" classname="a.test.js" time="1.500"/>"#));
+        assert!(out.contains(r#"<failure message="expected &lt;1&gt;">AssertionError"#));
+        assert!(out.contains("<skipped/></testcase>"));
+        assert!(out.ends_with("</testsuites>\n"));
+    }
+
+    #[test]
+    fn xml_escapes() {
+        assert_eq!(escape_xml("a < b && \"c\" > d"), "a &lt; b &amp;&amp; &quot;c&quot; &gt; d");
+        assert_eq!(escape_xml("\u{1b}[31mred\u{1b}[0m\0\tok\n"), "[31mred[0m\tok\n");
+    }
+}
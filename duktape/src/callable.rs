@@ -1,8 +1,9 @@
 use super::context::Context;
 use super::error::Result;
+use super::privates::throw_error;
 use super::types::ToDuktape;
 use duktape_sys::*;
-use std::ffi::{c_void, CString};
+use std::ffi::c_void;
 
 static KEY: &'static [u8] = b"\xFFptr";
 
@@ -27,13 +28,7 @@ unsafe extern "C" fn call(ctx: *mut duk_context) -> duk_ret_t {
     duk_pop_2(ctx);
     let ret = match pp.call(&mut c) {
         Err(e) => {
-            duk_error_raw(
-                ctx,
-                DUK_ERR_ERROR as i32,
-                "".as_ptr() as *const i8,
-                0,
-                CString::new(format!("{}", e.0)).unwrap().as_ptr(),
-            );
+            throw_error(ctx, &e);
             -1
         }
         Ok(ret) => ret,
@@ -3,6 +3,7 @@ use super::super::{
     error::{ErrorKind, Result},
     Context, Idx,
 };
+use super::super::privates::throw_error;
 use super::method::{push_method, Instance, Method, CTOR_KEY, DATA_KEY};
 use duktape_sys as duk;
 use std::collections::HashMap;
@@ -128,13 +129,7 @@ unsafe extern "C" fn class_ctor(ctx: *mut duk::duk_context) -> duk::duk_ret_t {
             Ok(_) => {}
             Err(e) => {
                 Box::into_raw(ctor);
-                duk::duk_error_raw(
-                    ctx,
-                    duk::DUK_ERR_ERROR as i32,
-                    "".as_ptr() as *const i8,
-                    0,
-                    format!("ctor call failed: {}", e).as_ptr() as *const i8,
-                );
+                throw_error(ctx, &e);
                 return 0;
             }
         };
@@ -1,6 +1,7 @@
+use super::super::privates::throw_error;
 use super::super::{error::Result, Context};
 use duktape_sys::*;
-use std::ffi::{c_void, CString};
+use std::ffi::c_void;
 use typemap::TypeMap;
 
 pub struct Instance {
@@ -97,13 +98,7 @@ unsafe extern "C" fn call(ctx: *mut duk_context) -> duk_ret_t {
             // Keep it
             Box::into_raw(method);
             Box::into_raw(pp);
-            duk_error_raw(
-                ctx,
-                DUK_ERR_ERROR as i32,
-                "".as_ptr() as *const i8,
-                0,
-                CString::new(format!("{}", e.0)).unwrap().as_ptr(),
-            );
+            throw_error(ctx, &e);
             return 0;
         }
         Ok(ret) => ret,
@@ -1,11 +1,22 @@
 use super::callable::{push_callable, Callable};
 use super::class::{push_class_builder, Builder};
+#[cfg(feature = "debugger")]
+use super::debugger::{self, BoxedTransport, Transport};
+#[cfg(feature = "coverage")]
+use super::coverage::{self, Coverage};
+#[cfg(feature = "profiler")]
+use super::profiler::{self, Profile};
 use super::error::{ErrorKind, Result};
+use super::heap::{self, ReachableStats, ValueInfo};
 use super::privates;
 use super::types::{FromDuktape, ToDuktape, Type};
 use duktape_sys::{self as duk, duk_context};
 use std::ffi::CStr;
 use std::fmt;
+#[cfg(feature = "debugger")]
+use std::os::raw::c_void;
+#[cfg(feature = "profiler")]
+use std::time::Duration;
 use std::ptr;
 use typemap::TypeMap;
 
@@ -55,7 +66,10 @@ pub struct Context {
 macro_rules! handle_error {
     ($ret: expr, $ctx: expr) => {
         if ($ret) != duk::DUK_EXEC_SUCCESS as i32 {
-            if $ctx.has_prop_string(-1, "stack") {
+            // Reading properties of thrown primitives would throw outside a protected call
+            if !$ctx.is_object(-1) {
+                $ctx.dup(-1);
+            } else if $ctx.has_prop_string(-1, "stack") {
                 $ctx.get_prop_string(-1, "stack");
             } else {
                 $ctx.get_prop_string(-1, "message");
@@ -103,7 +117,10 @@ impl Context {
     /// Will return an error, if a duk heap couldn't be created
     /// The context manage the lifetime of the wrapped duktape context
     pub fn new() -> Result<Context> {
+        #[cfg(not(feature = "profiler"))]
         let d = unsafe { duk::duk_create_heap_default() };
+        #[cfg(feature = "profiler")]
+        let d = unsafe { duk::duk_create_heap(None, None, None, profiler::heap(), None) };
         if d.is_null() {
             return Err(ErrorKind::InsufficientMemory.into());
         }
@@ -203,6 +220,25 @@ impl Context {
         Ok(())
     }
 
+    /// Replace the compiled function at the top of the stack with its bytecode
+    pub fn dump_function(&self) -> Result<&Self> {
+        if unsafe { duk::duk_is_ecmascript_function(self.inner, -1) } == 0 {
+            bail!(ErrorKind::TypeError(format!("ecmascript function expected")));
+        }
+        unsafe { duk::duk_dump_function(self.inner) };
+        Ok(self)
+    }
+
+    /// Replace the bytecode at the top of the stack with the function it was dumped from.
+    /// Bytecode is not validated, so only load bytecode from trusted sources
+    pub fn load_function(&self) -> Result<&Self> {
+        if !self.is(Type::Buffer, -1) {
+            bail!(ErrorKind::TypeError(format!("buffer expected")));
+        }
+        unsafe { duk::duk_load_function(self.inner) };
+        Ok(self)
+    }
+
     pub fn dump(&self) -> String {
         unsafe {
             duk::duk_push_context_dump(self.inner);
@@ -259,6 +295,16 @@ impl Context {
         self
     }
 
+    /// Push `value` as a Node.js `Buffer`
+    pub fn push_node_buffer<T: AsRef<[u8]>>(&self, value: T) -> &Self {
+        let len = value.as_ref().len();
+        self.push_bytes(value);
+        unsafe {
+            duk::duk_push_buffer_object(self.inner, -1, 0, len, duk::DUK_BUFOBJ_NODEJS_BUFFER)
+        };
+        self.remove(-2)
+    }
+
     pub fn push_function<T: 'static + Callable>(&self, call: T) -> &Self {
         let c = Box::new(call);
         unsafe { push_callable(self, c) };
@@ -516,6 +562,7 @@ impl Context {
             duk::DUK_TYPE_BOOLEAN => Type::Boolean,
             duk::DUK_TYPE_NUMBER => Type::Number,
             duk::DUK_TYPE_STRING => Type::String,
+            duk::DUK_TYPE_BUFFER => Type::Buffer,
             duk::DUK_TYPE_OBJECT => {
                 if self.is_function(index) {
                     return Type::Function;
@@ -573,6 +620,119 @@ impl Context {
         }
     }
 
+    /// Compare the values at `a` and `b` with `==`
+    pub fn equals(&self, a: Idx, b: Idx) -> bool {
+        unsafe { duk::duk_equals(self.inner, a, b) == 1 }
+    }
+
+    /// Compare the values at `a` and `b` with `===`
+    pub fn strict_equals(&self, a: Idx, b: Idx) -> bool {
+        unsafe { duk::duk_strict_equals(self.inner, a, b) == 1 }
+    }
+
+    /// Compare the values at `a` and `b` like `Object.is`
+    pub fn same_value(&self, a: Idx, b: Idx) -> bool {
+        unsafe { duk::duk_samevalue(self.inner, a, b) == 1 }
+    }
+
+    /// Push the prototype of the object at `idx`
+    pub fn get_prototype(&self, idx: Idx) -> &Self {
+        unsafe { duk::duk_get_prototype(self.inner, idx) };
+        self
+    }
+
+    /// Run a garbage collection. Compacting also shrinks the property tables of objects.
+    /// Call twice to free objects with finalizers
+    pub fn gc(&self, compact: bool) -> &Self {
+        let flags = if compact { duk::DUK_GC_COMPACT } else { 0 };
+        unsafe { duk::duk_gc(self.inner, flags) };
+        self
+    }
+
+    /// Sizes, reference count and property table of the value at `idx`
+    pub fn inspect_value(&self, idx: Idx) -> ValueInfo {
+        heap::inspect(self, idx)
+    }
+
+    /// Count the values reachable from the global object, the stashes and the value stack.
+    /// Values only held by closure scopes and other engine internals are left out
+    pub fn reachable_stats(&self) -> ReachableStats {
+        heap::stats(self)
+    }
+
+    /// Attach a debug client through `transport`.
+    /// Execution pauses at the next statement, until the client resumes it
+    #[cfg(feature = "debugger")]
+    pub fn attach_debugger<T: 'static + Transport>(&self, transport: T) -> &Self {
+        let transport: BoxedTransport = Box::new(transport);
+        let udata = Box::into_raw(Box::new(transport));
+        unsafe {
+            duk::duk_debugger_attach(
+                self.inner,
+                Some(debugger::read_cb),
+                Some(debugger::write_cb),
+                Some(debugger::peek_cb),
+                None,
+                Some(debugger::write_flush_cb),
+                None,
+                Some(debugger::detached_cb),
+                udata as *mut c_void,
+            )
+        };
+        self
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn detach_debugger(&self) -> &Self {
+        unsafe { duk::duk_debugger_detach(self.inner) };
+        self
+    }
+
+    /// Process pending debug messages, while no script is running
+    #[cfg(feature = "debugger")]
+    pub fn debugger_cooperate(&self) -> &Self {
+        unsafe { duk::duk_debugger_cooperate(self.inner) };
+        self
+    }
+
+    /// Sample the call stack of scripts, until `stop_profiling` is called.
+    /// Only contexts created with `Context::new` can be profiled
+    #[cfg(feature = "profiler")]
+    pub fn start_profiling(&self) -> &Self {
+        self.start_profiling_with_interval(profiler::DEFAULT_INTERVAL)
+    }
+
+    #[cfg(feature = "profiler")]
+    pub fn start_profiling_with_interval(&self, interval: Duration) -> &Self {
+        if let Some(heap) = unsafe { profiler::get_heap(self.inner) } {
+            heap.sampler = Some(profiler::Sampler::new(interval));
+        }
+        self
+    }
+
+    /// Stop profiling and return the samples
+    #[cfg(feature = "profiler")]
+    pub fn stop_profiling(&self) -> Profile {
+        match unsafe { profiler::get_heap(self.inner) }.and_then(|heap| heap.sampler.take()) {
+            Some(sampler) => sampler.finish(),
+            None => Profile::default(),
+        }
+    }
+
+    /// Record the lines run by modules loaded from now on, until `stop_coverage` is called.
+    /// Lines are recorded by module loaders, which instrument the modules they load
+    #[cfg(feature = "coverage")]
+    pub fn start_coverage(&self) -> &Self {
+        coverage::start(self);
+        self
+    }
+
+    /// Stop covering and return the lines run, with the executable lines of the files they are in
+    #[cfg(feature = "coverage")]
+    pub fn stop_coverage(&self) -> Coverage {
+        coverage::stop(self)
+    }
+
     // Class
     pub fn push_class(&self, builder: Builder) -> Result<&Self> {
         let ret = unsafe { push_class_builder(self, builder) };
@@ -620,9 +780,17 @@ impl Context {
 impl Drop for Context {
     fn drop(&mut self) {
         if !self.inner.is_null() && self.managed {
+            #[cfg(feature = "profiler")]
+            let heap = unsafe { profiler::get_heap(self.inner) }.map(|heap| heap as *mut profiler::Heap);
             unsafe {
                 duk::duk_destroy_heap(self.inner);
             };
+            #[cfg(feature = "profiler")]
+            {
+                if let Some(heap) = heap {
+                    drop(unsafe { Box::from_raw(heap) });
+                }
+            }
         }
 
         self.data = ptr::null_mut();
@@ -650,7 +818,8 @@ impl std::cmp::PartialEq for Context {
 #[cfg(test)]
 pub mod tests {
 
-    use super::Context;
+    use super::super::error::Error;
+    use super::{Compile, Context};
 
     #[test]
     fn context_new() {
@@ -694,4 +863,239 @@ pub mod tests {
         assert_eq!(bs, bs2);
     }
 
+    #[test]
+    fn context_dump_function() {
+        let duk = Context::new().unwrap();
+
+        duk.compile_string("(function () { return 'dumped'; })()", Compile::EVAL)
+            .unwrap();
+        let bytecode = duk.dump_function().unwrap().get::<&[u8]>(-1).unwrap().to_vec();
+        duk.pop(1);
+
+        let result: String = duk
+            .push_bytes(&bytecode)
+            .load_function()
+            .unwrap()
+            .call(0)
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "dumped");
+
+        duk.push(1).unwrap();
+        assert!(duk.dump_function().is_err());
+    }
+
+    #[test]
+    fn context_throw_primitive() {
+        let duk = Context::new().unwrap();
+
+        duk.compile_string("(function () { throw 'thrown'; })", Compile::EVAL)
+            .unwrap();
+        duk.call(0).unwrap();
+        let err = duk.call(0).err().unwrap();
+        assert!(err.to_string().contains("thrown"), "{}", err);
+
+        duk.compile_string("(function () { throw 1; })", Compile::EVAL)
+            .unwrap();
+        duk.call(0).unwrap();
+        assert!(duk.call(0).is_err());
+        assert_eq!(duk.top(), 0);
+    }
+
+    #[test]
+    fn context_equality() {
+        let duk = Context::new().unwrap();
+
+        duk.push_int(1).push_string("1");
+        assert!(duk.equals(-2, -1));
+        assert!(!duk.strict_equals(-2, -1));
+        duk.pop(2);
+
+        duk.push_number(0.0).push_number(-0.0);
+        assert!(duk.strict_equals(-2, -1));
+        assert!(!duk.same_value(-2, -1));
+        duk.pop(2);
+
+        duk.eval("[]").unwrap().get_prototype(-1);
+        duk.get_global_string("Array").get_prop_string(-1, "prototype");
+        assert!(duk.strict_equals(-3, -1));
+    }
+
+    #[cfg(feature = "debugger")]
+    #[test]
+    fn context_attach_debugger() {
+        use super::super::debugger::pipe;
+        use std::io::{BufRead, BufReader, Write};
+        use std::thread;
+
+        let (transport, client) = pipe();
+        let handle = thread::spawn(move || {
+            let mut client = BufReader::new(client);
+            // Version identification: "<protocol version> <duktape version> ..."
+            let mut version = String::new();
+            client.read_line(&mut version).unwrap();
+            // Resume request: REQ, command 0x13, EOM
+            client.get_mut().write_all(&[0x01, 0x80 + 0x13, 0x00]).unwrap();
+            version
+        });
+
+        let duk = Context::new().unwrap();
+        duk.attach_debugger(transport);
+        duk.eval("var answer = 42;").unwrap();
+        duk.detach_debugger();
+
+        let version = handle.join().unwrap();
+        assert!(version.starts_with("2 20300 "));
+        let result: i32 = duk.eval("answer").unwrap().getp().unwrap();
+        assert_eq!(result, 42);
+    }
+
+    #[cfg(feature = "profiler")]
+    #[test]
+    fn context_profiling() {
+        use std::time::Duration;
+
+        let duk = Context::new().unwrap();
+        duk.start_profiling_with_interval(Duration::from_micros(100));
+        duk.compile_string_filename(
+            "function fib(n) {\n  return n < 2 ? n : fib(n - 1) + fib(n - 2);\n}\nfib(25);",
+            "fib.js",
+            Compile::EVAL,
+        )
+        .unwrap();
+        duk.call(0).unwrap();
+        let profile = duk.stop_profiling();
+
+        assert!(!profile.samples.is_empty());
+        let (hottest, _) = profile.self_times()[0];
+        assert_eq!(hottest.function, "fib");
+        assert_eq!(hottest.file, "fib.js");
+        assert_eq!(hottest.line, 2);
+        assert!(profile.folded().contains("fib (fib.js:2);fib (fib.js:2)"));
+        assert!(profile.chrome().starts_with("{\"nodes\":[{\"id\":1,"));
+
+        // Nothing is sampled after stopping
+        assert!(duk.stop_profiling().samples.is_empty());
+    }
+
+    #[cfg(feature = "profiler")]
+    #[test]
+    fn context_profiling_threads() {
+        use std::time::Duration;
+
+        let duk = Context::new().unwrap();
+        duk.start_profiling_with_interval(Duration::from_micros(100));
+        duk.compile_string_filename(
+            "function spin() {\n  for (var i = 0, s = 0; i < 300000; i++) s += i;\n  return s;\n}\n\
+             var thread = new Duktape.Thread(function run() { var s = spin(); return s; });\n\
+             Duktape.Thread.resume(thread);",
+            "thread.js",
+            Compile::EVAL,
+        )
+        .unwrap();
+        duk.call(0).unwrap();
+        let profile = duk.stop_profiling();
+
+        // Samples are taken of the coroutine running, not of the thread resuming it
+        let (hottest, _) = profile.self_times()[0];
+        assert_eq!(hottest.function, "spin");
+        assert_eq!(hottest.line, 2);
+        assert!(profile.folded().contains("run (thread.js:5);spin (thread.js:2)"), "{}", profile.folded());
+    }
+
+    #[cfg(feature = "coverage")]
+    #[test]
+    fn context_coverage() {
+        let duk = Context::new().unwrap();
+        duk.start_coverage();
+        // As a module loader would, for a script whose line 2 ran twice and line 3 never
+        duk.push_global_stash()
+            .get_prop_string(-1, super::coverage::STASH_KEY)
+            .eval("({ '2': 2, '3': 0 })")
+            .unwrap()
+            .put_prop_string(-2, "cover.js")
+            .pop(2);
+        let coverage = duk.stop_coverage();
+
+        let file = &coverage.files["cover.js"];
+        assert_eq!(file.lines[&2], 2);
+        assert_eq!(file.uncovered(), vec![3]);
+        assert!(coverage.lcov().contains("SF:cover.js\nDA:2,2\nDA:3,0\n"));
+
+        // Nothing is recorded after stopping
+        assert!(duk.stop_coverage().files.is_empty());
+    }
+
+    #[test]
+    fn context_push_node_buffer() {
+        let duk = Context::new().unwrap();
+
+        duk.push_global_object()
+            .push_node_buffer(b"Hello")
+            .put_prop_string(-2, "buf")
+            .pop(1);
+
+        let result: String = duk
+            .eval("Buffer.isBuffer(buf) + ':' + buf.toString()")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "true:Hello");
+    }
+
+    #[test]
+    fn context_permission_error() {
+        let duk = Context::new().unwrap();
+        duk.push_global_object()
+            .push_function(|_ctx: &Context| Err(Error::permission_err("/etc")))
+            .put_prop_string(-2, "denied")
+            .pop(1);
+
+        duk.eval("try { denied() } catch (e) { e.name + ': ' + e.message }")
+            .unwrap();
+        assert_eq!(duk.get_string(-1).unwrap(), "PermissionDenied: /etc");
+    }
+
+    #[test]
+    fn context_reachable_stats() {
+        let duk = Context::new().unwrap();
+        duk.gc(true);
+        let before = duk.reachable_stats();
+        assert!(before.objects > 0 && before.strings > 0);
+
+        duk.eval("var leak = []; for (var i = 0; i < 100; i++) leak.push({ id: 'item' + i });")
+            .unwrap()
+            .pop(1);
+        let after = duk.reachable_stats();
+        assert_eq!(after.objects, before.objects + 101);
+        assert!(after.strings >= before.strings + 100);
+        assert!(after.bytes() > before.bytes());
+
+        duk.eval("leak").unwrap();
+        let info = duk.inspect_value(-1);
+        assert_eq!(info.class_name(), Some("Array"));
+        assert!(info.array_size >= 100);
+        assert_eq!(info.refcount, Some(2));
+        duk.pop(1);
+
+        duk.eval("leak = null").unwrap().pop(1);
+        duk.gc(false);
+        assert_eq!(duk.reachable_stats().objects, before.objects);
+
+        // A value referenced many times is counted once, so only its refcount grows
+        duk.eval("var shared = {}, many = [shared]").unwrap().pop(1);
+        duk.gc(false);
+        let before = duk.reachable_stats();
+        duk.eval("many.push(shared, shared)").unwrap().pop(1);
+        duk.gc(false);
+        let after = duk.reachable_stats();
+        assert_eq!(after.objects, before.objects);
+        assert_eq!(after.refcounts, before.refcounts + 2);
+
+        duk.push_int(1);
+        assert_eq!(duk.inspect_value(-1).pointer, None);
+        duk.pop(1);
+    }
+
 }
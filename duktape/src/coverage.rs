@@ -0,0 +1,133 @@
+//! Line coverage of scripts.
+//!
+//! `Context::start_coverage` adds a table of hits to the global stash, by file name and line.
+//! Module loaders instrument the scripts they load while it exists, adding the executable lines
+//! of a script to the table and counting the runs of each line in it. The `duktape_modules` loader
+//! does so with its `coverage` feature enabled.
+use super::context::{Context, Enumerate};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Global stash property with the hits of the covered files, e.g. `{"/src/a.js": {"1": 2}}`
+pub const STASH_KEY: &'static str = "coverage";
+
+/// The executable lines of a file and how many times they were run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileCoverage {
+    pub lines: BTreeMap<u32, u64>,
+}
+
+impl FileCoverage {
+    pub fn lines_found(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|hits| **hits > 0).count()
+    }
+
+    pub fn uncovered(&self) -> Vec<u32> {
+        self.lines
+            .iter()
+            .filter(|(_, hits)| **hits == 0)
+            .map(|(line, _)| *line)
+            .collect()
+    }
+}
+
+/// The lines run between `Context::start_coverage` and `Context::stop_coverage`, by file name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+impl Coverage {
+    /// Add the hits of `other`, e.g. when each test file is run in a context of its own
+    pub fn merge(&mut self, other: Coverage) {
+        for (file, coverage) in other.files {
+            let lines = &mut self.files.entry(file).or_default().lines;
+            for (line, hits) in coverage.lines {
+                *lines.entry(line).or_insert(0) += hits;
+            }
+        }
+    }
+
+    /// The coverage as an lcov tracefile, the input of genhtml and most coverage services
+    pub fn lcov(&self) -> String {
+        let mut out = String::new();
+        for (file, coverage) in &self.files {
+            writeln!(out, "TN:\nSF:{}", file).unwrap();
+            for (line, hits) in &coverage.lines {
+                writeln!(out, "DA:{},{}", line, hits).unwrap();
+            }
+            writeln!(
+                out,
+                "LF:{}\nLH:{}\nend_of_record",
+                coverage.lines_found(),
+                coverage.lines_hit()
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Add an empty table of hits, replacing the table of a coverage started before
+pub(crate) fn start(ctx: &Context) {
+    ctx.push_global_stash()
+        .push_bare_object()
+        .put_prop_string(-2, STASH_KEY)
+        .pop(1);
+}
+
+/// Read the own properties of the object at the top of the stack
+fn read_hits(ctx: &Context, mut f: impl FnMut(&Context, &str)) {
+    ctx.enumerator(-1, Enumerate::OWN_PROPERTIES_ONLY).unwrap();
+    while ctx.next(-1, true).unwrap() {
+        let key = ctx.get_string(-2).unwrap_or("").to_owned();
+        f(ctx, &key);
+        ctx.pop(2);
+    }
+    ctx.pop(1);
+}
+
+/// Remove the table of hits and return the coverage in it
+pub(crate) fn stop(ctx: &Context) -> Coverage {
+    let mut coverage = Coverage::default();
+    ctx.push_global_stash();
+    if ctx.has_prop_string(-1, STASH_KEY) {
+        ctx.get_prop_string(-1, STASH_KEY);
+        read_hits(ctx, |ctx, file| {
+            let lines = &mut coverage.files.entry(file.to_owned()).or_default().lines;
+            read_hits(ctx, |ctx, line| {
+                if let Ok(line) = line.parse() {
+                    lines.insert(line, ctx.get_number(-1).unwrap_or(0.0) as u64);
+                }
+            });
+        });
+        ctx.pop(1).del_prop_string(-1, STASH_KEY);
+    }
+    ctx.pop(1);
+    coverage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coverage, FileCoverage};
+
+    #[test]
+    fn coverage_lcov() {
+        let mut coverage = Coverage::default();
+        let mut file = FileCoverage::default();
+        file.lines.insert(1, 1);
+        file.lines.insert(2, 0);
+        coverage.files.insert("/src/a.js".to_owned(), file.clone());
+        coverage.merge(coverage.clone());
+
+        assert_eq!(coverage.files["/src/a.js"].uncovered(), vec![2]);
+        assert_eq!(
+            coverage.lcov(),
+            "TN:\nSF:/src/a.js\nDA:1,2\nDA:2,0\nLF:2\nLH:1\nend_of_record\n"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Transports for the Duktape debug protocol.
+//!
+//! A debug client, like Duktape's `duk_debug.js`, talks to the engine through a `Transport`
+//! attached with `Context::attach_debugger`.
+use duktape_sys as duk;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::raw::{c_char, c_void};
+use std::slice;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// The port Duktape's debug client connects to by default
+pub const DEFAULT_PORT: u16 = 9091;
+
+/// A connection to a debug client.
+/// Reads block until at least one byte is available, and return 0 when the client is gone
+pub trait Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+    /// The number of bytes, which can be read without blocking
+    fn peek(&mut self) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(self, buf)
+    }
+
+    fn peek(&mut self) -> io::Result<usize> {
+        let mut buf = [0u8; 1];
+        self.set_nonblocking(true)?;
+        let ret = match TcpStream::peek(self, &mut buf) {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        };
+        self.set_nonblocking(false)?;
+        ret
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// Wait for a debug client to connect on localhost at `port`
+pub fn listen(port: u16) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// One end of an in-process channel, for debug clients running on another thread
+pub struct Pipe {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+/// The read half of a `Pipe`
+pub struct PipeReader {
+    rx: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+/// The write half of a `Pipe`
+#[derive(Clone)]
+pub struct PipeWriter {
+    tx: Sender<Vec<u8>>,
+}
+
+/// Create a connected pair of pipes. One is attached to a context, the other is used by the client
+pub fn pipe() -> (Pipe, Pipe) {
+    let (atx, arx) = mpsc::channel();
+    let (btx, brx) = mpsc::channel();
+    (
+        Pipe {
+            reader: PipeReader::new(brx),
+            writer: PipeWriter { tx: atx },
+        },
+        Pipe {
+            reader: PipeReader::new(arx),
+            writer: PipeWriter { tx: btx },
+        },
+    )
+}
+
+impl Pipe {
+    /// Split the pipe, so reads and writes can happen on different threads
+    pub fn split(self) -> (PipeReader, PipeWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl PipeReader {
+    fn new(rx: Receiver<Vec<u8>>) -> PipeReader {
+        PipeReader {
+            rx,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The number of bytes, which can be read without blocking
+    pub fn available(&mut self) -> usize {
+        while let Ok(data) = self.rx.try_recv() {
+            self.pending.extend(data);
+        }
+        self.pending.len()
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv() {
+                Ok(data) => self.pending = data,
+                // The other end is dropped
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.tx.send(buf.to_vec()) {
+            Ok(_) => Ok(buf.len()),
+            Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn peek(&mut self) -> io::Result<usize> {
+        Ok(self.reader.available())
+    }
+}
+
+pub(crate) type BoxedTransport = Box<dyn Transport>;
+
+unsafe fn transport<'a>(udata: *mut c_void) -> &'a mut BoxedTransport {
+    &mut *(udata as *mut BoxedTransport)
+}
+
+pub(crate) unsafe extern "C" fn read_cb(
+    udata: *mut c_void,
+    buffer: *mut c_char,
+    length: duk::duk_size_t,
+) -> duk::duk_size_t {
+    let buf = slice::from_raw_parts_mut(buffer as *mut u8, length as usize);
+    // Returning 0 detaches the debugger
+    transport(udata).read(buf).unwrap_or(0) as duk::duk_size_t
+}
+
+pub(crate) unsafe extern "C" fn write_cb(
+    udata: *mut c_void,
+    buffer: *const c_char,
+    length: duk::duk_size_t,
+) -> duk::duk_size_t {
+    let buf = slice::from_raw_parts(buffer as *const u8, length as usize);
+    transport(udata).write(buf).unwrap_or(0) as duk::duk_size_t
+}
+
+pub(crate) unsafe extern "C" fn peek_cb(udata: *mut c_void) -> duk::duk_size_t {
+    transport(udata).peek().unwrap_or(0) as duk::duk_size_t
+}
+
+pub(crate) unsafe extern "C" fn write_flush_cb(udata: *mut c_void) {
+    transport(udata).flush().unwrap_or(());
+}
+
+pub(crate) unsafe extern "C" fn detached_cb(_ctx: *mut duk::duk_context, udata: *mut c_void) {
+    drop(Box::from_raw(udata as *mut BoxedTransport));
+}
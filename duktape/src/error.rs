@@ -29,6 +29,11 @@ error_chain!{
             description("Error")
             display("Error: {}", message)
         }
+
+        PermissionDenied(message: String) {
+            description("PermissionDenied")
+            display("Permission denied: {}", message)
+        }
     }
 
     foreign_links {
@@ -51,4 +56,5 @@ impl Error {
     err_impl!(ref_err, ReferenceError);
     err_impl!(eval_err, EvalError);
     err_impl!(err, Error);
+    err_impl!(permission_err, PermissionDenied);
 }
@@ -0,0 +1,276 @@
+//! Memory diagnostics, see `Context::reachable_stats`, `Context::inspect_value` and `Ref::inspect`.
+use super::context::{Context, Enumerate, Idx};
+use super::types::Type;
+use duktape_sys::{self as duk, duk_context};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{CStr, CString};
+
+const CLASSES: [&str; 30] = [
+    "None",
+    "Object",
+    "Array",
+    "Function",
+    "Arguments",
+    "Boolean",
+    "Date",
+    "Error",
+    "JSON",
+    "Math",
+    "Number",
+    "RegExp",
+    "String",
+    "global",
+    "Symbol",
+    "ObjEnv",
+    "DecEnv",
+    "Pointer",
+    "Thread",
+    "ArrayBuffer",
+    "DataView",
+    "Int8Array",
+    "Uint8Array",
+    "Uint8ClampedArray",
+    "Int16Array",
+    "Uint16Array",
+    "Int32Array",
+    "Uint32Array",
+    "Float32Array",
+    "Float64Array",
+];
+
+/// Internals of a value, as reported by `duk_inspect_value`.
+/// Sizes are zero, when they do not apply to the value
+#[derive(Debug, PartialEq)]
+pub struct ValueInfo {
+    pub kind: Type,
+    /// The address of heap allocated values
+    pub pointer: Option<usize>,
+    /// The reference count, including the references of the value stack
+    pub refcount: Option<usize>,
+    /// The class number of objects
+    pub class: Option<u32>,
+    /// The size of the heap header, including the data of strings
+    pub header_bytes: usize,
+    /// The size of the property table of objects
+    pub property_bytes: usize,
+    /// The size of the bytecode of script functions
+    pub bytecode_bytes: usize,
+    /// The size of the data of buffers
+    pub data_bytes: usize,
+    /// Slots of the entry part of the property table
+    pub entry_size: usize,
+    /// Used slots of the entry part
+    pub entry_next: usize,
+    /// Slots of the array part
+    pub array_size: usize,
+    /// Slots of the hash part
+    pub hash_size: usize,
+}
+
+impl ValueInfo {
+    pub fn class_name(&self) -> Option<&'static str> {
+        self.class.and_then(|c| CLASSES.get(c as usize).cloned())
+    }
+
+    /// The bytes allocated for the value
+    pub fn size(&self) -> usize {
+        self.header_bytes + self.property_bytes + self.bytecode_bytes + self.data_bytes
+    }
+}
+
+/// Counts of the values reachable from the global object, the stashes and the value stack.
+/// Values only referenced by the internals of the engine, like closure scopes, are not
+/// reachable this way, so the counts are a lower bound of what the heap holds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReachableStats {
+    pub objects: usize,
+    pub object_bytes: usize,
+    pub strings: usize,
+    pub string_bytes: usize,
+    pub buffers: usize,
+    pub buffer_bytes: usize,
+    /// The sum of the reference counts of the counted values
+    pub refcounts: usize,
+    /// Object counts by class name
+    pub classes: BTreeMap<&'static str, usize>,
+}
+
+impl ReachableStats {
+    /// The bytes allocated for the counted values
+    pub fn bytes(&self) -> usize {
+        self.object_bytes + self.string_bytes + self.buffer_bytes
+    }
+}
+
+unsafe fn get_field(ctx: *mut duk_context, name: &str) -> i32 {
+    let name = CString::new(name).unwrap();
+    duk::duk_get_prop_string(ctx, -1, name.as_ptr());
+    let value = duk::duk_get_int_default(ctx, -1, -1);
+    duk::duk_pop(ctx);
+    value
+}
+
+fn size(value: i32) -> usize {
+    if value < 0 {
+        0
+    } else {
+        value as usize
+    }
+}
+
+fn optional(value: i32) -> Option<usize> {
+    if value < 0 {
+        None
+    } else {
+        Some(value as usize)
+    }
+}
+
+pub(crate) fn inspect(ctx: &Context, idx: Idx) -> ValueInfo {
+    let kind = ctx.get_type(idx);
+    let inner = ctx.inner;
+    unsafe {
+        duk::duk_inspect_value(inner, idx);
+        duk::duk_get_prop_string(inner, -1, b"hptr\0".as_ptr() as *const i8);
+        let pointer = duk::duk_get_pointer(inner, -1) as usize;
+        duk::duk_pop(inner);
+
+        let info = ValueInfo {
+            kind,
+            pointer: if pointer == 0 { None } else { Some(pointer) },
+            refcount: optional(get_field(inner, "refc")),
+            class: optional(get_field(inner, "class")).map(|c| c as u32),
+            header_bytes: size(get_field(inner, "hbytes")),
+            property_bytes: size(get_field(inner, "pbytes")),
+            bytecode_bytes: size(get_field(inner, "bcbytes")),
+            data_bytes: size(get_field(inner, "dbytes")),
+            entry_size: size(get_field(inner, "esize")),
+            entry_next: size(get_field(inner, "enext")),
+            array_size: size(get_field(inner, "asize")),
+            hash_size: size(get_field(inner, "hsize")),
+        };
+        duk::duk_pop(inner);
+        info
+    }
+}
+
+/// Move the value at the top of the stack to the array at `work`, if it is heap allocated
+/// and was not queued before, otherwise pop it
+unsafe fn queue(ctx: *mut duk_context, work: Idx, visited: &mut HashSet<usize>) {
+    match duk::duk_get_type(ctx, -1) as u32 {
+        duk::DUK_TYPE_STRING | duk::DUK_TYPE_OBJECT | duk::DUK_TYPE_BUFFER
+            if visited.insert(duk::duk_get_heapptr(ctx, -1) as usize) =>
+        {
+            let len = duk::duk_get_length(ctx, work);
+            duk::duk_put_prop_index(ctx, work, len as u32);
+        }
+        _ => duk::duk_pop(ctx),
+    }
+}
+
+/// Queue the heap allocated property `name` of the object at the top of the stack
+unsafe fn queue_prop(ctx: *mut duk_context, work: Idx, visited: &mut HashSet<usize>, name: &[u8]) {
+    duk::duk_get_prop_lstring(ctx, -1, name.as_ptr() as *const i8, name.len() as duk::duk_size_t);
+    queue(ctx, work, visited);
+}
+
+/// Walk the values reachable from the roots, following own properties, accessors and prototypes.
+/// Values only referenced by the internals of the engine, like closure scopes, are not counted
+pub(crate) fn stats(ctx: &Context) -> ReachableStats {
+    let inner = ctx.inner;
+    let mut stats = ReachableStats::default();
+    let mut visited = HashSet::new();
+
+    unsafe {
+        let roots = duk::duk_get_top(inner);
+        duk::duk_require_stack(inner, 8);
+        let work = duk::duk_push_array(inner);
+        visited.insert(duk::duk_get_heapptr(inner, work) as usize);
+
+        for idx in 0..roots {
+            duk::duk_dup(inner, idx);
+            queue(inner, work, &mut visited);
+        }
+        duk::duk_push_global_object(inner);
+        queue(inner, work, &mut visited);
+        duk::duk_push_global_stash(inner);
+        queue(inner, work, &mut visited);
+        duk::duk_push_heap_stash(inner);
+        queue(inner, work, &mut visited);
+
+        loop {
+            let len = duk::duk_get_length(inner, work);
+            if len == 0 {
+                break;
+            }
+            duk::duk_get_prop_index(inner, work, (len - 1) as u32);
+            duk::duk_set_length(inner, work, len - 1);
+
+            let info = inspect(ctx, -1);
+            // Leave out the reference of the value stack
+            stats.refcounts += info.refcount.map_or(0, |r| r.saturating_sub(1));
+
+            match duk::duk_get_type(inner, -1) as u32 {
+                duk::DUK_TYPE_STRING => {
+                    stats.strings += 1;
+                    stats.string_bytes += info.size();
+                }
+                duk::DUK_TYPE_BUFFER => {
+                    stats.buffers += 1;
+                    stats.buffer_bytes += info.size();
+                }
+                duk::DUK_TYPE_OBJECT => {
+                    stats.objects += 1;
+                    stats.object_bytes += info.size();
+                    if let Some(name) = info.class_name() {
+                        *stats.classes.entry(name).or_insert(0) += 1;
+                    }
+
+                    duk::duk_get_prototype(inner, -1);
+                    queue(inner, work, &mut visited);
+
+                    // Proxies are enumerated as they are, so no traps are called
+                    let flags = Enumerate::INCLUDE_NONENUMERABLE
+                        | Enumerate::INCLUDE_HIDDEN
+                        | Enumerate::INCLUDE_SYMBOLS
+                        | Enumerate::OWN_PROPERTIES_ONLY
+                        | Enumerate::NO_PROXY_BEHAVIOR;
+                    duk::duk_enum(inner, -1, flags.bits());
+                    while duk::duk_next(inner, -1, 0) == 1 {
+                        // Keys of the array part are not strings
+                        let key = duk::duk_get_string(inner, -1);
+                        let in_array = info.array_size > 0
+                            && !key.is_null()
+                            && CStr::from_ptr(key)
+                                .to_str()
+                                .ok()
+                                .and_then(|s| s.parse::<usize>().ok())
+                                .map_or(false, |i| i < info.array_size);
+
+                        // Read descriptors instead of values, so getters are not called
+                        duk::duk_dup(inner, -1);
+                        duk::duk_get_prop_desc(inner, -4, 0);
+                        if duk::duk_is_object(inner, -1) == 1 {
+                            queue_prop(inner, work, &mut visited, b"value");
+                            queue_prop(inner, work, &mut visited, b"get");
+                            queue_prop(inner, work, &mut visited, b"set");
+                        }
+                        duk::duk_pop(inner);
+
+                        if in_array {
+                            duk::duk_pop(inner);
+                        } else {
+                            queue(inner, work, &mut visited);
+                        }
+                    }
+                    duk::duk_pop(inner);
+                }
+                _ => {}
+            }
+            duk::duk_pop(inner);
+        }
+        duk::duk_pop(inner);
+    }
+
+    stats
+}
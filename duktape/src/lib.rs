@@ -9,9 +9,16 @@ extern crate value;
 mod callable;
 pub mod class;
 mod context;
+#[cfg(feature = "coverage")]
+pub mod coverage;
+#[cfg(feature = "debugger")]
+pub mod debugger;
 pub mod error;
+pub mod heap;
 mod macros;
 mod privates;
+#[cfg(feature = "profiler")]
+pub mod profiler;
 pub mod types;
 
 pub use self::callable::Callable;
@@ -1,5 +1,6 @@
+use super::error::{Error, ErrorKind};
 use duktape_sys::{self as duk, duk_context};
-use std::ffi::c_void;
+use std::ffi::{c_void, CString};
 use typemap::TypeMap;
 static REF_KEY: &'static [u8] = b"refs";
 static DATA_KEY: &'static [u8] = b"data";
@@ -146,3 +147,36 @@ pub unsafe fn unref(ctx: *mut duk_context, refer: u32) {
 
     duk::duk_pop(ctx);
 }
+
+/// Throw `err` as a js error.
+/// Permission errors are thrown with the name `PermissionDenied`,
+/// so scripts can tell them apart from other errors.
+pub unsafe fn throw_error(ctx: *mut duk_context, err: &Error) {
+    match err.kind() {
+        ErrorKind::PermissionDenied(msg) => {
+            let msg = CString::new(msg.as_str()).unwrap_or_default();
+            duk::duk_push_error_object_raw(
+                ctx,
+                duk::DUK_ERR_ERROR as i32,
+                "\0".as_ptr() as *const i8,
+                0,
+                "%s\0".as_ptr() as *const i8,
+                msg.as_ptr(),
+            );
+            duk::duk_push_lstring(ctx, "PermissionDenied".as_ptr() as *const i8, 16);
+            duk::duk_put_prop_lstring(ctx, -2, "name".as_ptr() as *const i8, 4);
+            duk::duk_throw_raw(ctx);
+        }
+        _ => {
+            let msg = CString::new(format!("{}", err.0)).unwrap_or_default();
+            duk::duk_error_raw(
+                ctx,
+                duk::DUK_ERR_ERROR as i32,
+                "\0".as_ptr() as *const i8,
+                0,
+                "%s\0".as_ptr() as *const i8,
+                msg.as_ptr(),
+            );
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! A sampling profiler for scripts.
+//!
+//! Contexts created with `Context::new` sample the call stack from the interrupt handler
+//! of the bytecode executor, while profiling with `Context::start_profiling`.
+//! Time spent in native functions is attributed to the script calling them.
+use duktape_sys::{self as duk, duk_context};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::os::raw::c_void;
+use std::slice;
+use std::time::{Duration, Instant};
+
+/// The time between samples
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Frames deeper in the call stack are left out of samples
+const MAX_DEPTH: i32 = 256;
+
+/// A function in a sampled call stack, at the line it was executing
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Frame {
+    pub function: String,
+    pub file: String,
+    pub line: u32,
+}
+
+impl Frame {
+    fn label(&self) -> String {
+        let function = if self.function.is_empty() {
+            "(anonymous)"
+        } else {
+            &self.function
+        };
+        if self.file.is_empty() {
+            function.to_owned()
+        } else {
+            format!("{} ({}:{})", function, self.file, self.line)
+        }
+    }
+}
+
+/// A sampled call stack
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Indices of `Profile::frames`, outermost first
+    pub stack: Vec<usize>,
+    /// The time since the previous sample
+    pub elapsed: Duration,
+}
+
+/// The samples collected between `Context::start_profiling` and `Context::stop_profiling`
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub frames: Vec<Frame>,
+    pub samples: Vec<Sample>,
+    pub duration: Duration,
+    indices: HashMap<Frame, usize>,
+}
+
+impl Profile {
+    fn frame(&mut self, frame: Frame) -> usize {
+        if let Some(index) = self.indices.get(&frame) {
+            return *index;
+        }
+        self.frames.push(frame.clone());
+        self.indices.insert(frame, self.frames.len() - 1);
+        self.frames.len() - 1
+    }
+
+    /// The time spent in each frame, not counting the functions it calls, longest first
+    pub fn self_times(&self) -> Vec<(&Frame, Duration)> {
+        let mut times = vec![Duration::default(); self.frames.len()];
+        for sample in &self.samples {
+            if let Some(leaf) = sample.stack.last() {
+                times[*leaf] += sample.elapsed;
+            }
+        }
+        let mut times: Vec<(&Frame, Duration)> = self
+            .frames
+            .iter()
+            .zip(times)
+            .filter(|(_, time)| *time > Duration::default())
+            .collect();
+        times.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        times
+    }
+
+    /// Collapsed stacks with their sample counts, the input of flamegraph tools
+    pub fn folded(&self) -> String {
+        let mut counts: HashMap<&[usize], usize> = HashMap::new();
+        for sample in &self.samples {
+            *counts.entry(&sample.stack).or_insert(0) += 1;
+        }
+
+        let mut lines: Vec<String> = counts
+            .into_iter()
+            .map(|(stack, count)| {
+                let labels: Vec<String> = stack
+                    .iter()
+                    .map(|i| self.frames[*i].label().replace(';', ":"))
+                    .collect();
+                format!("{} {}", labels.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+
+    /// The profile in the `.cpuprofile` format of the Chrome developer tools
+    pub fn chrome(&self) -> String {
+        // Nodes of the call tree, with the root at index 0
+        let mut nodes: Vec<(Option<usize>, Vec<usize>, usize)> = vec![(None, Vec::new(), 0)];
+        let mut children: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut ids = Vec::with_capacity(self.samples.len());
+
+        for sample in &self.samples {
+            let mut node = 0;
+            for frame in &sample.stack {
+                node = match children.get(&(node, *frame)) {
+                    Some(child) => *child,
+                    None => {
+                        nodes.push((Some(*frame), Vec::new(), 0));
+                        let child = nodes.len() - 1;
+                        nodes[node].1.push(child);
+                        children.insert((node, *frame), child);
+                        child
+                    }
+                };
+            }
+            nodes[node].2 += 1;
+            ids.push(node + 1);
+        }
+
+        let mut out = String::from("{\"nodes\":[");
+        for (i, (frame, children, hits)) in nodes.iter().enumerate() {
+            let (function, file, line) = match frame {
+                Some(frame) => {
+                    let frame = &self.frames[*frame];
+                    let function = if frame.function.is_empty() {
+                        "(anonymous)"
+                    } else {
+                        &frame.function
+                    };
+                    (function, frame.file.as_str(), frame.line as i64 - 1)
+                }
+                None => ("(root)", "", -1),
+            };
+            let children: Vec<String> = children.iter().map(|c| (c + 1).to_string()).collect();
+            if i > 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                "{{\"id\":{},\"callFrame\":{{\"functionName\":{},\"scriptId\":\"0\",\"url\":{},\"lineNumber\":{},\"columnNumber\":-1}},\"hitCount\":{},\"children\":[{}]}}",
+                i + 1,
+                quote(function),
+                quote(file),
+                line,
+                hits,
+                children.join(",")
+            )
+            .unwrap();
+        }
+
+        let samples: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let deltas: Vec<String> = self
+            .samples
+            .iter()
+            .map(|s| micros(s.elapsed).to_string())
+            .collect();
+        write!(
+            out,
+            "],\"startTime\":0,\"endTime\":{},\"samples\":[{}],\"timeDeltas\":[{}]}}",
+            micros(self.duration),
+            samples.join(","),
+            deltas.join(",")
+        )
+        .unwrap();
+        out
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) struct Sampler {
+    interval: Duration,
+    started: Instant,
+    last: Instant,
+    profile: Profile,
+}
+
+impl Sampler {
+    pub(crate) fn new(interval: Duration) -> Sampler {
+        let now = Instant::now();
+        Sampler {
+            interval,
+            started: now,
+            last: now,
+            profile: Profile::default(),
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Profile {
+        self.profile.duration = self.started.elapsed();
+        self.profile
+    }
+
+    unsafe fn string_prop(ctx: *mut duk_context, key: &[u8]) -> String {
+        duk::duk_get_prop_lstring(ctx, -1, key.as_ptr() as *const i8, key.len() as duk::duk_size_t);
+        let mut len = 0;
+        let ptr = duk::duk_get_lstring(ctx, -1, &mut len);
+        let value = if ptr.is_null() {
+            String::new()
+        } else {
+            String::from_utf8_lossy(slice::from_raw_parts(ptr as *const u8, len as usize)).into_owned()
+        };
+        duk::duk_pop(ctx);
+        value
+    }
+
+    fn is_due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    /// Sample the call stack of `ctx`, the thread running when the executor was interrupted
+    unsafe fn sample(&mut self, ctx: *mut duk_context) {
+        let now = Instant::now();
+        let mut stack = Vec::new();
+        let mut level = -1;
+        while level >= -MAX_DEPTH && duk::duk_check_stack(ctx, 3) == 1 {
+            duk::duk_inspect_callstack_entry(ctx, level);
+            if duk::duk_is_undefined(ctx, -1) == 1 {
+                duk::duk_pop(ctx);
+                break;
+            }
+            duk::duk_get_prop_lstring(ctx, -1, "lineNumber".as_ptr() as *const i8, 10);
+            let line = duk::duk_get_uint(ctx, -1);
+            duk::duk_pop(ctx);
+            duk::duk_get_prop_lstring(ctx, -1, "function".as_ptr() as *const i8, 8);
+            let function = Sampler::string_prop(ctx, b"name");
+            let file = Sampler::string_prop(ctx, b"fileName");
+            duk::duk_pop_2(ctx);
+
+            stack.push(self.profile.frame(Frame { function, file, line }));
+            level -= 1;
+        }
+        stack.reverse();
+
+        self.profile.samples.push(Sample {
+            stack,
+            elapsed: now - self.last,
+        });
+        self.last = now;
+    }
+}
+
+/// The heap udata of contexts created with `Context::new`.
+/// Starts with the function, which duk_config.h calls on interrupts with the udata
+/// and the interrupted thread
+#[repr(C)]
+pub(crate) struct Heap {
+    check: unsafe extern "C" fn(*mut c_void, *mut duk_context) -> duk::duk_bool_t,
+    pub(crate) sampler: Option<Sampler>,
+}
+
+unsafe extern "C" fn sample(ctx: *mut duk_context, udata: *mut c_void) -> duk::duk_ret_t {
+    (*(udata as *mut Sampler)).sample(ctx);
+    0
+}
+
+unsafe extern "C" fn check(udata: *mut c_void, thread: *mut duk_context) -> duk::duk_bool_t {
+    let heap = &mut *(udata as *mut Heap);
+    if let Some(ref mut sampler) = heap.sampler {
+        // Errors thrown by the sampling, like running out of memory, stay out of the script
+        if sampler.is_due() {
+            duk::duk_safe_call(thread, Some(sample), sampler as *mut Sampler as *mut c_void, 0, 0);
+        }
+    }
+    // Never time out
+    0
+}
+
+pub(crate) fn heap() -> *mut c_void {
+    Box::into_raw(Box::new(Heap {
+        check,
+        sampler: None,
+    })) as *mut c_void
+}
+
+/// The profiler state of the heap of `ctx`, if it was created by `Context::new`
+pub(crate) unsafe fn get_heap<'a>(ctx: *mut duk_context) -> Option<&'a mut Heap> {
+    let mut funcs: duk::duk_memory_functions = std::mem::zeroed();
+    duk::duk_get_memory_functions(ctx, &mut funcs);
+    (funcs.udata as *mut Heap).as_mut()
+}
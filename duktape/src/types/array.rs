@@ -51,6 +51,13 @@ impl<'a> ToDuktape for Array<'a> {
     }
 }
 
+impl<'a> ToDuktape for &'a Array<'a> {
+    fn to_context(self, _ctx: &Context) -> Result<()> {
+        self.refer.push();
+        Ok(())
+    }
+}
+
 impl<'a> FromDuktape<'a> for Array<'a> {
     fn from_context(ctx: &'a Context, index: i32) -> Result<Self> {
         if !ctx.is_array(index) {
@@ -1,5 +1,6 @@
 use super::super::context::{Context, Idx};
 use super::super::error::Result;
+use super::super::heap::ValueInfo;
 use super::super::privates::{make_ref, push_ref, unref};
 use super::Type;
 use super::{FromDuktape, ToDuktape};
@@ -42,6 +43,17 @@ impl<'a> Ref<'a> {
         self
     }
 
+    /// Sizes, reference count and property table of the value.
+    /// The reference count includes the reference held by `Ref`
+    pub fn inspect(&self) -> ValueInfo {
+        self.push();
+        let mut info = self.ctx.inspect_value(-1);
+        self.ctx.pop(1);
+        // Leave out the push above
+        info.refcount = info.refcount.map(|r| r - 1);
+        info
+    }
+
     pub fn instance_of(&self, reference: &Ref) -> bool {
         self.push();
         reference.push();
@@ -5,11 +5,41 @@ static SOURCE: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/buble.j
 static RUNTIME: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/es6.shim.js"));
 
 use duktape::prelude::*;
-use duktape_modules::{require, CJSContext};
+use duktape_modules::{esm, require, CJSContext, CommonJS, ModuleInfo, SourceMap};
 use std::str;
 
 struct Es6Loader {}
 
+/// Transform `buffer` with buble and register the source map of `file_name`
+fn transform(ctx: &Context, file_name: &str, buffer: &[u8]) -> duktape_modules::error::Result<String> {
+    let buble = ctx.require("es2015")?;
+    // Module syntax is rewritten before buble, which does not transform it
+    let source = esm::transform(str::from_utf8(buffer)?)?;
+
+    // let options: Object = ctx.create()?;
+    // options.set("presets", vec!["es2015"]);
+    // options.set("plugins", vec!["transform-decorators-legacy"]);
+
+    let options: Object = ctx.create()?;
+    let transforms: Object = ctx.create()?;
+    transforms.set("dangerousForOf", true);
+    options
+        .set("transforms", transforms)
+        .set("source", file_name)
+        .set("file", file_name);
+
+    let out = buble.call::<_, _, Object>("transform", (source.as_str(), options))?;
+    let code = out.get::<_, String>("code")?;
+
+    let map = out.get::<_, Object>("map")?.call::<_, _, String>("toString", ())?;
+    ctx.data()?
+        .get::<CommonJS>()
+        .unwrap()
+        .add_source_map(file_name, SourceMap::from_json(&map)?);
+
+    Ok(code)
+}
+
 impl duktape_modules::ModuleLoader for Es6Loader {
     fn load(
         &self,
@@ -17,25 +47,22 @@ impl duktape_modules::ModuleLoader for Es6Loader {
         module: &Object,
         buffer: &[u8],
     ) -> duktape_modules::error::Result<()> {
-        let buble = ctx.require("es2015")?;
-        let source = str::from_utf8(buffer)?;
-
-        // let options: Object = ctx.create()?;
-        // options.set("presets", vec!["es2015"]);
-        // options.set("plugins", vec!["transform-decorators-legacy"]);
-
-        let options: Object = ctx.create()?;
-        let transforms: Object = ctx.create()?;
-        transforms.set("dangerousForOf", true);
-        options.set("transforms", transforms);
-
-        let out = buble.call::<_, _, Object>("transform", (source, options))?;
-        let code = out.get::<_, &str>("code")?;
+        let file_name = module.get::<_, String>("fileName")?;
+        let code = transform(ctx, &file_name, buffer)?;
 
         require::eval_module(&ctx, code.as_bytes(), module)?;
 
         Ok(())
     }
+
+    fn compile(
+        &self,
+        ctx: &Context,
+        info: &ModuleInfo,
+        buffer: &[u8],
+    ) -> duktape_modules::error::Result<Option<String>> {
+        Ok(Some(transform(ctx, &info.id, buffer)?))
+    }
 }
 
 pub fn register(ctx: &Context, builder: &mut duktape_modules::Builder) {
@@ -50,6 +77,7 @@ pub fn register(ctx: &Context, builder: &mut duktape_modules::Builder) {
 
     builder.loader("es6", Box::new(Es6Loader {}));
     builder.loader("js", Box::new(Es6Loader {}));
+    builder.loader("mjs", Box::new(Es6Loader {}));
 }
 
 #[cfg(test)]
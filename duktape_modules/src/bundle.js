@@ -0,0 +1,44 @@
+(function (modules) {
+    var cache = {};
+    var external = typeof require === 'function' ? require : function (id) {
+        throw new Error("could not resolve: '" + id + "'");
+    };
+
+    function load(index) {
+        if (cache[index]) {
+            return cache[index].exports;
+        }
+
+        var definition = modules[index];
+        var fileName = definition[2];
+        var dirName = fileName.indexOf('/') > -1 ? fileName.replace(/\/[^\/]*$/, '') : '.';
+        var module = cache[index] = {
+            id: fileName,
+            fileName: fileName,
+            dirName: dirName,
+            exports: {},
+            loaded: false
+        };
+
+        try {
+            definition[0](module.exports, bind(definition[1]), module, fileName, dirName);
+        } catch (e) {
+            delete cache[index];
+            throw e;
+        }
+
+        module.loaded = true;
+        return module.exports;
+    }
+
+    function bind(dependencies) {
+        return function require(id) {
+            if (Object.prototype.hasOwnProperty.call(dependencies, id)) {
+                return load(dependencies[id]);
+            }
+            return external(id);
+        };
+    }
+
+    return load(0);
+})
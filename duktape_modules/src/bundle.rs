@@ -0,0 +1,310 @@
+//! Bundles a module and the modules it requires into a single script.
+//!
+//! Dependencies are found by scanning for `require('id')` calls with a string literal,
+//! and are resolved and loaded through the resolvers, loaders and transforms of the
+//! registered `CommonJS`. Builtin modules and requires with computed ids are left
+//! to the `require` of the context running the bundle.
+//! The same walk gives the module graph with `graph`, without running the modules.
+use super::commonjs::{cache_key, read_module, resolve_from, CommonJS};
+use super::error::{ErrorKind, Result};
+use super::esm;
+use super::internal;
+use super::types::{ModuleInfo, ModuleRecord};
+use duktape::prelude::*;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str;
+
+static RUNTIME: &'static str = include_str!("bundle.js");
+
+struct Entry {
+    protocol: String,
+    id: String,
+    loader: String,
+    /// Size of the source in bytes
+    size: usize,
+    body: String,
+    /// The required ids in the order they are first required, with the index
+    /// of the entry they resolve to or `None` for builtin modules
+    requires: Vec<(String, Option<usize>)>,
+}
+
+/// Compile the module `id` to a javascript module body, returning it with the size of the source
+fn compile(ctx: &Context, common: &CommonJS, protocol: &str, id: &str) -> Result<(String, usize)> {
+    let ext = Path::new(id)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    let loader = match common.loaders.iter().find(|l| l.extension == ext) {
+        Some(loader) => loader,
+        None => return Err(ErrorKind::Resolve(format!("no loader for: {}", id)).into()),
+    };
+
+    let info = ModuleInfo {
+        id: id.to_owned(),
+        protocol: protocol.to_owned(),
+    };
+    let content = read_module(ctx, protocol, id, common)?;
+    let content = common.transform(content, &info)?;
+
+    if let Some(body) = loader.loader.compile(ctx, &info, &content)? {
+        return Ok((body, content.len()));
+    }
+
+    let module = internal::push_module_object(ctx, id, false)?;
+    loader.loader.load(ctx, &module, &content)?;
+    let json = ctx
+        .get_global_string("JSON")
+        .getp::<Object>()?
+        .call::<_, _, String>("stringify", (module.get::<_, Ref>("exports")?, ()))?;
+    Ok((format!("module.exports = {};", json), content.len()))
+}
+
+/// The file name of the module in the bundle, relative to the directory of the entry
+fn display_name(root: &Path, id: &str) -> String {
+    match Path::new(id).strip_prefix(root) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => id.to_owned(),
+    }
+}
+
+/// Compile the module `entry`, a path or an url, and the modules it requires, entry first.
+/// Returns them with the directory of the entry
+fn walk(ctx: &Context, entry: &str) -> Result<(PathBuf, Vec<Entry>)> {
+    let common = ctx.data()?.get::<CommonJS>().unwrap();
+    let builtins = common.modules();
+
+    let entry = if entry.contains("://") {
+        entry.to_owned()
+    } else {
+        env::current_dir()?.join(entry).to_string_lossy().into_owned()
+    };
+    let (protocol, id) = resolve_from(ctx, &entry, "", "file", common)?;
+    let root = match Path::new(&id).parent() {
+        Some(root) => root.to_path_buf(),
+        None => PathBuf::from("/"),
+    };
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    indices.insert(cache_key(&protocol, &id), 0);
+    queue.push_back((protocol, id));
+
+    while let Some((protocol, id)) = queue.pop_front() {
+        let (body, size) = compile(ctx, common, &protocol, &id)?;
+
+        let mut requires = Vec::new();
+        for request in esm::requires(&body)? {
+            let aliased = common.alias(&request).into_owned();
+            if builtins.contains(&aliased) {
+                requires.push((aliased, None));
+                continue;
+            }
+
+            let (dep_protocol, dep_id) = resolve_from(ctx, &aliased, &id, &protocol, common)?;
+            let next = indices.len();
+            let index = *indices
+                .entry(cache_key(&dep_protocol, &dep_id))
+                .or_insert_with(|| {
+                    queue.push_back((dep_protocol, dep_id));
+                    next
+                });
+            requires.push((request, Some(index)));
+        }
+
+        let loader = Path::new(&id)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_owned();
+        entries.push(Entry {
+            protocol,
+            id,
+            loader,
+            size,
+            body,
+            requires,
+        });
+    }
+
+    Ok((root, entries))
+}
+
+/// Bundle the module `entry`, a path or an url, and the modules it requires into a single script.
+/// Evaluating the script returns the exports of `entry`
+pub fn bundle(ctx: &Context, entry: &str) -> Result<String> {
+    let (root, entries) = walk(ctx, entry)?;
+
+    let mut out = String::from(RUNTIME.trim_end());
+    out.push_str("([\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let dependencies: BTreeMap<&str, usize> = entry
+            .requires
+            .iter()
+            .filter_map(|(request, index)| index.map(|index| (request.as_str(), index)))
+            .collect();
+        out.push_str("[function (exports, require, module, __filename, __dirname) {\n");
+        out.push_str(&entry.body);
+        out.push_str("\n}, ");
+        out.push_str(&serde_json::to_string(&dependencies).unwrap());
+        out.push_str(", ");
+        out.push_str(&serde_json::to_string(&display_name(&root, &entry.id)).unwrap());
+        out.push_str(if i + 1 < entries.len() { "],\n" } else { "]\n" });
+    }
+    out.push_str("]);\n");
+
+    Ok(out)
+}
+
+/// The graph of the module `entry`, a path or an url, and the modules it requires, entry first.
+/// Modules are found like `bundle` does, so they are read and transformed but not evaluated,
+/// and load times are left at zero
+pub fn graph(ctx: &Context, entry: &str) -> Result<Vec<ModuleRecord>> {
+    let (_, entries) = walk(ctx, entry)?;
+
+    let mut records: Vec<ModuleRecord> = entries
+        .iter()
+        .map(|entry| ModuleRecord {
+            id: cache_key(&entry.protocol, &entry.id),
+            protocol: entry.protocol.clone(),
+            loader: entry.loader.clone(),
+            parents: Vec::new(),
+            dependencies: Vec::new(),
+            load_time: Default::default(),
+            size: entry.size,
+        })
+        .collect();
+
+    let mut builtins: Vec<ModuleRecord> = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let parent = records[i].id.clone();
+        for (request, index) in &entry.requires {
+            let dependency = match index {
+                Some(index) => &mut records[*index],
+                None => {
+                    let found = builtins.iter().position(|b| &b.id == request);
+                    let index = found.unwrap_or_else(|| {
+                        builtins.push(ModuleRecord {
+                            id: request.clone(),
+                            protocol: "builtin".to_owned(),
+                            loader: "builtin".to_owned(),
+                            parents: Vec::new(),
+                            dependencies: Vec::new(),
+                            load_time: Default::default(),
+                            size: 0,
+                        });
+                        builtins.len() - 1
+                    });
+                    &mut builtins[index]
+                }
+            };
+            if !dependency.parents.contains(&parent) {
+                dependency.parents.push(parent.clone());
+            }
+            let id = dependency.id.clone();
+            if !records[i].dependencies.contains(&id) {
+                records[i].dependencies.push(id);
+            }
+        }
+    }
+
+    records.extend(builtins);
+    Ok(records)
+}
+
+/// Compile `script` to Duktape bytecode, which is run with `Context::load_function`
+pub fn bytecode(ctx: &Context, script: &str, file_name: &str) -> Result<Vec<u8>> {
+    ctx.compile_string_filename(script, file_name, Compile::EVAL)?;
+    let bytecode = ctx.dump_function()?.get::<&[u8]>(-1)?.to_vec();
+    ctx.pop(1);
+    Ok(bytecode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::resolvers::MemoryResolver;
+    use super::super::{register, Builder};
+    use super::{bundle, bytecode, graph};
+    use duktape::prelude::*;
+
+    fn context(resolver: MemoryResolver) -> Context {
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder
+            .resolver("mem", Box::new(resolver))
+            .native_module("answer", |_ctx: &Context, exports: &Object| {
+                exports.set("value", 42);
+                Ok(())
+            });
+        register(&ctx, builder).unwrap();
+        ctx
+    }
+
+    fn files() -> MemoryResolver {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add(
+                "/app/main.js",
+                "var lib = require('./lib');
+                module.exports = [lib.name, require('./data.json').n, require('answer').value, __filename].join(',');",
+            )
+            .add("/app/lib/index.js", "exports.name = require('../util.mjs').name;")
+            .add("/app/util.mjs", "export const name = 'lib';")
+            .add("/app/data.json", r#"{"n": 1}"#);
+        resolver
+    }
+
+    #[test]
+    fn bundles() {
+        let script = bundle(&context(files()), "mem:///app/main.js").unwrap();
+
+        // Only the bundle and builtin modules are available when running the bundle
+        let ctx = context(MemoryResolver::new());
+        let result: String = ctx.eval(&script).unwrap().getp().unwrap();
+        assert_eq!(result, "lib,1,42,main.js");
+
+        let bytecode = bytecode(&ctx, &script, "bundle.js").unwrap();
+        let result: String = ctx
+            .push_bytes(&bytecode)
+            .load_function()
+            .unwrap()
+            .call(0)
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "lib,1,42,main.js");
+    }
+
+    #[test]
+    fn graph_without_running() {
+        let mut resolver = files();
+        resolver.add("/app/util.mjs", "export const name = 'lib'; require('./lib'); global.ran = true;");
+        let ctx = context(resolver);
+
+        let records = graph(&ctx, "mem:///app/main.js").unwrap();
+        let ids: Vec<_> = records.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "mem:///app/main.js",
+                "mem:///app/lib/index.js",
+                "mem:///app/data.json",
+                "mem:///app/util.mjs",
+                "answer"
+            ]
+        );
+        assert_eq!(
+            records[0].dependencies,
+            vec!["mem:///app/lib/index.js", "mem:///app/data.json", "answer"]
+        );
+        assert_eq!(records[1].parents, vec!["mem:///app/main.js", "mem:///app/util.mjs"]);
+        assert_eq!(records[4].loader, "builtin");
+        assert_eq!(records[4].parents, vec!["mem:///app/main.js"]);
+        assert_eq!(records[2].size, 8);
+        let ran: String = ctx.eval("typeof ran").unwrap().getp().unwrap();
+        assert_eq!(ran, "undefined");
+    }
+}
@@ -1,17 +1,32 @@
-use super::types::{ModuleLoader, ModuleResolver};
-use super::types::{KEY, MODULE_ID_KEY};
+use super::error as module_error;
+use super::types::{ModuleInfo, ModuleLoader, ModuleRecord, ModuleResolver, NativeModule};
+use super::types::{KEY, MODULE_ID_KEY, PROTOCOL_KEY};
 // use duktape::{error::ErrorKind, error::Result, Callable, Context};
 use super::internal;
+use super::policy::FsPolicy;
+use super::source_map::SourceMap;
+use super::watcher;
 use duktape::prelude::*;
 use duktape::Key;
 use duktape::{error::ErrorKind, error::Result};
 use regex::Regex;
-use std::path::Path;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str;
+use std::time::Instant;
 
 pub struct CommonJS {
     pub(crate) loaders: Vec<Loader>,
     resolvers: Vec<Resolver>,
     modules: Vec<Module>,
+    transforms: Vec<Transform>,
+    aliases: Vec<(String, String)>,
+    source_maps: RefCell<HashMap<String, SourceMap>>,
+    graph: RefCell<Vec<ModuleRecord>>,
 }
 
 impl CommonJS {
@@ -35,6 +50,121 @@ impl CommonJS {
             .map(|m| m.name.clone())
             .collect::<Vec<_>>()
     }
+
+    /// Run the transforms matching the module over `source`
+    pub(crate) fn transform(&self, source: Vec<u8>, info: &ModuleInfo) -> module_error::Result<Vec<u8>> {
+        let mut source = source;
+        for transform in self.transforms.iter().filter(|t| t.pattern.is_match(&info.id)) {
+            let out = (transform.transform)(str::from_utf8(&source)?, info)?;
+            source = out.into_bytes();
+        }
+        Ok(source)
+    }
+
+    /// Map `id` through the aliases. Exact aliases are preferred over prefixes,
+    /// and the longest matching prefix wins
+    pub fn alias<'a>(&self, id: &'a str) -> Cow<'a, str> {
+        if let Some((_, target)) = self.aliases.iter().find(|(from, _)| from == id) {
+            return Cow::Owned(target.clone());
+        }
+
+        let prefix = self
+            .aliases
+            .iter()
+            .filter(|(from, _)| from.ends_with('/') && id.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len());
+
+        match prefix {
+            Some((from, target)) => Cow::Owned(format!("{}{}", target, &id[from.len()..])),
+            None => Cow::Borrowed(id),
+        }
+    }
+
+    /// Register the source map of the module `file`,
+    /// errors thrown from the module are remapped to the original source
+    pub fn add_source_map<T: Into<String>>(&self, file: T, map: SourceMap) {
+        self.source_maps.borrow_mut().insert(file.into(), map);
+    }
+
+    pub(crate) fn has_source_maps(&self) -> bool {
+        !self.source_maps.borrow().is_empty()
+    }
+
+    /// Find the original source and line of `line` in the generated `file`
+    pub fn original_position(&self, file: &str, line: u32) -> Option<(String, u32)> {
+        let maps = self.source_maps.borrow();
+        let (source, line) = maps.get(file)?.lookup(line, 0)?;
+        // Sources are relative to the generated file
+        let source = match Path::new(file).parent() {
+            Some(dir) if Path::new(source).is_relative() => dir.join(source),
+            _ => Path::new(source).to_path_buf(),
+        };
+        Some((source.to_string_lossy().into_owned(), line))
+    }
+
+    /// Remove the module `id` from the cache, so the next `require` loads it again.
+    /// `id` is the key given by `cache_key`. Returns false if the module was not loaded
+    pub fn invalidate<T: AsRef<str>>(ctx: &Context, id: T) -> Result<bool> {
+        let mut cache = cache_object(ctx)?;
+        if !cache.has(id.as_ref()) {
+            return Ok(false);
+        }
+        cache.del(id.as_ref());
+        if let Some(common) = ctx.data()?.get::<CommonJS>() {
+            let mut graph = common.graph.borrow_mut();
+            graph.retain(|m| m.id != id.as_ref());
+            for module in graph.iter_mut() {
+                module.parents.retain(|p| p != id.as_ref());
+                module.dependencies.retain(|d| d != id.as_ref());
+            }
+        }
+        Ok(true)
+    }
+
+    /// The loaded modules, in the order they were loaded
+    pub fn graph(&self) -> Vec<ModuleRecord> {
+        self.graph.borrow().clone()
+    }
+
+    /// Record the module keyed `id`, before it is evaluated
+    pub(crate) fn record(&self, id: &str, protocol: &str, loader: &str, size: usize) {
+        let mut graph = self.graph.borrow_mut();
+        graph.retain(|m| m.id != id);
+        graph.push(ModuleRecord {
+            id: id.to_owned(),
+            protocol: protocol.to_owned(),
+            loader: loader.to_owned(),
+            parents: Vec::new(),
+            dependencies: Vec::new(),
+            load_time: Default::default(),
+            size,
+        });
+    }
+
+    /// Record the time the module `id` took to load
+    pub(crate) fn loaded(&self, id: &str, started: Instant) {
+        if let Some(module) = self.graph.borrow_mut().iter_mut().find(|m| m.id == id) {
+            module.load_time = started.elapsed();
+        }
+    }
+
+    /// Record that `parent` requires `id`
+    pub(crate) fn depend(&self, parent: &str, id: &str) {
+        for module in self.graph.borrow_mut().iter_mut() {
+            if module.id == parent && !module.dependencies.iter().any(|d| d == id) {
+                module.dependencies.push(id.to_owned());
+            } else if module.id == id && !module.parents.iter().any(|p| p == parent) {
+                module.parents.push(parent.to_owned());
+            }
+        }
+    }
+
+    /// The ids of the loaded modules
+    pub fn cached(ctx: &Context) -> Result<Vec<String>> {
+        let cache = cache_object(ctx)?;
+        let keys = cache.keys();
+        Ok(keys.iter().map(|k| k.to_string()).collect())
+    }
 }
 
 impl Drop for CommonJS {
@@ -45,10 +175,24 @@ impl Key for CommonJS {
     type Value = Self;
 }
 
+/// The key of the module `id` resolved through `protocol`, in `require.cache` and the graph.
+/// Files and builtins are keyed by their id like in node, other protocols by `protocol://id`
+/// so modules of different resolvers never collide
+pub fn cache_key(protocol: &str, id: &str) -> String {
+    match protocol {
+        "file" | "builtin" => id.to_owned(),
+        _ => format!("{}://{}", protocol, id),
+    }
+}
+
+/// Protocols of modules loaded over the network
+static REMOTE_PROTOCOLS: &[&str] = &["http", "https"];
+
 lazy_static! {
     static ref PROTOCOL_RE: Regex =
-        Regex::new(r"^([a-zA-Z0-9]+)(?:://)(/?[a-zA-Z0-9\.\-]+(?:/[a-zA-Z0-9\.\-]+)*)$").unwrap();
+        Regex::new(r"^([a-zA-Z0-9]+)(?:://)(/?[a-zA-Z0-9\.\-_@:~+]+(?:/[a-zA-Z0-9\.\-_@:~+]+)*)$").unwrap();
     static ref FILE_RE: Regex = Regex::new(r"^(?:/|\.\.?/)(?:[^/\\0]+(?:/)?)+$").unwrap();
+    static ref PROTOCOL_NAME_RE: Regex = Regex::new(r"^[a-zA-Z0-9]+$").unwrap();
 }
 
 pub(crate) struct Loader {
@@ -66,11 +210,40 @@ struct Module {
     module: Box<dyn Callable>,
 }
 
+/// Adapts a `NativeModule` to the builtin module calling convention
+struct Native<T>(T);
+
+impl<T: NativeModule> Callable for Native<T> {
+    fn call(&self, ctx: &Context) -> Result<i32> {
+        let module: Object = ctx.get(-1)?;
+        let exports: Object = ctx.create()?;
+
+        self.0.exports(ctx, &exports)?;
+        module.set("exports", exports);
+
+        if let Some(source) = self.0.source() {
+            if let Err(e) = internal::eval_module(ctx, source, &module) {
+                return Err(ErrorKind::Error(format!("{}", e)).into());
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+struct Transform {
+    pattern: Regex,
+    transform: Box<dyn Fn(&str, &ModuleInfo) -> module_error::Result<String>>,
+}
+
 pub struct Builder {
     loaders: Vec<Loader>,
     resolvers: Vec<Resolver>,
     modules: Vec<Module>,
+    transforms: Vec<Transform>,
+    aliases: Vec<(String, String)>,
     pub(crate) file_loader: bool,
+    pub(crate) policy: FsPolicy,
 }
 
 impl Builder {
@@ -80,7 +253,10 @@ impl Builder {
             loaders: Vec::new(),
             resolvers: Vec::new(),
             modules: Vec::new(),
+            transforms: Vec::new(),
+            aliases: Vec::new(),
             file_loader: true,
+            policy: FsPolicy::new(),
         }
     }
 
@@ -130,64 +306,307 @@ impl Builder {
         self
     }
 
+    /// Add a builtin module implemented by `module`
+    pub fn native_module<T: 'static + NativeModule>(&mut self, id: &str, module: T) -> &mut Self {
+        self.module(id, Native(module))
+    }
+
+    /// Add a transform of the source of modules, whose id matches the regular expression `pattern`.
+    /// Transforms runs in the order they are added, before the loader.
+    ///
+    /// Panics if `pattern` is not a valid regular expression
+    pub fn transform<F>(&mut self, pattern: &str, transform: F) -> &mut Self
+    where
+        F: 'static + Fn(&str, &ModuleInfo) -> module_error::Result<String>,
+    {
+        self.transforms.push(Transform {
+            pattern: Regex::new(pattern).expect("invalid transform pattern"),
+            transform: Box::new(transform),
+        });
+        self
+    }
+
+    /// Map the module id `from` onto `to` before it is resolved.
+    /// Like import maps, an alias ending with `/` maps every id starting with it,
+    /// eg. `@app/` to `/src/` maps `@app/config` onto `/src/config`
+    pub fn alias<T: Into<String>, U: Into<String>>(&mut self, from: T, to: U) -> &mut Self {
+        let from = from.into();
+        self.aliases.retain(|(f, _)| *f != from);
+        self.aliases.push((from, to.into()));
+        self
+    }
+
+    /// Add the aliases of the import map at `path`, a JSON file on the form
+    /// `{"imports": {"@app/config": "./config.js", "@app/": "./src/"}}`.
+    /// Relative targets are resolved from the directory of the import map
+    pub fn import_map<T: AsRef<Path>>(&mut self, path: T) -> module_error::Result<&mut Self> {
+        let path = env::current_dir()?.join(path);
+        let content = fs::read_to_string(&path)?;
+        let dir = path.parent().unwrap_or(Path::new("/"));
+        for (from, to) in parse_import_map(&content, dir)? {
+            self.alias(from, to);
+        }
+        Ok(self)
+    }
+
     pub fn file_loader(&mut self, enable: bool) -> &mut Self {
         self.file_loader = enable;
         self
     }
 
+    /// Restrict the file loader to the paths allowed by `policy`
+    pub fn fs_policy(&mut self, policy: FsPolicy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
+
     // Build
     pub fn build(self) -> CommonJS {
         CommonJS {
             loaders: self.loaders,
             modules: self.modules,
             resolvers: self.resolvers,
+            transforms: self.transforms,
+            aliases: self.aliases,
+            source_maps: RefCell::new(HashMap::new()),
+            graph: RefCell::new(Vec::new()),
         }
     }
 }
 
-pub struct Require;
+fn parse_import_map(json: &str, dir: &Path) -> module_error::Result<Vec<(String, String)>> {
+    let invalid = |message: String| -> module_error::Error {
+        module_error::ErrorKind::Syntax(format!("invalid import map: {}", message)).into()
+    };
 
-impl Require {
-    /// Load a module
-    fn load_module<'a>(&self, id: &str, ctx: &'a Context, repo: &CommonJS) -> Result<Object<'a>> {
-        let caps = PROTOCOL_RE.captures(id).unwrap();
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+    let imports = match value.get("imports").and_then(|i| i.as_object()) {
+        Some(imports) => imports,
+        None => return Err(invalid("expected an 'imports' object".to_owned())),
+    };
 
-        let protocol = caps.get(1).unwrap().as_str();
-        let idr = caps.get(2).unwrap().as_str();
-        let resolver = match repo
-            .resolvers
-            .iter()
-            .find(|m| m.protocol.as_str() == protocol)
-        {
-            Some(resolver) => resolver,
-            None => {
-                return Err(ErrorKind::TypeError(format!(
-                    "could not find resolver for protocol: '{}'",
-                    protocol
-                ))
-                .into());
+    let mut aliases = Vec::new();
+    for (from, to) in imports {
+        let to = match to.as_str() {
+            Some(to) => to,
+            None => return Err(invalid(format!("target of '{}' is not a string", from))),
+        };
+        let to = if to.starts_with("./") || to.starts_with("../") {
+            let mut path = PathBuf::new();
+            for component in dir.join(to).components() {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => {
+                        path.pop();
+                    }
+                    c => path.push(c.as_os_str()),
+                }
             }
+            let mut joined = path.to_string_lossy().into_owned();
+            // Components drop the trailing slash of prefixes
+            if to.ends_with('/') && !joined.ends_with('/') {
+                joined.push('/');
+            }
+            joined
+        } else {
+            to.to_owned()
         };
+        aliases.push((from.clone(), to));
+    }
+    Ok(aliases)
+}
 
-        let o: Object = ctx.push_current_function().getp()?;
-        let parent = o.get::<_, String>(MODULE_ID_KEY)?;
+/// Find the protocol of `id` and resolve it relative to the calling module
+fn resolve_id(ctx: &Context, id: &str, repo: &CommonJS) -> Result<(String, String)> {
+    let current: Object = ctx.push_current_function().getp()?;
 
-        let id = match resolver.resolver.resolve(idr, &parent, &repo.extensions()) {
-            Ok(id) => id,
-            Err(e) => return Err(ErrorKind::TypeError(format!("{}", e)).into()),
+    let protocol = if current.has(PROTOCOL_KEY) {
+        current.get::<_, String>(PROTOCOL_KEY)?
+    } else {
+        "file".to_string()
+    };
+    let parent = current.get::<_, String>(MODULE_ID_KEY)?;
+
+    resolve_from(ctx, id, &parent, &protocol, repo)
+}
+
+/// Find the protocol of `id` and resolve it relative to the module `parent`
+pub(crate) fn resolve_from(
+    ctx: &Context,
+    id: &str,
+    parent: &str,
+    parent_protocol: &str,
+    repo: &CommonJS,
+) -> Result<(String, String)> {
+    let (protocol, idr) = if let Some(caps) = PROTOCOL_RE.captures(id) {
+        // Remote modules may not reach into the local file system
+        if &caps[1] == "file" && REMOTE_PROTOCOLS.contains(&parent_protocol) {
+            return Err(ErrorKind::PermissionDenied(format!("requiring '{}' from a remote module", id)).into());
+        }
+        (caps[1].to_owned(), caps[2].to_owned())
+    } else if FILE_RE.is_match(id) {
+        // Paths resolves through the protocol of the requiring module
+        (parent_protocol.to_owned(), id.to_owned())
+    } else if REMOTE_PROTOCOLS.contains(&parent_protocol) {
+        // The node_modules of remote modules would be looked up on the local file system
+        return Err(ErrorKind::PermissionDenied(format!(
+            "requiring '{}' from a remote module, map it with an import map",
+            id
+        ))
+        .into());
+    } else {
+        // Bare module ids are looked up in node_modules
+        ("file".to_string(), id.to_owned())
+    };
+
+    let mut extensions = repo.extensions();
+    extensions.extend(script_extensions(ctx)?);
+
+    if let Some(resolver) = repo.resolvers.iter().find(|m| m.protocol == protocol) {
+        return match resolver.resolver.resolve(&idr, parent, &extensions) {
+            Ok(id) => Ok((protocol, id)),
+            Err(e) => Err(ErrorKind::TypeError(format!("{}", e)).into()),
         };
+    }
 
-        if self.has_cache(ctx, &id)? {
-            return self.get_cache(ctx, &id);
+    let resolvers = stash_object(ctx, "resolvers")?;
+    if resolvers.has(&protocol) {
+        let array: Array = ctx.create()?;
+        for ext in &extensions {
+            array.push(ext.as_str())?;
         }
+        let id = resolvers
+            .get::<_, Object>(&protocol)?
+            .call::<_, _, String>("resolve", (idr.as_str(), parent, array))?;
+        return Ok((protocol, id));
+    }
 
-        let path = Path::new(&id);
+    Err(ErrorKind::TypeError(format!(
+        "could not find resolver for protocol: '{}'",
+        protocol
+    ))
+    .into())
+}
+
+/// Read the module `id` through the resolver of `protocol`
+pub(crate) fn read_module(ctx: &Context, protocol: &str, id: &str, repo: &CommonJS) -> Result<Vec<u8>> {
+    if let Some(resolver) = repo.resolvers.iter().find(|m| m.protocol == protocol) {
+        return match resolver.resolver.read(id) {
+            Ok(content) => Ok(content),
+            Err(e) => Err(ErrorKind::Error(format!("{}", e)).into()),
+        };
+    }
+
+    let resolver = stash_object(ctx, "resolvers")?.get::<_, Object>(protocol)?;
+    let content = resolver.call::<_, _, Ref>("read", id)?;
+    if content.is(Type::String) {
+        Ok(content.get::<String>()?.into_bytes())
+    } else {
+        Ok(content.get::<&[u8]>()?.to_vec())
+    }
+}
+
+/// The extensions of loaders registered in `require.extensions`
+fn script_extensions(ctx: &Context) -> Result<Vec<String>> {
+    let extensions = stash_object(ctx, "extensions")?;
+    let keys = extensions.keys();
+    Ok(keys
+        .iter()
+        .map(|k| k.to_string().trim_start_matches('.').to_owned())
+        .collect())
+}
+
+fn stash_object<'a>(ctx: &'a Context, name: &str) -> Result<Object<'a>> {
+    ctx.push_global_stash()
+        .getp::<Object>()?
+        .get::<_, Object>(KEY)?
+        .get::<_, Object>(name)
+}
+
+pub(crate) fn cache_object(ctx: &Context) -> Result<Object> {
+    stash_object(ctx, "cache")
+}
+
+/// `require.graph()`: the loaded modules, in the order they were loaded
+fn graph(ctx: &Context) -> Result<i32> {
+    let common = ctx.data()?.get::<CommonJS>().unwrap();
+    let graph: Array = ctx.create()?;
+    for record in common.graph() {
+        let module: Object = ctx.create()?;
+        let load_time = record.load_time.as_secs() as f64 * 1000.0
+            + f64::from(record.load_time.subsec_nanos()) / 1_000_000.0;
+        module
+            .set("id", record.id)
+            .set("protocol", record.protocol)
+            .set("loader", record.loader)
+            .set("parents", record.parents)
+            .set("dependencies", record.dependencies)
+            .set("loadTime", load_time)
+            .set("size", record.size);
+        graph.push(module)?;
+    }
+    ctx.push(graph)?;
+    Ok(1)
+}
+
+/// `require.registerResolver(protocol, { resolve(id, parent, extensions), read(id) })`
+fn register_resolver(ctx: &Context) -> Result<i32> {
+    let protocol: String = ctx.get(0)?;
+    if !ctx.is(Type::Object, 1) {
+        return Err(ErrorKind::TypeError("resolver must be an object".to_string()).into());
+    }
+    let resolver: Object = ctx.get(1)?;
+
+    if !PROTOCOL_NAME_RE.is_match(&protocol) {
+        return Err(ErrorKind::TypeError(format!("invalid protocol: '{}'", protocol)).into());
+    }
+
+    let common = ctx.data()?.get::<CommonJS>().unwrap();
+    if common.resolvers.iter().any(|r| r.protocol == protocol) {
+        return Err(ErrorKind::TypeError(format!(
+            "protocol already registered: '{}'",
+            protocol
+        ))
+        .into());
+    }
+
+    for method in &["resolve", "read"] {
+        if !resolver.has(method) {
+            return Err(ErrorKind::TypeError(format!("resolver has no '{}' method", method)).into());
+        }
+    }
+
+    stash_object(ctx, "resolvers")?.set(protocol, resolver);
+
+    Ok(0)
+}
+
+pub struct Require;
+
+impl Require {
+    /// Load a module
+    fn load_module<'a>(
+        &self,
+        protocol: &str,
+        id: &str,
+        ctx: &'a Context,
+        repo: &CommonJS,
+    ) -> Result<Object<'a>> {
+        let key = cache_key(protocol, id);
+        if self.has_cache(ctx, &key)? {
+            return self.get_cache(ctx, &key);
+        }
+
+        let path = Path::new(id);
 
         let module = match internal::push_module_object(ctx, &path, false) {
             Ok(id) => id,
             Err(e) => return Err(ErrorKind::Error(format!("{}", e)).into()),
         };
 
+        // Relative requires from the module resolves through the same protocol
+        module.set(PROTOCOL_KEY, protocol);
+
         if path.extension().is_none() {
             bail!(ErrorKind::TypeError(format!(
                 "could not infer extension for path {}",
@@ -195,22 +614,62 @@ impl Require {
             )));
         }
 
-        let ext = path.extension().unwrap();
+        let ext = path.extension().unwrap().to_str().unwrap_or("");
+
+        // Loaders registered from javascript takes precedence
+        let handlers = stash_object(ctx, "extensions")?;
+        let handler = format!(".{}", ext);
+        let handler = if handlers.has(&handler) {
+            Some(handlers.get::<_, Function>(&handler)?)
+        } else {
+            None
+        };
+
+        let loader = repo.loaders.iter().find(|m| m.extension.as_str() == ext);
+        if handler.is_none() && loader.is_none() {
+            bail!(ErrorKind::Error(format!("no loader for: {:?}", ext)));
+        }
+
+        let started = Instant::now();
+        if protocol == "file" {
+            watcher::record_mtime(&module, id);
+        }
+        let content = read_module(ctx, protocol, id, repo)?;
 
-        let loader = match repo.loaders.iter().find(|m| m.extension.as_str() == ext) {
-            Some(loader) => loader,
-            None => bail!(ErrorKind::Error(format!("no loader for: {:?}", ext))),
+        let info = ModuleInfo {
+            id: id.to_owned(),
+            protocol: protocol.to_owned(),
         };
 
-        let content = match resolver.resolver.read(&id) {
+        let content = match repo.transform(content, &info) {
             Err(e) => bail!(ErrorKind::Error(format!("{}", e))),
             Ok(m) => m,
         };
 
-        match loader.loader.load(ctx, &module, &content) {
-            Err(e) => bail!(ErrorKind::Error(format!("{}", e))),
-            Ok(_) => Ok(module),
+        // Cache before evaluating, so cyclic requires gets the partial exports
+        self.set_cache(ctx, &key, &module)?;
+        repo.record(&key, protocol, ext, content.len());
+
+        let result = match handler {
+            Some(handler) => handler
+                .call::<_, ()>((module.clone(), id, str::from_utf8(&content)?))
+                .map_err(|e| format!("{}", e)),
+            None => loader
+                .unwrap()
+                .loader
+                .load(ctx, &module, &content)
+                .map_err(|e| format!("{}", e)),
+        };
+
+        if let Err(e) = result {
+            CommonJS::invalidate(ctx, &key)?;
+            bail!(ErrorKind::Error(e));
         }
+
+        module.set("loaded", true);
+        repo.loaded(&key, started);
+
+        Ok(module)
     }
 
     /// Load a builtin module
@@ -232,6 +691,7 @@ impl Require {
 
         let found = found.unwrap();
 
+        let started = Instant::now();
         let module = internal::push_module_object(ctx, id, false).unwrap();
         module.clone().to_context(ctx)?;
         let top = ctx.top();
@@ -242,38 +702,25 @@ impl Require {
             module.set("exports", ctx.getp::<Ref>()?);
         }
         ctx.pop(1);
+
+        module.set("loaded", true);
+        self.set_cache(ctx, id, &module)?;
+        repo.record(id, "builtin", "builtin", 0);
+        repo.loaded(id, started);
+
         Ok(module)
     }
 
     fn has_cache(&self, ctx: &Context, id: &str) -> Result<bool> {
-        let cache = ctx
-            .push_global_stash()
-            .getp::<Object>()?
-            .get::<_, Object>(KEY)?
-            .get::<_, Object>("cache")?;
-
-        Ok(cache.has(id))
+        Ok(cache_object(ctx)?.has(id))
     }
 
     fn get_cache<'a>(&self, ctx: &'a Context, id: &str) -> Result<Object<'a>> {
-        let cache = ctx
-            .push_global_stash()
-            .getp::<Object>()?
-            .get::<_, Object>(KEY)?
-            .get::<_, Object>("cache")?;
-
-        Ok(cache.get::<_, Object>(id).unwrap())
+        cache_object(ctx)?.get::<_, Object>(id)
     }
 
     fn set_cache(&self, ctx: &Context, id: &str, module: &Object) -> Result<()> {
-        let cache = ctx
-            .push_global_stash()
-            .getp::<Object>()?
-            .get::<_, Object>(KEY)?
-            .get::<_, Object>("cache")?;
-
-        cache.set(id, module);
-
+        cache_object(ctx)?.set(id, module);
         Ok(())
     }
 }
@@ -287,49 +734,99 @@ impl Callable for Require {
             return Err(ErrorKind::TypeError("string expected".to_string()).into());
         }
 
-        let mut id: String = ctx.get(0)?;
+        let id: String = ctx.get(0)?;
         ctx.pop(1);
 
         let common = ctx.data()?.get::<CommonJS>().unwrap();
+        let id = common.alias(&id).into_owned();
 
-        let module = if common.modules.iter().find(|m| m.name == id).is_some() {
-            self.load_builtin_module(&id, ctx, common)?
+        let (key, module) = if common.modules.iter().find(|m| m.name == id).is_some() {
+            let module = self.load_builtin_module(&id, ctx, common)?;
+            (id, module)
         } else {
-            if FILE_RE.is_match(&id) {
-                id = format!("file://{}", id);
-            }
-            if !PROTOCOL_RE.is_match(&id) {
-                return Err(ErrorKind::TypeError(format!("invalid require id: {}", id)).into());
-            }
-            self.load_module(&id, ctx, &common)?
+            let (protocol, id) = resolve_id(ctx, &id, common)?;
+            let module = self.load_module(&protocol, &id, ctx, common)?;
+            (cache_key(&protocol, &id), module)
         };
 
+        let current: Object = ctx.push_current_function().getp()?;
+        let parent = current.get::<_, String>(MODULE_ID_KEY)?;
+        if !parent.is_empty() {
+            let protocol = if current.has(PROTOCOL_KEY) {
+                current.get::<_, String>(PROTOCOL_KEY)?
+            } else {
+                "file".to_string()
+            };
+            common.depend(&cache_key(&protocol, &parent), &key);
+        }
+
         if !module.has("exports") {
             bail!(ErrorKind::TypeError(format!(
                 "module does not have a 'exports' field"
             )));
         }
 
-        self.set_cache(ctx, &id, &module)?;
-
         module.get::<_, Ref>("exports")?.push();
 
         Ok(1)
     }
 }
 
+/// `require.resolve`: resolve a module id to the key used in `require.cache`, without loading it
+pub struct Resolve;
+
+impl Callable for Resolve {
+    fn argc(&self) -> i32 {
+        1
+    }
+    fn call(&self, ctx: &Context) -> Result<i32> {
+        if !ctx.is_string(0) {
+            return Err(ErrorKind::TypeError("string expected".to_string()).into());
+        }
+
+        let id: String = ctx.get(0)?;
+        ctx.pop(1);
+
+        let common = ctx.data()?.get::<CommonJS>().unwrap();
+        let id = common.alias(&id).into_owned();
+
+        if common.modules.iter().find(|m| m.name == id).is_some() {
+            ctx.push(id)?;
+        } else {
+            let (protocol, id) = resolve_id(ctx, &id, common)?;
+            ctx.push(cache_key(&protocol, &id))?;
+        }
+
+        Ok(1)
+    }
+}
+
 impl Drop for Require {
     fn drop(&mut self) {}
 }
 
-pub(crate) fn build_require<'a>(ctx: &'a Context, module_id: &str) -> Result<Function<'a>> {
+pub(crate) fn build_require<'a>(
+    ctx: &'a Context,
+    module_id: &str,
+    protocol: &str,
+) -> Result<Function<'a>> {
     let function: Object = ctx.push_function(Require {}).getp()?;
 
+    let resolve: Object = ctx.push_function(Resolve {}).getp()?;
+    resolve
+        .set(MODULE_ID_KEY, module_id)
+        .set(PROTOCOL_KEY, protocol);
+
     let mut stash: Object = ctx.push_global_stash().getp()?;
     stash = stash.get(KEY)?;
 
     function
         .set(MODULE_ID_KEY, module_id)
+        .set(PROTOCOL_KEY, protocol)
+        .set("resolve", resolve)
+        .set("extensions", stash.get::<_, Ref>("extensions")?)
+        .set("registerResolver", (2, register_resolver))
+        .set("graph", (0, graph))
         .set("cache", stash.get::<_, Ref>("cache")?)
         .set("main", stash.get::<_, Ref>("main")?);
 
@@ -343,3 +840,359 @@ pub(crate) fn build_require<'a>(ctx: &'a Context, module_id: &str) -> Result<Fun
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::cache_key;
+    use super::super::resolvers::MemoryResolver;
+    use super::super::{register, Builder, CJSContext, CommonJS, FsPolicy, NativeModule};
+    use duktape::error::Result;
+    use duktape::prelude::*;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    fn context(resolver: MemoryResolver) -> Context {
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("mem", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+        ctx
+    }
+
+    #[test]
+    fn circular_dependencies() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add(
+                "/a.js",
+                "exports.early = 'a'; var b = require('./b'); exports.b = b.seen; exports.loaded = module.loaded;",
+            )
+            .add("/b.js", "var a = require('./a'); exports.seen = a.early + ':' + a.b;");
+
+        let ctx = context(resolver);
+        ctx.require("mem:///a.js").unwrap();
+
+        let result: String = ctx
+            .eval("var a = require('mem:///a.js'); a.b + ',' + a.loaded + ',' + require.cache['mem:///a.js'].loaded")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "a:undefined,false,true");
+    }
+
+    #[test]
+    fn evict_on_throw() {
+        let mut resolver = MemoryResolver::new();
+        resolver.add(
+            "/fail.js",
+            "global.attempts = (global.attempts || 0) + 1; throw new Error('fail');",
+        );
+
+        let ctx = context(resolver);
+        assert!(ctx.require("mem:///fail.js").is_err());
+        assert!(ctx.require("mem:///fail.js").is_err());
+
+        let attempts: i32 = ctx.eval("attempts").unwrap().getp().unwrap();
+        assert_eq!(attempts, 2);
+        let cached: bool = ctx.eval("'mem:///fail.js' in require.cache").unwrap().getp().unwrap();
+        assert!(!cached);
+    }
+
+    #[test]
+    fn fs_policy() {
+        let root = env::temp_dir().join("duktape_modules_fs_policy");
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::write(root.join("app/main.js"), "module.exports = require('./util');").unwrap();
+        fs::write(root.join("app/util.js"), "module.exports = 'util';").unwrap();
+        fs::write(root.join("app/leak.js"), "module.exports = require('../secret');").unwrap();
+        fs::write(root.join("secret.js"), "module.exports = 'secret';").unwrap();
+
+        let ctx = Context::new().unwrap();
+        let mut policy = FsPolicy::restricted();
+        policy.allow(root.join("app"));
+        let mut builder = Builder::new();
+        builder.fs_policy(policy);
+        register(&ctx, builder).unwrap();
+
+        let main = root.join("app/main.js");
+        let result: String = ctx.require(main.to_str().unwrap()).unwrap().as_ref().get().unwrap();
+        assert_eq!(result, "util");
+
+        let leak = root.join("app/leak.js");
+        let err = ctx.require(leak.to_str().unwrap()).err().unwrap();
+        assert!(err.to_string().contains("permission denied"), "{}", err);
+    }
+
+    #[test]
+    fn remote_modules_cannot_require_files() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/main.js", "module.exports = require('file:///etc/hosts');")
+            .add("/bare.js", "module.exports = require('lodash');");
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("https", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        let err = ctx.require("https:///main.js").err().unwrap();
+        assert!(err.to_string().contains("remote module"), "{}", err);
+        // Bare ids would be looked up in the local node_modules
+        let err = ctx.require("https:///bare.js").err().unwrap();
+        assert!(err.to_string().contains("remote module"), "{}", err);
+
+        let ctx = Context::new().unwrap();
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/mapped.js", "module.exports = require('lodash');")
+            .add("/lodash.js", "module.exports = 'remote lodash';");
+        let mut builder = Builder::new();
+        builder
+            .resolver("https", Box::new(resolver))
+            .alias("lodash", "https:///lodash.js");
+        register(&ctx, builder).unwrap();
+        let value: String = ctx.require("https:///mapped.js").unwrap().as_ref().get().unwrap();
+        assert_eq!(value, "remote lodash");
+    }
+
+    #[test]
+    fn transforms() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/main.js", "module.exports = __NAME__ + require('./data.txt');")
+            .add("/data.txt", "data");
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder
+            .resolver("mem", Box::new(resolver))
+            .transform(r"\.js$", |source, info| {
+                Ok(source.replace("__NAME__", &format!("'{}:{}:'", info.protocol, info.id)))
+            })
+            .transform(r"\.txt$", |source, _| Ok(source.to_uppercase()));
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx
+            .require("mem:///main.js")
+            .unwrap()
+            .as_ref()
+            .get()
+            .unwrap();
+        assert_eq!(result, "mem:/main.js:DATA");
+    }
+
+    #[test]
+    fn script_loaders_and_resolvers() {
+        let ctx = Context::new().unwrap();
+        register(&ctx, Builder::new()).unwrap();
+
+        let result: String = ctx
+            .eval(
+                "var files = {
+                    '/main.js': 'module.exports = require(\\'./greeting\\');',
+                    '/greeting.up': 'hello'
+                };
+                require.registerResolver('str', {
+                    resolve: function (id, parent, extensions) {
+                        var path = id.replace(/^\\./, '');
+                        for (var i = 0; i < extensions.length; i++) {
+                            if (files[path + '.' + extensions[i]]) return path + '.' + extensions[i];
+                        }
+                        return path;
+                    },
+                    read: function (id) { return files[id]; }
+                });
+                require.extensions['.up'] = function (module, filename, source) {
+                    module.exports = source.toUpperCase() + ' ' + filename;
+                };
+                require('str:///main.js');",
+            )
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "HELLO /greeting.up");
+    }
+
+    #[test]
+    fn native_modules() {
+        struct Greeter;
+
+        impl NativeModule for Greeter {
+            fn exports(&self, _ctx: &Context, exports: &Object) -> Result<()> {
+                exports.set("name", "native");
+                Ok(())
+            }
+
+            fn source(&self) -> Option<&[u8]> {
+                Some(b"exports.greet = function () { return 'hello ' + exports.name; };")
+            }
+        }
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder
+            .native_module("greeter", Greeter)
+            .native_module("answer", |_ctx: &Context, exports: &Object| {
+                exports.set("value", 42);
+                Ok(())
+            });
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx
+            .eval("require('greeter').greet() + ' ' + require('answer').value")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "hello native 42");
+    }
+
+    #[test]
+    fn aliases() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/config.js", "module.exports = 'config';")
+            .add("/src/lib/index.js", "module.exports = 'lib';")
+            .add("/vendor/lib.js", "module.exports = 'vendor';");
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder
+            .resolver("mem", Box::new(resolver))
+            .native_module("answer", |_ctx: &Context, exports: &Object| {
+                exports.set("value", 42);
+                Ok(())
+            })
+            .alias("@app/config", "mem:///config")
+            .alias("@app/", "mem:///src/")
+            .alias("@app/vendor/", "mem:///vendor/")
+            .alias("@answer", "answer");
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx
+            .eval(
+                "[
+                    require('@app/config'),
+                    require('@app/lib'),
+                    require('@app/vendor/lib'),
+                    require('@answer').value,
+                    require.resolve('@app/config')
+                ].join(',')",
+            )
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "config,lib,vendor,42,mem:///config.js");
+    }
+
+    #[test]
+    fn import_maps() {
+        let map = r#"{"imports": {"@app/config": "./config.js", "@app/": "../src/", "net": "mem:///net.js"}}"#;
+        let mut aliases = super::parse_import_map(map, Path::new("/project")).unwrap();
+        aliases.sort();
+        assert_eq!(
+            aliases,
+            vec![
+                ("@app/".to_owned(), "/src/".to_owned()),
+                ("@app/config".to_owned(), "/project/config.js".to_owned()),
+                ("net".to_owned(), "mem:///net.js".to_owned()),
+            ]
+        );
+
+        assert!(super::parse_import_map(r#"{"@app/": "./src/"}"#, Path::new("/")).is_err());
+        assert!(super::parse_import_map(r#"{"imports": {"a": 1}}"#, Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn relative_import_map_path() {
+        let dir = env::temp_dir().join("duktape_modules_import_map");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/a.js"), "module.exports = 'a';").unwrap();
+        fs::write(dir.join("map.json"), r#"{"imports": {"@app/": "./src/"}}"#).unwrap();
+
+        // The map given relative to the working directory
+        let cwd = env::current_dir().unwrap();
+        let up = "../".repeat(cwd.components().count() - 1);
+        let map = dir.join("map.json");
+        let map = format!("{}{}", up, map.strip_prefix("/").unwrap().display());
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.import_map(&map).unwrap();
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx.eval("require('@app/a')").unwrap().getp().unwrap();
+        assert_eq!(result, "a");
+    }
+
+    #[test]
+    fn module_graph() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/main.js", "require('./a'); require('./b');")
+            .add("/a.js", "require('./b'); require('answer');")
+            .add("/b.js", "module.exports = 'b';");
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder
+            .resolver("mem", Box::new(resolver))
+            .native_module("answer", |_ctx: &Context, _exports: &Object| Ok(()));
+        register(&ctx, builder).unwrap();
+
+        ctx.eval("require('mem:///main.js')").unwrap();
+
+        let graph = ctx.data().unwrap().get::<CommonJS>().unwrap().graph();
+        let ids: Vec<_> = graph.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["mem:///main.js", "mem:///a.js", "mem:///b.js", "answer"]);
+        assert_eq!(graph[0].dependencies, vec!["mem:///a.js", "mem:///b.js"]);
+        assert_eq!(graph[1].dependencies, vec!["mem:///b.js", "answer"]);
+        assert_eq!(graph[2].parents, vec!["mem:///a.js", "mem:///main.js"]);
+        assert_eq!(graph[2].size, 21);
+        assert_eq!(graph[3].loader, "builtin");
+
+        let result: String = ctx
+            .eval(
+                "require.graph().map(function (m) {
+                    return m.id + ':' + m.dependencies.length + ':' + (typeof m.loadTime);
+                }).join(',')",
+            )
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(
+            result,
+            "mem:///main.js:2:number,mem:///a.js:2:number,mem:///b.js:0:number,answer:0:number"
+        );
+
+        CommonJS::invalidate(&ctx, "mem:///b.js").unwrap();
+        let graph = ctx.data().unwrap().get::<CommonJS>().unwrap().graph();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph[0].dependencies, vec!["mem:///a.js"]);
+        assert_eq!(graph[1].dependencies, vec!["answer"]);
+    }
+
+    #[test]
+    fn protocols_do_not_collide() {
+        let mut mem = MemoryResolver::new();
+        mem.add("/x.js", "module.exports = 'mem';");
+        let mut other = MemoryResolver::new();
+        other.add("/x.js", "module.exports = 'other';");
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder
+            .resolver("mem", Box::new(mem))
+            .resolver("other", Box::new(other));
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx
+            .eval("require('mem:///x.js') + ',' + require('other:///x.js')")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "mem,other");
+        assert_eq!(CommonJS::cached(&ctx).unwrap(), vec!["mem:///x.js", "other:///x.js"]);
+        assert_eq!(cache_key("file", "/x.js"), "/x.js");
+    }
+}
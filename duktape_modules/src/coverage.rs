@@ -0,0 +1,409 @@
+//! Instruments modules while their context is covering, see `duktape::coverage`.
+//!
+//! A counter is inserted before the first statement starting on a line, so lines keep their
+//! numbers. Statements are found from the tokens of the source: they start after semicolons,
+//! the braces of blocks and function bodies, case labels and line breaks ending a statement.
+//! A statement without braces around it, like the body of `if (a) b()`, is counted with the
+//! statement it belongs to.
+use super::error::Result;
+use super::esm::{text, tokenize, Kind, Token, REGEX_KEYWORDS, STATEMENT_HEADS};
+use duktape::coverage::STASH_KEY;
+use duktape::prelude::*;
+use std::fmt::Write;
+
+/// Parameter of the function around the module wrapper, with the hits of the module by line
+static COUNTERS: &str = "__coverage";
+
+/// Keywords which can not end a statement before a line break
+static CONTINUED: &[&str] = &[
+    "var", "let", "const", "function", "class", "extends", "async", "if", "for", "while", "with",
+    "switch", "catch", "try", "finally", "import", "export",
+];
+
+/// Keywords which continue the statement before them
+static CONTINUING: &[&str] = &[
+    "else",
+    "catch",
+    "finally",
+    "in",
+    "instanceof",
+    "of",
+    "case",
+    "default",
+];
+
+/// Keywords followed by a block
+static BLOCK_KEYWORDS: &[&str] = &["else", "try", "finally", "do", "catch"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bracket {
+    /// A block or function body, whose closing brace ends a statement if `statement` is set
+    Block { statement: bool },
+    /// The case clauses of a switch
+    Switch,
+    /// The parenthesized head of a statement like `if (..)`
+    Head { switch: bool },
+    /// The parameters of a function, followed by the body
+    Params { declaration: bool },
+    /// Parentheses, brackets, object literals, class bodies and template substitutions
+    Expression,
+}
+
+struct Level {
+    bracket: Bracket,
+    // Open `do` statements, whose `while` continues them
+    dos: usize,
+    // In the label of a case clause, with the conditional operators open in it
+    label: bool,
+    conditionals: usize,
+}
+
+impl Level {
+    fn new(bracket: Bracket) -> Level {
+        Level {
+            bracket,
+            dos: 0,
+            label: false,
+            conditionals: 0,
+        }
+    }
+
+    /// Statements are listed in blocks and case clauses, which a counter can go between
+    fn lists_statements(&self) -> bool {
+        match self.bracket {
+            Bracket::Block { .. } | Bracket::Switch => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_punct(src: &[u8], t: &Token, c: u8) -> bool {
+    t.kind == Kind::Punct && src[t.start] == c
+}
+
+/// Whether a line break after `t` ends the statement, if the next line starts with a name.
+/// `closed` is the bracket closed by `t`
+fn ends_statement(src: &[u8], t: &Token, closed: Option<Bracket>) -> bool {
+    match t.kind {
+        Kind::Ident => {
+            let word = text(src, t);
+            !REGEX_KEYWORDS.contains(&word) && !CONTINUED.contains(&word)
+        }
+        Kind::Punct => match src[t.start] {
+            b')' => match closed {
+                Some(Bracket::Head { .. }) => false,
+                _ => true,
+            },
+            b']' | b'}' => true,
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+/// Whether a counter can be put before `t`, when a statement starts at it.
+/// Strings are left alone, as they may be directives like "use strict"
+fn starts_statement(src: &[u8], t: &Token) -> bool {
+    match t.kind {
+        Kind::Punct => b"{([!~+-".contains(&src[t.start]),
+        Kind::Str => false,
+        _ => true,
+    }
+}
+
+/// Insert counters into `source`, returning the instrumented source with the lines counted
+pub fn instrument(source: &str) -> Result<(String, Vec<u32>)> {
+    let src = source.as_bytes();
+    let tokens = tokenize(source)?;
+
+    let mut out = String::with_capacity(source.len() + source.len() / 2);
+    let mut lines = Vec::new();
+    let mut copied = 0;
+    let mut line = 1;
+    let mut line_start = 0;
+
+    let mut stack = vec![Level::new(Bracket::Block { statement: true })];
+    // The previous token ended a statement
+    let mut start = true;
+    let mut prev_start = false;
+    // The bracket closed by the previous token
+    let mut closed = None;
+    // A function keyword was seen, with whether it starts a declaration
+    let mut function = None;
+    // The `while` of a do statement was seen, whose condition ends the statement
+    let mut do_while = false;
+
+    for (i, t) in tokens.iter().enumerate() {
+        let prev = if i > 0 { Some(&tokens[i - 1]) } else { None };
+        // Keywords used as property names are plain names
+        let word = match prev {
+            Some(p) if is_punct(src, p, b'.') => "",
+            _ if t.kind == Kind::Ident => text(src, t),
+            _ => "",
+        };
+
+        let level = stack.last_mut().unwrap();
+        let continuing = CONTINUING.contains(&word) || (word == "while" && level.dos > 0);
+        let at_start = level.lists_statements()
+            && !continuing
+            && (start
+                || (t.newline
+                    && t.kind == Kind::Ident
+                    && prev.map_or(false, |p| ends_statement(src, p, closed))));
+
+        if at_start && starts_statement(src, t) {
+            line += src[line_start..t.start]
+                .iter()
+                .filter(|c| **c == b'\n')
+                .count() as u32;
+            line_start = t.start;
+            if lines.last() != Some(&line) {
+                out.push_str(&source[copied..t.start]);
+                write!(out, "{}[{}]++;", COUNTERS, line).unwrap();
+                copied = t.start;
+                lines.push(line);
+            }
+        }
+
+        let mut next_start = false;
+        let mut next_closed = None;
+        match t.kind {
+            Kind::Ident => match word {
+                "function" => {
+                    let after_async = prev_start && prev.map_or(false, |p| text(src, p) == "async");
+                    function = Some(at_start || after_async);
+                }
+                "do" => level.dos += 1,
+                "while" if continuing => {
+                    level.dos -= 1;
+                    do_while = true;
+                }
+                "case" | "default" if level.bracket == Bracket::Switch => {
+                    level.label = true;
+                    level.conditionals = 0;
+                }
+                _ => {}
+            },
+            Kind::Punct => match src[t.start] {
+                b';' => next_start = level.lists_statements(),
+                b'?' if level.label => level.conditionals += 1,
+                b':' if level.label => {
+                    if level.conditionals > 0 {
+                        level.conditionals -= 1;
+                    } else {
+                        level.label = false;
+                        next_start = true;
+                    }
+                }
+                b'(' => {
+                    let head = prev.filter(|p| {
+                        !do_while
+                            && p.kind == Kind::Ident
+                            && STATEMENT_HEADS.contains(&text(src, p))
+                            && !(i > 1 && is_punct(src, &tokens[i - 2], b'.'))
+                    });
+                    do_while = false;
+                    let bracket = match (function.take(), head) {
+                        (Some(declaration), _) => Bracket::Params { declaration },
+                        (None, Some(head)) => Bracket::Head {
+                            switch: text(src, head) == "switch",
+                        },
+                        (None, None) => Bracket::Expression,
+                    };
+                    stack.push(Level::new(bracket));
+                }
+                b'[' => stack.push(Level::new(Bracket::Expression)),
+                b'{' => {
+                    let arrow = i > 1
+                        && prev.map_or(false, |p| is_punct(src, p, b'>'))
+                        && is_punct(src, &tokens[i - 2], b'=')
+                        && tokens[i - 2].end == tokens[i - 1].start;
+                    let bracket = match prev {
+                        _ if at_start => Bracket::Block { statement: true },
+                        Some(p) if is_punct(src, p, b')') => match closed {
+                            Some(Bracket::Head { switch: true }) => Bracket::Switch,
+                            Some(Bracket::Head { .. }) => Bracket::Block { statement: true },
+                            Some(Bracket::Params { declaration }) => Bracket::Block {
+                                statement: declaration,
+                            },
+                            // Methods
+                            _ => Bracket::Block { statement: false },
+                        },
+                        Some(p)
+                            if p.kind == Kind::Ident && BLOCK_KEYWORDS.contains(&text(src, p)) =>
+                        {
+                            Bracket::Block { statement: true }
+                        }
+                        _ if arrow => Bracket::Block { statement: false },
+                        _ => Bracket::Expression,
+                    };
+                    next_start = bracket != Bracket::Switch && bracket != Bracket::Expression;
+                    stack.push(Level::new(bracket));
+                }
+                b')' | b']' | b'}' if stack.len() > 1 => {
+                    let level = stack.pop().unwrap();
+                    next_start = match level.bracket {
+                        Bracket::Block { statement } => statement,
+                        Bracket::Switch => true,
+                        _ => false,
+                    };
+                    next_closed = Some(level.bracket);
+                }
+                _ => {}
+            },
+            Kind::Template => {
+                // Substitutions are closed by the next part of the template
+                if src[t.start] == b'}' && stack.len() > 1 {
+                    stack.pop();
+                }
+                if src[t.end - 1] == b'{' {
+                    stack.push(Level::new(Bracket::Expression));
+                }
+            }
+            _ => {}
+        }
+
+        start = next_start;
+        closed = next_closed;
+        prev_start = at_start;
+    }
+
+    out.push_str(&source[copied..]);
+    Ok((out, lines))
+}
+
+/// Instrument the source of `module`, if the context is covering. Returns the instrumented source
+/// in the module wrapper, inside a function to call with the hits of the file
+pub(crate) fn instrument_module<'a>(
+    ctx: &'a Context,
+    source: &str,
+    module: &Object,
+) -> Result<Option<(String, Object<'a>)>> {
+    // Modules without a file name, like builtin ones, are left out
+    let file = match module.get::<_, String>("fileName") {
+        Ok(ref file) if !file.is_empty() => file.clone(),
+        _ => return Ok(None),
+    };
+    ctx.push_global_stash();
+    if !ctx.has_prop_string(-1, STASH_KEY) {
+        ctx.pop(1);
+        return Ok(None);
+    }
+    // Sources the tokenizer can not read are left for the compiler to report
+    let (code, lines) = match instrument(source) {
+        Ok(instrumented) => instrumented,
+        Err(_) => {
+            ctx.pop(1);
+            return Ok(None);
+        }
+    };
+
+    ctx.get_prop_string(-1, STASH_KEY);
+    if !ctx.has_prop_string(-1, &file) {
+        ctx.push_bare_object().put_prop_string(-2, &file);
+    }
+    ctx.get_prop_string(-1, &file);
+    // Modules loaded again keep the hits of before
+    for line in lines {
+        if !ctx.has_prop_index(-1, line) {
+            ctx.push_uint(0).put_prop_index(-2, line);
+        }
+    }
+    let hits = ctx.getp::<Object>()?;
+    ctx.pop(2);
+
+    // The lines of the module are kept, as the wrappers start on its first line
+    let wrapped = format!(
+        "(function({}) {{ return function(exports,require,module,__filename,__dirname) {{{}\n}}}})",
+        COUNTERS, code
+    );
+    Ok(Some((wrapped, hits)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::resolvers::MemoryResolver;
+    use super::super::{register, Builder};
+    use super::instrument;
+    use duktape::prelude::*;
+
+    fn counted(source: &str) -> Vec<u32> {
+        let (code, lines) = instrument(source).unwrap();
+        // Lines keep their numbers
+        assert_eq!(code.matches('\n').count(), source.matches('\n').count());
+        // The instrumented source still compiles
+        let ctx = Context::new().unwrap();
+        ctx.compile_string(
+            format!("(function(__coverage) {{ {}\n}})", code),
+            Compile::EVAL,
+        )
+        .unwrap_or_else(|e| panic!("{}\n{}", e, code));
+        lines
+    }
+
+    #[test]
+    fn instrument_statements() {
+        assert_eq!(counted("'use strict';\nvar a = 1\nfoo(a)\n"), vec![2, 3]);
+        assert_eq!(
+            counted("function f(a) {\n  return a;\n}\nvar g = function () {\n  return 1;\n};\n"),
+            vec![1, 2, 4, 5]
+        );
+        assert_eq!(
+            counted("if (a) {\n  b();\n} else if (c)\n  d();\nelse {\n  e();\n}\n"),
+            vec![1, 2, 6]
+        );
+        assert_eq!(
+            counted("var o = {\n  a: 1,\n  b: function () {\n    return 2;\n  }\n}\nfoo(o)\n"),
+            vec![1, 4, 7]
+        );
+        assert_eq!(
+            counted("switch (a) {\n  case 1:\n    b();\n    break;\n  default:\n    c(a ? 1 : 2);\n}\nd();\n"),
+            vec![1, 3, 4, 6, 8]
+        );
+        assert_eq!(counted("do\n  a()\nwhile (b)\nc()\n"), vec![1, 4]);
+        assert_eq!(
+            counted("p.catch(function (e) {\n  return e;\n})\n  .then(f)\n"),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn instrument_es2015() {
+        // Duktape does not compile these, but the es2015 loader may hand them over untranspiled
+        let (code, lines) = instrument("var s = `${{ a: 1 }.a}`\nclass A extends B {\n  m() {\n    return s;\n  }\n}\nvar f = x => {\n  return x;\n};\n").unwrap();
+        assert_eq!(lines, vec![1, 2, 4, 7, 8]);
+        assert_eq!(
+            code,
+            "__coverage[1]++;var s = `${{ a: 1 }.a}`\n__coverage[2]++;class A extends B {\n  m() {\n    __coverage[4]++;return s;\n  }\n}\n__coverage[7]++;var f = x => {\n  __coverage[8]++;return x;\n};\n"
+        );
+    }
+
+    #[test]
+    fn instrument_modules() {
+        let ctx = Context::new().unwrap();
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add(
+                "/cover.js",
+                "function used(n) {\n  return n + 1;\n}\nfunction unused() {\n  return 2;\n}\nvar x = used(1);\nif (x > 5) {\n  x = 0;\n}\nmodule.exports = x;\n",
+            )
+            .add("/loop.js", "for (var i = 0; i < 3; i++) {\n  require('./cover');\n}\n")
+            .add("/other.js", "module.exports = 1;\n");
+        let mut builder = Builder::new();
+        builder.resolver("memory", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        ctx.start_coverage();
+        ctx.eval("require('memory:///loop.js')").unwrap();
+        let coverage = ctx.stop_coverage();
+
+        let cover = &coverage.files["/cover.js"];
+        assert_eq!(cover.lines[&2], 1);
+        assert_eq!(cover.uncovered(), vec![5, 9]);
+        assert_eq!(coverage.files["/loop.js"].lines[&2], 3);
+
+        // Modules loaded after stopping are not instrumented
+        ctx.eval("require('memory:///other.js')").unwrap();
+        assert!(ctx.stop_coverage().files.is_empty());
+    }
+}
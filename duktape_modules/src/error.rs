@@ -16,5 +16,25 @@ error_chain!{
             description("ResolveError")
             display("could not resolve: '{}'",path)
         }
+        Package(path:String, message:String) {
+            description("PackageError")
+            display("invalid package '{}': {}",path, message)
+        }
+        Syntax(message:String) {
+            description("SyntaxError")
+            display("syntax error: {}",message)
+        }
+        Fetch(url:String, message:String) {
+            description("FetchError")
+            display("could not fetch '{}': {}",url, message)
+        }
+        Integrity(url:String) {
+            description("IntegrityError")
+            display("content of '{}' does not match the lockfile",url)
+        }
+        PermissionDenied(path:String) {
+            description("PermissionDenied")
+            display("permission denied: '{}'",path)
+        }
     }
 }
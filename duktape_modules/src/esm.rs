@@ -0,0 +1,1172 @@
+//! Rewrites ES module syntax (`import`/`export`) onto CommonJS `require` and `exports`.
+//!
+//! Named exports are getters on `exports` reading the local binding, and references
+//! to imported bindings are rewritten to member accesses on the required module,
+//! so both sides sees live values. Modules without `__esModule` (plain CommonJS)
+//! are imported with `module.exports` as the default export.
+//! References within the scope of a declaration shadowing an imported binding are
+//! left as they are, while redeclaring an import at the top level is a syntax error.
+use super::error::{ErrorKind, Result};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Ident,
+    Punct,
+    Str,
+    Template,
+    Regex,
+    Number,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub(crate) kind: Kind,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    // Nesting depth of brackets and template substitutions
+    depth: usize,
+    // Innermost open bracket
+    scope: u8,
+    pub(crate) newline: bool,
+}
+
+pub(crate) static REGEX_KEYWORDS: &[&str] = &[
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+    "await",
+];
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+fn is_ident_part(c: u8) -> bool {
+    is_ident_start(c) || c.is_ascii_digit()
+}
+
+fn syntax_err<T>(message: &str) -> Result<T> {
+    Err(ErrorKind::Syntax(message.to_owned()).into())
+}
+
+/// Statements whose parenthesized head may be followed by a regular expression
+static CONDITION_KEYWORDS: &[&str] = &["if", "while", "for", "with"];
+
+/// Statements with a parenthesized head followed by a block
+pub(crate) static STATEMENT_HEADS: &[&str] = &["if", "while", "for", "with", "switch", "catch"];
+
+/// Tokens which may follow a name bound by a pattern
+static BINDING_ENDS: &[&str] = &[",", ")", "}", "]", "="];
+
+struct Tokenizer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    // Open brackets, with `c` for the head of a statement like `if (..)`
+    stack: Vec<u8>,
+    tokens: Vec<Token>,
+    // The last token closed the head of a statement
+    after_condition: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn peek(&self, offset: usize) -> u8 {
+        *self.src.get(self.pos + offset).unwrap_or(&0)
+    }
+
+    /// Skip whitespace and comments. Returns true if a line break was skipped
+    fn skip_trivia(&mut self) -> Result<bool> {
+        let mut newline = false;
+        if self.pos == 0 && self.src.starts_with(b"#!") {
+            while self.pos < self.src.len() && self.src[self.pos] != b'\n' {
+                self.pos += 1;
+            }
+        }
+        while self.pos < self.src.len() {
+            match (self.peek(0), self.peek(1)) {
+                (b'\n', _) => {
+                    newline = true;
+                    self.pos += 1;
+                }
+                (c, _) if c.is_ascii_whitespace() => self.pos += 1,
+                (b'/', b'/') => {
+                    while self.pos < self.src.len() && self.src[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                (b'/', b'*') => {
+                    let end = match find(self.src, self.pos + 2, b"*/") {
+                        Some(end) => end,
+                        None => return syntax_err("unterminated comment"),
+                    };
+                    if self.src[self.pos..end].contains(&b'\n') {
+                        newline = true;
+                    }
+                    self.pos = end + 2;
+                }
+                _ => break,
+            }
+        }
+        Ok(newline)
+    }
+
+    fn regex_allowed(&self) -> bool {
+        match self.tokens.last() {
+            None => true,
+            Some(t) => match t.kind {
+                Kind::Punct => match self.src[t.end - 1] {
+                    b')' => self.after_condition,
+                    b']' | b'}' => false,
+                    _ => true,
+                },
+                Kind::Ident => REGEX_KEYWORDS.contains(&text(self.src, t)),
+                _ => false,
+            },
+        }
+    }
+
+    fn quoted(&mut self, quote: u8) -> Result<()> {
+        self.pos += 1;
+        loop {
+            match self.peek(0) {
+                0 if self.pos >= self.src.len() => return syntax_err("unterminated string"),
+                b'\\' => self.pos += 2,
+                c if c == quote => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    /// Scan template characters up to and including the closing backtick or `${`
+    fn template(&mut self) -> Result<()> {
+        loop {
+            match self.peek(0) {
+                0 if self.pos >= self.src.len() => return syntax_err("unterminated template"),
+                b'\\' => self.pos += 2,
+                b'`' => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                b'$' if self.peek(1) == b'{' => {
+                    self.pos += 2;
+                    self.stack.push(b'`');
+                    return Ok(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn regex(&mut self) -> Result<()> {
+        self.pos += 1;
+        let mut class = false;
+        loop {
+            match self.peek(0) {
+                0 | b'\n' => return syntax_err("unterminated regular expression"),
+                b'\\' => self.pos += 2,
+                b'[' => {
+                    class = true;
+                    self.pos += 1
+                }
+                b']' => {
+                    class = false;
+                    self.pos += 1
+                }
+                b'/' if !class => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => self.pos += 1,
+            }
+        }
+        while is_ident_part(self.peek(0)) {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) {
+        let hex = self.peek(0) == b'0' && (self.peek(1) | 0x20) == b'x';
+        while is_ident_part(self.peek(0)) || self.peek(0) == b'.' {
+            let c = self.peek(0);
+            self.pos += 1;
+            if !hex && (c == b'e' || c == b'E') && (self.peek(0) == b'+' || self.peek(0) == b'-') {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<Token>> {
+        loop {
+            let newline = self.skip_trivia()?;
+            if self.pos >= self.src.len() {
+                break;
+            }
+
+            let start = self.pos;
+            let c = self.peek(0);
+            let mut depth = self.stack.len();
+            let mut scope = *self.stack.last().unwrap_or(&0);
+
+            let kind = match c {
+                b'"' | b'\'' => {
+                    self.quoted(c)?;
+                    Kind::Str
+                }
+                b'`' => {
+                    self.pos += 1;
+                    self.template()?;
+                    Kind::Template
+                }
+                b'}' if scope == b'`' => {
+                    self.stack.pop();
+                    depth -= 1;
+                    scope = *self.stack.last().unwrap_or(&0);
+                    self.pos += 1;
+                    self.template()?;
+                    Kind::Template
+                }
+                b'/' if self.regex_allowed() => {
+                    self.regex()?;
+                    Kind::Regex
+                }
+                c if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_ascii_digit()) => {
+                    self.number();
+                    Kind::Number
+                }
+                c if is_ident_start(c) => {
+                    while is_ident_part(self.peek(0)) {
+                        self.pos += 1;
+                    }
+                    Kind::Ident
+                }
+                b'.' if self.peek(1) == b'.' && self.peek(2) == b'.' => {
+                    self.pos += 3;
+                    Kind::Punct
+                }
+                b'(' | b'[' | b'{' => {
+                    let condition = c == b'('
+                        && self.tokens.last().map_or(false, |t| {
+                            t.kind == Kind::Ident && CONDITION_KEYWORDS.contains(&text(self.src, t))
+                        });
+                    self.stack.push(if condition { b'c' } else { c });
+                    self.pos += 1;
+                    Kind::Punct
+                }
+                b')' | b']' | b'}' => {
+                    let open = match self.stack.pop() {
+                        Some(open) => open,
+                        None => return syntax_err("unbalanced brackets"),
+                    };
+                    self.after_condition = open == b'c';
+                    depth -= 1;
+                    scope = *self.stack.last().unwrap_or(&0);
+                    self.pos += 1;
+                    Kind::Punct
+                }
+                _ => {
+                    self.pos += 1;
+                    Kind::Punct
+                }
+            };
+
+            self.tokens.push(Token {
+                kind,
+                start,
+                end: self.pos.min(self.src.len()),
+                depth,
+                scope,
+                newline,
+            });
+        }
+
+        Ok(self.tokens)
+    }
+}
+
+fn find(src: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    src[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+pub(crate) fn text<'a>(src: &'a [u8], token: &Token) -> &'a str {
+    ::std::str::from_utf8(&src[token.start..token.end]).unwrap_or("")
+}
+
+pub(crate) fn tokenize(src: &str) -> Result<Vec<Token>> {
+    Tokenizer {
+        src: src.as_bytes(),
+        pos: 0,
+        stack: Vec::new(),
+        tokens: Vec::new(),
+        after_condition: false,
+    }
+    .run()
+}
+
+struct Export {
+    name: String,
+    // Local binding or expression the getter returns
+    local: String,
+    // Resolve `local` through the imported bindings
+    binding: bool,
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: &'a [Token],
+    pos: usize,
+    requires: Vec<Option<String>>,
+    bindings: HashMap<String, String>,
+    exports: Vec<Export>,
+    stars: Vec<String>,
+    edits: Vec<(usize, usize, String)>,
+}
+
+impl<'a> Parser<'a> {
+    fn text(&self, idx: usize) -> &'a str {
+        match self.tokens.get(idx) {
+            Some(t) => text(self.src.as_bytes(), t),
+            None => "",
+        }
+    }
+
+    fn is(&self, idx: usize, s: &str) -> bool {
+        self.text(idx) == s
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.tokens.get(self.pos) {
+            Some(t) if t.kind == Kind::Ident => {
+                self.pos += 1;
+                Ok(self.text(self.pos - 1).to_owned())
+            }
+            _ => syntax_err(&format!("expected identifier, found '{}'", self.text(self.pos))),
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        if self.is(self.pos, s) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            syntax_err(&format!("expected '{}', found '{}'", s, self.text(self.pos)))
+        }
+    }
+
+    /// Parse `from 'source'` and allocate a require for the source
+    fn source(&mut self) -> Result<String> {
+        match self.tokens.get(self.pos) {
+            Some(t) if t.kind == Kind::Str => {
+                self.pos += 1;
+                Ok(self.require(Some(self.text(self.pos - 1).to_owned())))
+            }
+            _ => syntax_err("expected module specifier"),
+        }
+    }
+
+    fn require(&mut self, source: Option<String>) -> String {
+        self.requires.push(source);
+        format!("__esm${}", self.requires.len() - 1)
+    }
+
+    fn semicolon(&mut self) {
+        if self.is(self.pos, ";") {
+            self.pos += 1;
+        }
+    }
+
+    /// The end of the token range of the statement starting at `from`
+    fn statement_end(&self, from: usize) -> usize {
+        let depth = self.tokens[from].depth;
+        let mut idx = from + 1;
+        while idx < self.tokens.len() {
+            let t = &self.tokens[idx];
+            if t.depth < depth {
+                return idx;
+            }
+            if t.depth == depth {
+                if self.is(idx, ";") {
+                    return idx + 1;
+                }
+                if t.newline && ends_value(self.src.as_bytes(), &self.tokens[idx - 1]) && starts_value(self.src.as_bytes(), t) {
+                    return idx;
+                }
+            }
+            idx += 1;
+        }
+        idx
+    }
+
+    /// Replace the tokens in `from..to` with `replacement`, keeping line breaks
+    fn replace(&mut self, from: usize, to: usize, replacement: &str) {
+        let start = self.tokens[from].start;
+        let end = self.tokens[to - 1].end;
+        let newlines = self.src[start..end].matches('\n').count();
+        let mut text = replacement.to_owned();
+        text.push_str(&"\n".repeat(newlines));
+        self.edits.push((start, end, text));
+    }
+
+    fn parse_import(&mut self) -> Result<()> {
+        let from = self.pos;
+        self.pos += 1;
+
+        if self.tokens.get(self.pos).map(|t| t.kind) == Some(Kind::Str) {
+            let source = self.text(self.pos).to_owned();
+            self.pos += 1;
+            self.require(Some(source));
+        } else {
+            let mut default = None;
+            let mut namespace = None;
+            let mut named = Vec::new();
+
+            if self.tokens.get(self.pos).map(|t| t.kind) == Some(Kind::Ident) {
+                default = Some(self.ident()?);
+                if self.is(self.pos, ",") {
+                    self.pos += 1;
+                }
+            }
+
+            if self.is(self.pos, "*") {
+                self.pos += 1;
+                self.expect("as")?;
+                namespace = Some(self.ident()?);
+            } else if self.is(self.pos, "{") {
+                self.pos += 1;
+                while !self.is(self.pos, "}") {
+                    let name = self.ident()?;
+                    let local = if self.is(self.pos, "as") {
+                        self.pos += 1;
+                        self.ident()?
+                    } else {
+                        name.clone()
+                    };
+                    named.push((name, local));
+                    if !self.is(self.pos, "}") {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+            }
+
+            self.expect("from")?;
+            let var = self.source()?;
+
+            if let Some(local) = default {
+                self.bindings.insert(local, member(&var, "default"));
+            }
+            if let Some(local) = namespace {
+                self.bindings.insert(local, var.clone());
+            }
+            for (name, local) in named {
+                self.bindings.insert(local, member(&var, &name));
+            }
+        }
+
+        self.semicolon();
+        let to = self.pos;
+        self.replace(from, to, "");
+        Ok(())
+    }
+
+    fn parse_export(&mut self) -> Result<()> {
+        let from = self.pos;
+        self.pos += 1;
+
+        match self.text(self.pos) {
+            "default" => {
+                self.pos += 1;
+                let mut idx = self.pos;
+                if self.is(idx, "async") {
+                    idx += 1;
+                }
+                let declaration = self.is(idx, "function") || self.is(idx, "class");
+                if self.is(idx + 1, "*") {
+                    idx += 1;
+                }
+                let named = self.tokens.get(idx + 1).map(|t| t.kind) == Some(Kind::Ident)
+                    && !self.is(idx + 1, "extends");
+
+                if declaration && named {
+                    let name = self.text(idx + 1).to_owned();
+                    self.export("default", &name, false);
+                    self.replace(from, self.pos, "");
+                } else {
+                    self.export("default", "__esm$default", false);
+                    self.replace(from, self.pos, "var __esm$default =");
+                }
+            }
+            "var" | "let" | "const" => {
+                self.replace(from, from + 1, "");
+                let end = self.statement_end(self.pos);
+                let depth = self.tokens[self.pos].depth;
+                let mut expect_name = true;
+                for idx in self.pos + 1..end {
+                    let t = &self.tokens[idx];
+                    if t.depth != depth {
+                        continue;
+                    }
+                    if expect_name {
+                        if t.kind != Kind::Ident {
+                            return syntax_err("destructuring exports are not supported");
+                        }
+                        let name = self.text(idx).to_owned();
+                        self.export(&name, &name, false);
+                        expect_name = false;
+                    } else if self.is(idx, ",") {
+                        expect_name = true;
+                    }
+                }
+                self.pos = end;
+            }
+            "function" | "class" | "async" => {
+                self.replace(from, from + 1, "");
+                let mut idx = self.pos + 1;
+                if self.is(self.pos, "async") {
+                    idx += 1;
+                }
+                if self.is(idx, "*") {
+                    idx += 1;
+                }
+                let name = self.text(idx).to_owned();
+                self.export(&name, &name, false);
+                self.pos = idx;
+            }
+            "{" => {
+                self.pos += 1;
+                let mut names = Vec::new();
+                while !self.is(self.pos, "}") {
+                    let local = self.ident()?;
+                    let name = if self.is(self.pos, "as") {
+                        self.pos += 1;
+                        self.ident()?
+                    } else {
+                        local.clone()
+                    };
+                    names.push((name, local));
+                    if !self.is(self.pos, "}") {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+
+                if self.is(self.pos, "from") {
+                    self.pos += 1;
+                    let var = self.source()?;
+                    for (name, local) in names {
+                        self.export(&name, &member(&var, &local), false);
+                    }
+                } else {
+                    for (name, local) in names {
+                        self.export(&name, &local, true);
+                    }
+                }
+                self.semicolon();
+                self.replace(from, self.pos, "");
+            }
+            "*" => {
+                self.pos += 1;
+                if self.is(self.pos, "as") {
+                    self.pos += 1;
+                    let name = self.ident()?;
+                    self.expect("from")?;
+                    let var = self.source()?;
+                    self.export(&name, &var, false);
+                } else {
+                    self.expect("from")?;
+                    let var = self.source()?;
+                    self.stars.push(var);
+                }
+                self.semicolon();
+                self.replace(from, self.pos, "");
+            }
+            s => return syntax_err(&format!("unexpected '{}' after export", s)),
+        }
+
+        Ok(())
+    }
+
+    fn export(&mut self, name: &str, local: &str, binding: bool) {
+        self.exports.push(Export {
+            name: name.to_owned(),
+            local: local.to_owned(),
+            binding,
+        });
+    }
+
+    fn parse(&mut self) -> Result<()> {
+        while self.pos < self.tokens.len() {
+            let t = &self.tokens[self.pos];
+            let after_dot = self.pos > 0 && self.is(self.pos - 1, ".");
+            if t.depth == 0 && t.kind == Kind::Ident && !after_dot {
+                match self.text(self.pos) {
+                    "import" if !self.is(self.pos + 1, "(") && !self.is(self.pos + 1, ".") => {
+                        self.parse_import()?;
+                        continue;
+                    }
+                    "export" => {
+                        self.parse_export()?;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// The index of the token closing the bracket at `open`
+    fn closing(&self, open: usize) -> usize {
+        let depth = self.tokens[open].depth;
+        let mut idx = open + 1;
+        while idx < self.tokens.len() && self.tokens[idx].depth > depth {
+            idx += 1;
+        }
+        idx
+    }
+
+    fn is_arrow(&self, idx: usize) -> bool {
+        self.is(idx, "=") && self.is(idx + 1, ">") && self.tokens[idx].end == self.tokens[idx + 1].start
+    }
+
+    /// Whether the parentheses at `open` are the parameters of a function
+    fn is_parameters(&self, open: usize) -> bool {
+        let close = self.closing(open);
+        if self.is_arrow(close + 1) {
+            return true;
+        }
+        let head = open > 0
+            && self.tokens[open - 1].kind == Kind::Ident
+            && STATEMENT_HEADS.contains(&self.text(open - 1));
+        self.is(close + 1, "{") && !head
+    }
+
+    /// Names bound by the parameters or destructuring pattern at `open`.
+    /// Default values are skipped
+    fn pattern_names(&self, open: usize, names: &mut Vec<usize>) {
+        let depth = self.tokens[open].depth + 1;
+        let mut default = false;
+        for idx in open + 1..self.closing(open) {
+            let t = &self.tokens[idx];
+            if t.depth != depth {
+                continue;
+            }
+            match self.text(idx) {
+                "," => default = false,
+                "=" => default = true,
+                "{" | "[" if !default => self.pattern_names(idx, names),
+                _ if !default && t.kind == Kind::Ident && BINDING_ENDS.contains(&self.text(idx + 1)) => {
+                    names.push(idx)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Names declared by the `var`, `let` or `const` statement at `keyword`
+    fn declarator_names(&self, keyword: usize, names: &mut Vec<usize>) {
+        let depth = self.tokens[keyword].depth;
+        let mut expect_name = true;
+        for idx in keyword + 1..self.statement_end(keyword) {
+            if self.tokens[idx].depth != depth {
+                continue;
+            }
+            if expect_name {
+                match self.text(idx) {
+                    "{" | "[" => self.pattern_names(idx, names),
+                    _ if self.tokens[idx].kind == Kind::Ident => names.push(idx),
+                    _ => {}
+                }
+                expect_name = false;
+            } else if self.is(idx, ",") {
+                expect_name = true;
+            }
+        }
+    }
+
+    /// The index of the bracket opening the innermost brackets around `idx`
+    fn enclosing(&self, idx: usize) -> Option<usize> {
+        let depth = self.tokens[idx].depth;
+        (0..idx).rev().find(|&i| self.tokens[i].depth < depth)
+    }
+
+    /// The index of the bracket closed by the bracket at `close`
+    fn opening(&self, close: usize) -> usize {
+        let depth = self.tokens[close].depth;
+        (0..close).rev().find(|&i| self.tokens[i].depth <= depth).unwrap_or(0)
+    }
+
+    /// The end of the body starting at `from`, a block or the expression of an arrow function
+    fn body_end(&self, from: usize) -> usize {
+        let depth = match self.tokens.get(from) {
+            Some(t) => t.depth,
+            None => return from,
+        };
+        if self.is(from, "{") {
+            return self.closing(from) + 1;
+        }
+        let mut idx = from;
+        while idx < self.tokens.len() {
+            let t = &self.tokens[idx];
+            if t.depth < depth || (t.depth == depth && (self.is(idx, ",") || self.is(idx, ";"))) {
+                break;
+            }
+            idx += 1;
+        }
+        idx
+    }
+
+    /// The token range of the function whose parameters open at `open`
+    fn parameters_scope(&self, open: usize) -> (usize, usize) {
+        let close = self.closing(open);
+        let body = if self.is_arrow(close + 1) { close + 3 } else { close + 1 };
+        (open, self.body_end(body))
+    }
+
+    /// The token range of the function whose parameters or body opens at `open`
+    fn function_at(&self, open: usize) -> Option<(usize, usize)> {
+        let params = match self.text(open) {
+            "(" => open,
+            "{" if open >= 3 && self.is_arrow(open - 2) => {
+                if !self.is(open - 3, ")") {
+                    return Some((open - 3, self.closing(open) + 1));
+                }
+                self.opening(open - 3)
+            }
+            "{" if open >= 1 && self.is(open - 1, ")") => self.opening(open - 1),
+            _ => return None,
+        };
+        if self.is_parameters(params) {
+            Some(self.parameters_scope(params))
+        } else {
+            None
+        }
+    }
+
+    /// The token range of the function around `idx`, `None` at the top level
+    fn function_scope(&self, idx: usize) -> Option<(usize, usize)> {
+        let mut open = self.enclosing(idx)?;
+        loop {
+            if let Some(scope) = self.function_at(open) {
+                return Some(scope);
+            }
+            open = self.enclosing(open)?;
+        }
+    }
+
+    /// The token range of the block around `idx`, `None` at the top level.
+    /// The scope of `let` in the head of a `for` statement is the statement
+    fn block_scope(&self, idx: usize) -> Option<(usize, usize)> {
+        let open = self.enclosing(idx)?;
+        if !self.is(open, "(") {
+            return Some((open, self.closing(open) + 1));
+        }
+        let body = self.closing(open) + 1;
+        let end = if body >= self.tokens.len() || self.is(body, "{") {
+            self.body_end(body)
+        } else {
+            self.statement_end(body)
+        };
+        Some((open, end))
+    }
+
+    /// Whether the `function` or `class` keyword at `idx` starts a declaration, not an expression
+    fn is_declaration(&self, idx: usize) -> bool {
+        let idx = if idx > 0 && self.is(idx - 1, "async") { idx - 1 } else { idx };
+        idx == 0 || [";", "{", "}", ")", "else", "export", "default"].contains(&self.text(idx - 1))
+    }
+
+    /// The tokens declaring a name in any scope of the module, with the token range of the scope.
+    /// The scope of declarations at the top level is `None`
+    fn declarations(&self) -> Vec<(usize, Option<(usize, usize)>)> {
+        let mut declarations = Vec::new();
+        for idx in 0..self.tokens.len() {
+            let t = &self.tokens[idx];
+            if idx > 0 && self.is(idx - 1, ".") {
+                continue;
+            }
+            let mut names = Vec::new();
+            let scope = match self.text(idx) {
+                "var" if t.kind == Kind::Ident => {
+                    self.declarator_names(idx, &mut names);
+                    self.function_scope(idx)
+                }
+                "let" | "const" if t.kind == Kind::Ident => {
+                    self.declarator_names(idx, &mut names);
+                    self.block_scope(idx)
+                }
+                "function" | "class" if t.kind == Kind::Ident => {
+                    let name = if self.is(idx + 1, "*") { idx + 2 } else { idx + 1 };
+                    if self.tokens.get(name).map(|t| t.kind) == Some(Kind::Ident) {
+                        names.push(name);
+                    }
+                    if self.is_declaration(idx) {
+                        self.block_scope(idx)
+                    } else {
+                        // The name of an expression is only bound within it
+                        let depth = t.depth;
+                        let body = (idx..self.tokens.len())
+                            .find(|&i| self.tokens[i].depth == depth && self.is(i, "{"));
+                        Some((idx, body.map_or(self.tokens.len(), |b| self.closing(b) + 1)))
+                    }
+                }
+                "(" if t.kind == Kind::Punct && self.is_parameters(idx) => {
+                    self.pattern_names(idx, &mut names);
+                    Some(self.parameters_scope(idx))
+                }
+                "(" if t.kind == Kind::Punct && idx > 0 && self.is(idx - 1, "catch") => {
+                    self.pattern_names(idx, &mut names);
+                    Some((idx, self.body_end(self.closing(idx) + 1)))
+                }
+                _ if t.kind == Kind::Ident && self.is_arrow(idx + 1) => {
+                    names.push(idx);
+                    Some((idx, self.body_end(idx + 3)))
+                }
+                _ => None,
+            };
+            declarations.extend(names.into_iter().map(|name| (name, scope)));
+        }
+        declarations
+    }
+
+    /// Rewrite references to imported bindings.
+    /// References within the scope of a declaration shadowing the import are kept
+    fn rewrite_references(&mut self) -> Result<()> {
+        let src = self.src.as_bytes();
+        let removed: Vec<(usize, usize)> = self.edits.iter().map(|e| (e.0, e.1)).collect();
+
+        let mut shadowed = Vec::new();
+        for (idx, scope) in self.declarations() {
+            let t = &self.tokens[idx];
+            let name = text(src, t);
+            if !self.bindings.contains_key(name) || removed.iter().any(|&(s, e)| t.start >= s && t.end <= e) {
+                continue;
+            }
+            match scope {
+                Some((start, end)) => shadowed.push((name, start, end)),
+                None => return syntax_err(&format!("'{}' redeclares an imported binding", name)),
+            }
+        }
+
+        for idx in 0..self.tokens.len() {
+            let t = &self.tokens[idx];
+            if t.kind != Kind::Ident {
+                continue;
+            }
+            let expr = match self.bindings.get(text(src, t)) {
+                Some(expr) => expr.clone(),
+                None => continue,
+            };
+            if removed.iter().any(|&(s, e)| t.start >= s && t.end <= e) {
+                continue;
+            }
+            let name = text(src, t);
+            if shadowed.iter().any(|&(n, start, end)| n == name && idx >= start && idx < end) {
+                continue;
+            }
+
+            let prev = if idx > 0 { self.text(idx - 1) } else { "" };
+            let next = self.text(idx + 1);
+            if prev == "." {
+                continue;
+            }
+
+            let in_object = t.scope == b'{' && (prev == "{" || prev == ",");
+            if in_object && (next == ":" || next == "(") {
+                // Property key or method name
+                continue;
+            }
+
+            let replacement = if in_object && (next == "}" || next == ",") {
+                format!("{}: {}", text(src, t), expr)
+            } else {
+                expr
+            };
+            self.edits.push((t.start, t.end, replacement));
+        }
+        Ok(())
+    }
+
+    fn prologue(&self) -> String {
+        let mut out = String::from("Object.defineProperty(exports, \"__esModule\", { value: true });");
+
+        if self.requires.iter().any(|r| r.is_some()) {
+            out.push_str(INTEROP);
+        }
+
+        for (i, source) in self.requires.iter().enumerate() {
+            if let Some(source) = source {
+                out.push_str(&format!("var __esm${} = __esm$interop(require({}));", i, source));
+            }
+        }
+
+        for export in &self.exports {
+            let local = if export.binding {
+                self.bindings.get(&export.local).unwrap_or(&export.local)
+            } else {
+                &export.local
+            };
+            out.push_str(&format!(
+                "Object.defineProperty(exports, \"{}\", {{ enumerable: true, get: function () {{ return {}; }} }});",
+                export.name, local
+            ));
+        }
+
+        for var in &self.stars {
+            out.push_str(&format!("__esm$star({});", var));
+        }
+
+        out
+    }
+}
+
+static INTEROP: &str = "function __esm$interop(m) { if (m && m.__esModule) return m; \
+var ns = {}; if (m !== null && typeof m === 'object') for (var k in m) ns[k] = m[k]; \
+ns['default'] = m; return ns; }\
+function __esm$star(m) { Object.keys(m).forEach(function (k) { \
+if (k === 'default' || Object.prototype.hasOwnProperty.call(exports, k)) return; \
+Object.defineProperty(exports, k, { enumerable: true, get: function () { return m[k]; } }); }); }";
+
+fn member(var: &str, name: &str) -> String {
+    if name == "default" {
+        format!("{}[\"default\"]", var)
+    } else {
+        format!("{}.{}", var, name)
+    }
+}
+
+fn ends_value(src: &[u8], t: &Token) -> bool {
+    match t.kind {
+        Kind::Punct => match src[t.end - 1] {
+            b')' | b']' | b'}' => true,
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+fn starts_value(src: &[u8], t: &Token) -> bool {
+    match t.kind {
+        Kind::Punct => false,
+        Kind::Ident => !REGEX_KEYWORDS.contains(&text(src, t)),
+        _ => true,
+    }
+}
+
+/// Rewrite the ES module `source` to CommonJS.
+/// Sources without module declarations are returned unchanged
+pub fn transform(source: &str) -> Result<String> {
+    let tokens = tokenize(source)?;
+
+    let mut parser = Parser {
+        src: source,
+        tokens: &tokens,
+        pos: 0,
+        requires: Vec::new(),
+        bindings: HashMap::new(),
+        exports: Vec::new(),
+        stars: Vec::new(),
+        edits: Vec::new(),
+    };
+
+    parser.parse()?;
+
+    if parser.requires.is_empty() && parser.exports.is_empty() && parser.stars.is_empty() {
+        return Ok(source.to_owned());
+    }
+
+    parser.rewrite_references()?;
+
+    let mut edits = parser.edits.clone();
+    edits.sort_by_key(|e| e.0);
+
+    let mut out = parser.prologue();
+    let mut pos = 0;
+    for (start, end, replacement) in edits {
+        if start < pos {
+            continue;
+        }
+        out.push_str(&source[pos..start]);
+        out.push_str(&replacement);
+        pos = end;
+    }
+    out.push_str(&source[pos..]);
+
+    Ok(out)
+}
+
+/// The value of the string literal `literal`
+fn unquote(literal: &str) -> String {
+    let inner = &literal[1..literal.len() - 1];
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// The ids of the `require('id')` calls in `source`, with a string literal as argument.
+/// Calls of methods named `require`, eg. `module.require`, are not included
+pub fn requires(source: &str) -> Result<Vec<String>> {
+    let tokens = tokenize(source)?;
+    let src = source.as_bytes();
+    let is = |idx: usize, kind: Kind, s: &str| {
+        tokens
+            .get(idx)
+            .map(|t| t.kind == kind && (s.is_empty() || text(src, t) == s))
+            .unwrap_or(false)
+    };
+
+    let mut ids = Vec::new();
+    for i in 0..tokens.len() {
+        if is(i, Kind::Ident, "require")
+            && !(i > 0 && is(i - 1, Kind::Punct, "."))
+            && is(i + 1, Kind::Punct, "(")
+            && is(i + 2, Kind::Str, "")
+            && is(i + 3, Kind::Punct, ")")
+        {
+            let id = unquote(text(src, &tokens[i + 2]));
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::resolvers::MemoryResolver;
+    use super::super::{register, Builder};
+    use super::{requires, transform};
+    use duktape::prelude::*;
+
+    #[test]
+    fn keeps_scripts() {
+        let source = "var s = `import ${x} from` + /export/.source; // import x from 'y'\n";
+        assert_eq!(transform(source).unwrap(), source);
+    }
+
+    #[test]
+    fn keeps_lines() {
+        let out = transform("import {\n  a\n} from './a';\nexport default a;\n").unwrap();
+        assert_eq!(out.matches('\n').count(), 4);
+    }
+
+    #[test]
+    fn import_export() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add(
+                "/counter.mjs",
+                "export var count = 0;\n\
+                 export function inc() { count++; }\n\
+                 export default 'counter';\n",
+            )
+            .add("/cjs.js", "module.exports = { value: 42 };")
+            .add("/reexport.mjs", "export * from './counter';\nexport { value as answer } from './cjs';\n")
+            .add(
+                "/main.mjs",
+                "import name, * as ns from './counter';\n\
+                 import { count, inc as increment } from './reexport';\n\
+                 import cjs, { answer } from './reexport';\n\
+                 import plain from './cjs';\n\
+                 increment();\n\
+                 var o = { count, key: count };\n\
+                 export default [name, count, ns.count, o.count, o.key, answer, plain.value].join(',');\n",
+            );
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("mem", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx
+            .eval("var m = require('mem:///main.mjs'); m.__esModule + ':' + m['default']")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "true:counter,1,1,1,1,42,42");
+    }
+
+    #[test]
+    fn shadowing() {
+        let shadowing = [
+            "function f(a) { return a; }",
+            "function f() { var b = 1, a = 2; if (b) { return a; } }",
+            "var f = (x, { y: a }) => a;",
+            "var f = a => a, g = a => { return a; };",
+            "try {} catch (a) { a; }",
+            "for (const [k, a] of []) { a; }",
+            "{ let a = 1; a++; }",
+            "var f = function a() { return a; };",
+            "var o = { m() { var a; return a; } };",
+        ];
+        for source in shadowing.iter() {
+            let source = format!("import {{ a }} from './a';\n{}\na;\n", source);
+            let out = transform(&source).unwrap();
+            assert_eq!(out.matches("__esm$0.a").count(), 1, "{}", out);
+            assert!(out.ends_with("\n__esm$0.a;\n"), "{}", out);
+        }
+
+        let out = transform("import { a } from './a';\nif (a) { const a = 1; }\nfunction f() { { let a; } return a; }\n").unwrap();
+        assert_eq!(out.matches("__esm$0.a").count(), 2, "{}", out);
+
+        for source in ["function a() {}", "var a;", "let [b, a] = [];", "class a {}"].iter() {
+            let source = format!("import {{ a }} from './a';\n{}\n", source);
+            let err = transform(&source).err().unwrap();
+            assert!(err.to_string().contains("'a' redeclares"), "{}: {}", source, err);
+        }
+
+        let source = "import { a } from './a';\nfunction f(b = a, c) { if (a) { return { a, k: a, a() {} }; } }\n";
+        let out = transform(source).unwrap();
+        assert!(out.contains("b = __esm$0.a"), "{}", out);
+        assert!(out.contains("{ a: __esm$0.a, k: __esm$0.a, a() {} }"), "{}", out);
+    }
+
+    #[test]
+    fn regex_after_condition() {
+        let source = "if (x) /'/.test(s);\nwhile (y) /[)]/.exec(s);\nrequire('a');\nvar n = (a) / 2 / (b);\n";
+        assert_eq!(requires(source).unwrap(), vec!["a"]);
+
+        let out = transform("import x from './x';\nif (x) /x/.test(x);\n").unwrap();
+        assert!(out.contains("if (__esm$0[\"default\"]) /x/.test(__esm$0[\"default\"]);"), "{}", out);
+    }
+
+    #[test]
+    fn finds_requires() {
+        let source = "var a = require('./a'), b = require(\"b\");
+            // require('comment')
+            var s = \"require('string')\";
+            module.require('method'); require(dynamic); require('./a');
+            require('c\\'d');";
+        assert_eq!(requires(source).unwrap(), vec!["./a", "b", "c'd"]);
+    }
+}
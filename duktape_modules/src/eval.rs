@@ -1,10 +1,12 @@
-use super::commonjs::CommonJS;
+use super::commonjs::{cache_object, CommonJS};
 use super::error;
 use super::internal;
+use super::types::ModuleInfo;
 use duktape::prelude::*;
 use std::env;
 use std::fs;
 use std::path::Path;
+use std::time::Instant;
 
 pub trait CJSContext {
     fn eval_main<'a, T: AsRef<Path>>(&'a self, path: T) -> error::Result<Object<'a>>;
@@ -40,7 +42,10 @@ pub fn eval_main_script<'a, T: AsRef<Path>, S: AsRef<[u8]>>(
 
     let common = ctx.data()?.get::<CommonJS>().unwrap();
 
-    let ext = real_p.extension().unwrap();
+    let ext = match real_p.extension() {
+        Some(ext) => ext,
+        None => bail!(error::ErrorKind::Resolve(format!("no extension: {:?}", real_p))),
+    };
 
     let loader = match common.loaders.iter().find(|m| m.extension.as_str() == ext) {
         Some(loader) => loader,
@@ -55,7 +60,25 @@ pub fn eval_main_script<'a, T: AsRef<Path>, S: AsRef<[u8]>>(
     //     internal::eval_module(ctx, script.as_ref(), &mut module)?;
     // }
 
-    loader.loader.load(&ctx, &module, script.as_ref())?;
+    let id = real_p.to_str().unwrap_or("");
+
+    let info = ModuleInfo {
+        id: id.to_owned(),
+        protocol: "file".to_owned(),
+    };
+    let script = common.transform(script.as_ref().to_vec(), &info)?;
+
+    let started = Instant::now();
+    cache_object(ctx)?.set(id, &module);
+    common.record(id, "file", &loader.extension, script.len());
+
+    if let Err(e) = loader.loader.load(&ctx, &module, &script) {
+        CommonJS::invalidate(ctx, id)?;
+        return Err(e);
+    }
+
+    module.set("loaded", true);
+    common.loaded(id, started);
 
     Ok(module)
 }
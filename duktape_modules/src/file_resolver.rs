@@ -1,9 +1,30 @@
 use super::error::{ErrorKind, Result};
 use super::utils;
+use super::policy::FsPolicy;
 use super::ModuleResolver;
+use serde_json::Value;
+use std::env;
+use std::ffi::OsStr;
 use std::fs;
-use std::path::{self, PathBuf};
-pub struct FileResolver;
+use std::path::{Path, PathBuf};
+
+/// Export conditions matched, in order of preference
+static CONDITIONS: &[&str] = &["require", "node", "default"];
+
+pub struct FileResolver {
+    policy: FsPolicy,
+}
+
+impl FileResolver {
+    pub fn new() -> FileResolver {
+        FileResolver::with_policy(FsPolicy::new())
+    }
+
+    /// Create a resolver which only resolves and reads paths allowed by `policy`
+    pub fn with_policy(policy: FsPolicy) -> FileResolver {
+        FileResolver { policy }
+    }
+}
 
 macro_rules! to_string {
     ($id: ident) => {{
@@ -17,61 +38,305 @@ macro_rules! resolve_err {
     };
 }
 
-impl ModuleResolver for FileResolver {
-    fn read(&self, id: &str) -> Result<Vec<u8>> {
-        Ok(fs::read(id)?)
+impl FileResolver {
+    /// Resolve `path` as a file, trying each of the extensions in turn
+    fn load_as_file(&self, path: &Path, extensions: &[String]) -> Option<PathBuf> {
+        if path.is_file() {
+            return Some(path.to_path_buf());
+        }
+        extensions
+            .iter()
+            .map(|ext| PathBuf::from(format!("{}.{}", path.display(), ext)))
+            .find(|p| p.is_file())
     }
 
-    fn resolve(&self, id: &str, parent: &str, extensions: &[String]) -> Result<String> {
-        let parent = path::Path::new(parent);
-        let mut id = PathBuf::from(id);
+    /// Resolve `path` as a directory through `package.json` main or an index file
+    fn load_as_directory(&self, path: &Path, extensions: &[String]) -> Result<Option<PathBuf>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
 
-        if !id.is_absolute() {
-            let parent_dir = parent.parent();
-            if parent_dir.is_none() {
-                resolve_err!(id);
+        if let Some(pkg) = self.read_package(path)? {
+            if let Some(main) = pkg.get("main").and_then(|m| m.as_str()) {
+                let main = utils::join(path, main)?;
+                if let Some(found) = self.load_as_file(&main, extensions) {
+                    return Ok(Some(found));
+                }
+                if let Some(found) = self.load_as_index(&main, extensions) {
+                    return Ok(Some(found));
+                }
             }
+        }
+
+        Ok(self.load_as_index(path, extensions))
+    }
 
-            id = utils::join(parent_dir.unwrap(), id.to_str().unwrap())?;
+    fn load_as_index(&self, path: &Path, extensions: &[String]) -> Option<PathBuf> {
+        extensions
+            .iter()
+            .map(|ext| path.join(format!("index.{}", ext)))
+            .find(|p| p.is_file())
+    }
+
+    fn read_package(&self, dir: &Path) -> Result<Option<Value>> {
+        let path = dir.join("package.json");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let path = self.policy.check_read(&path)?;
+        let content = fs::read(&path)?;
+        match serde_json::from_slice(&content) {
+            Ok(pkg) => Ok(Some(pkg)),
+            Err(e) => Err(ErrorKind::Package(to_string!(path), e.to_string()).into()),
         }
+    }
 
-        if !id.exists() && id.extension().is_none() {
-            let mut found = false;
-            for ext in extensions {
-                let mut path = id.clone();
-                path.set_extension(ext);
-                if path.exists() {
-                    id = path;
-                    found = true;
-                    break;
-                }
+    fn load_relative(&self, path: &Path, extensions: &[String]) -> Result<PathBuf> {
+        if let Some(found) = self.load_as_file(path, extensions) {
+            return Ok(found);
+        }
+        if let Some(found) = self.load_as_directory(path, extensions)? {
+            return Ok(found);
+        }
+        resolve_err!(path);
+    }
+
+    /// Look up a bare module id in the `node_modules` directories above `dir`
+    fn load_node_module(&self, id: &str, dir: &Path, extensions: &[String]) -> Result<PathBuf> {
+        let (name, subpath) = split_package(id);
+
+        for ancestor in dir.ancestors() {
+            if ancestor.file_name() == Some(OsStr::new("node_modules")) {
+                continue;
             }
-            if !found {
-                resolve_err!(id);
+            let modules = ancestor.join("node_modules");
+            if !modules.is_dir() || self.policy.check_read(&modules).is_err() {
+                continue;
             }
-        } else if !id.exists() {
-            resolve_err!(id);
-        } else if id.is_dir() {
-            let nid = id.join("index");
-            let mut found = false;
-            for ext in extensions {
-                let mut path = nid.clone();
-                path.set_extension(ext);
-                if path.exists() {
-                    id = path;
-                    found = true;
-                    break;
+
+            let pkg_dir = modules.join(name);
+            if !pkg_dir.exists() {
+                continue;
+            }
+
+            if let Some(pkg) = self.read_package(&pkg_dir)? {
+                if let Some(exports) = pkg.get("exports") {
+                    let target = match resolve_exports(exports, &subpath) {
+                        Some(target) => target,
+                        None => {
+                            return Err(ErrorKind::Resolve(format!(
+                                "'{}' is not exported from '{}'",
+                                subpath, name
+                            ))
+                            .into())
+                        }
+                    };
+                    let path = utils::join(&pkg_dir, &target)?;
+                    if path.is_file() {
+                        return Ok(path);
+                    }
+                    resolve_err!(path);
                 }
             }
-            if !found {
-                resolve_err!(id);
+
+            if subpath == "." {
+                if let Some(found) = self.load_as_directory(&pkg_dir, extensions)? {
+                    return Ok(found);
+                }
+            } else if let Ok(found) = self.load_relative(&pkg_dir.join(&subpath[2..]), extensions) {
+                return Ok(found);
             }
         }
 
+        Err(ErrorKind::Resolve(id.to_owned()).into())
+    }
+}
+
+impl ModuleResolver for FileResolver {
+    fn read(&self, id: &str) -> Result<Vec<u8>> {
+        let path = self.policy.check_read(id)?;
+        Ok(fs::read(path)?)
+    }
+
+    fn resolve(&self, id: &str, parent: &str, extensions: &[String]) -> Result<String> {
+        let parent = Path::new(parent);
+        let path = PathBuf::from(id);
+
+        let id = if path.is_absolute() {
+            self.policy.check_read(&path)?;
+            self.load_relative(&path, extensions)?
+        } else if id.starts_with("./") || id.starts_with("../") {
+            let parent_dir = parent.parent();
+            if parent_dir.is_none() {
+                resolve_err!(path);
+            }
+            let path = utils::join(parent_dir.unwrap(), id)?;
+            self.policy.check_read(&path)?;
+            self.load_relative(&path, extensions)?
+        } else {
+            let dir = match parent.parent() {
+                Some(dir) if parent.is_absolute() => dir.to_path_buf(),
+                _ => env::current_dir()?,
+            };
+            self.load_node_module(id, &dir, extensions)?
+        };
+
+        let id = self.policy.check_read(&id)?;
+
         Ok(id.to_str().unwrap().to_owned())
     }
 }
 
+/// Split a bare module id into the package name and the subpath within the package.
+/// Scoped packages (`@scope/name`) span two segments
+fn split_package(id: &str) -> (&str, String) {
+    let skip = if id.starts_with('@') { 1 } else { 0 };
+    match id.match_indices('/').nth(skip) {
+        Some((i, _)) => (&id[..i], format!(".{}", &id[i..])),
+        None => (id, ".".to_string()),
+    }
+}
+
+/// Resolve `subpath` through a package.json `exports` field
+fn resolve_exports(exports: &Value, subpath: &str) -> Option<String> {
+    let is_subpath_map = match exports {
+        Value::Object(map) => map.keys().any(|k| k.starts_with('.')),
+        _ => false,
+    };
+
+    if !is_subpath_map {
+        return if subpath == "." {
+            resolve_target(exports, None)
+        } else {
+            None
+        };
+    }
+
+    let map = exports.as_object().unwrap();
+
+    if let Some(target) = map.get(subpath) {
+        return resolve_target(target, None);
+    }
+
+    // Like node, the pattern with the longest prefix wins, then the longest pattern
+    let mut best: Option<(usize, &String, &Value)> = None;
+    for (key, target) in map {
+        if let Some(star) = key.find('*') {
+            let (prefix, suffix) = (&key[..star], &key[star + 1..]);
+            let matches = subpath.len() >= prefix.len() + suffix.len()
+                && subpath.starts_with(prefix)
+                && subpath.ends_with(suffix);
+            let better = best.map_or(true, |(len, best_key, _)| {
+                (prefix.len(), key.len()) > (len, best_key.len())
+            });
+            if matches && better {
+                best = Some((prefix.len(), key, target));
+            }
+        }
+    }
+
+    best.and_then(|(prefix, key, target)| {
+        let suffix = key.len() - prefix - 1;
+        resolve_target(target, Some(&subpath[prefix..subpath.len() - suffix]))
+    })
+}
+
+/// Resolve an export target; a path, an array of fallbacks or a conditions object
+fn resolve_target(target: &Value, pattern: Option<&str>) -> Option<String> {
+    match target {
+        Value::String(s) => Some(match pattern {
+            Some(p) => s.replace('*', p),
+            None => s.clone(),
+        }),
+        Value::Array(targets) => targets.iter().filter_map(|t| resolve_target(t, pattern)).next(),
+        Value::Object(conditions) => CONDITIONS
+            .iter()
+            .filter_map(|c| conditions.get(*c))
+            .filter_map(|t| resolve_target(t, pattern))
+            .next(),
+        _ => None,
+    }
+}
+
 pub fn file_resolver() -> Box<dyn ModuleResolver> {
-    return Box::new(FileResolver);
+    return Box::new(FileResolver::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ModuleResolver;
+    use super::{split_package, FileResolver};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    fn write<P: AsRef<Path>>(path: P, content: &str) {
+        let path = path.as_ref();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn package_names() {
+        assert_eq!(split_package("lodash"), ("lodash", ".".to_string()));
+        assert_eq!(split_package("lodash/fp"), ("lodash", "./fp".to_string()));
+        assert_eq!(split_package("@scope/pkg"), ("@scope/pkg", ".".to_string()));
+        assert_eq!(
+            split_package("@scope/pkg/lib/a"),
+            ("@scope/pkg", "./lib/a".to_string())
+        );
+    }
+
+    #[test]
+    fn node_modules() {
+        let root = env::temp_dir().join("duktape_modules_node_modules");
+        let modules = root.join("node_modules");
+        write(modules.join("main/package.json"), r#"{"main": "lib/main"}"#);
+        write(modules.join("main/lib/main.js"), "");
+        write(modules.join("main/util.js"), "");
+        write(modules.join("index/index.js"), "");
+        write(modules.join("@scope/pkg/index.js"), "");
+        write(
+            modules.join("exported/package.json"),
+            r#"{"exports": {".": {"require": "./cjs.js", "import": "./esm.js"}, "./features/*": "./src/*.js"}}"#,
+        );
+        write(modules.join("exported/cjs.js"), "");
+        write(modules.join("exported/private.js"), "");
+        write(modules.join("exported/src/a.js"), "");
+        write(
+            modules.join("patterns/package.json"),
+            r#"{"exports": {"./*": "./any/*.js", "./lib/*": "./lib/*.js", "./lib/internal/*": null}}"#,
+        );
+        write(modules.join("patterns/any/a.js"), "");
+        write(modules.join("patterns/lib/a.js"), "");
+        write(modules.join("patterns/lib/internal/a.js"), "");
+        write(root.join("app/src/main.js"), "");
+        write(root.join("app/lib/util.js"), "");
+
+        let resolver = FileResolver::new();
+        let exts = vec!["js".to_string()];
+        let parent = root.join("app/src/main.js");
+        let parent = parent.to_str().unwrap();
+        let resolve = |id: &str| {
+            resolver
+                .resolve(id, parent, &exts)
+                .map(|p| p.replace(modules.to_str().unwrap(), ""))
+        };
+
+        assert_eq!(resolve("main").unwrap(), "/main/lib/main.js");
+        assert_eq!(resolve("main/util").unwrap(), "/main/util.js");
+        assert_eq!(resolve("index").unwrap(), "/index/index.js");
+        assert_eq!(resolve("@scope/pkg").unwrap(), "/@scope/pkg/index.js");
+        assert_eq!(resolve("exported").unwrap(), "/exported/cjs.js");
+        assert_eq!(resolve("exported/features/a").unwrap(), "/exported/src/a.js");
+        assert!(resolve("exported/private").is_err());
+        assert!(resolve("missing").is_err());
+        assert_eq!(resolve("patterns/a").unwrap(), "/patterns/any/a.js");
+        assert_eq!(resolve("patterns/lib/a").unwrap(), "/patterns/lib/a.js");
+        assert!(resolve("patterns/lib/internal/a").is_err());
+        assert_eq!(
+            resolver.resolve("../lib/util", parent, &exts).unwrap(),
+            root.join("app/lib/util.js").to_str().unwrap()
+        );
+    }
 }
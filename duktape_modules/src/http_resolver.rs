@@ -0,0 +1,266 @@
+use super::error::{ErrorKind, Result};
+use super::ModuleResolver;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Fetches the content of remote modules
+pub trait Fetcher {
+    fn fetch(&self, url: &str) -> Result<Vec<u8>>;
+}
+
+impl<F: Fn(&str) -> Result<Vec<u8>>> Fetcher for F {
+    fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        self(url)
+    }
+}
+
+/// Resolves `https://` modules through a `Fetcher`.
+/// Fetched modules are stored in `cache`, named by the sha256 of their content,
+/// and the hash of each url is recorded in a lockfile.
+/// Modules in the lockfile are verified against the hash, when they are read.
+pub struct HttpResolver {
+    fetcher: Box<dyn Fetcher>,
+    cache: PathBuf,
+    lockfile: PathBuf,
+    lock: RefCell<Option<BTreeMap<String, String>>>,
+    offline: bool,
+}
+
+impl HttpResolver {
+    pub fn new<T: AsRef<Path>>(fetcher: Box<dyn Fetcher>, cache: T) -> HttpResolver {
+        let cache = cache.as_ref().to_path_buf();
+        HttpResolver {
+            fetcher,
+            lockfile: cache.join("lock.json"),
+            cache,
+            lock: RefCell::new(None),
+            offline: false,
+        }
+    }
+
+    /// Use the lockfile at `path`, instead of the one in the cache directory
+    pub fn lockfile<T: AsRef<Path>>(mut self, path: T) -> Self {
+        self.lockfile = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Only read modules from the cache, never fetch
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    fn locked(&self, url: &str) -> Result<Option<String>> {
+        let mut lock = self.lock.borrow_mut();
+        if lock.is_none() {
+            *lock = Some(if self.lockfile.exists() {
+                let content = fs::read_to_string(&self.lockfile)?;
+                match serde_json::from_str(&content) {
+                    Ok(lock) => lock,
+                    Err(e) => {
+                        return Err(ErrorKind::Syntax(format!(
+                            "invalid lockfile '{}': {}",
+                            self.lockfile.display(),
+                            e
+                        ))
+                        .into())
+                    }
+                }
+            } else {
+                BTreeMap::new()
+            });
+        }
+        Ok(lock.as_ref().unwrap().get(url).cloned())
+    }
+
+    fn lock(&self, url: &str, hash: &str) -> Result<()> {
+        let mut lock = self.lock.borrow_mut();
+        let lock = lock.get_or_insert_with(BTreeMap::new);
+        lock.insert(url.to_owned(), hash.to_owned());
+        if let Some(dir) = self.lockfile.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.lockfile, serde_json::to_string_pretty(&*lock).unwrap())?;
+        Ok(())
+    }
+
+    fn fetch(&self, url: &str) -> Result<(String, Vec<u8>)> {
+        if self.offline {
+            return Err(ErrorKind::Fetch(url.to_owned(), "not cached while offline".to_owned()).into());
+        }
+        let content = self.fetcher.fetch(url)?;
+        let hash = hash(&content);
+
+        fs::create_dir_all(&self.cache)?;
+        // Write to a temporary file first, so an interrupted write is never read from the cache
+        let tmp = self.cache.join(format!("{}.tmp", hash));
+        fs::write(&tmp, &content)?;
+        fs::rename(&tmp, self.cache.join(&hash))?;
+
+        Ok((hash, content))
+    }
+}
+
+fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Join `id` onto the url `parent`, following the rules of relative urls
+fn join_url(id: &str, parent: &str) -> Result<String> {
+    let rest = match parent.find("://") {
+        Some(idx) => &parent[idx + 3..],
+        None => return Err(ErrorKind::Resolve(id.to_owned()).into()),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+
+    let mut segments: Vec<&str> = if id.starts_with('/') {
+        Vec::new()
+    } else {
+        let dir = &path[..path.rfind('/').unwrap_or(0)];
+        dir.split('/').filter(|s| !s.is_empty()).collect()
+    };
+
+    for segment in id.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+
+    Ok(format!("https://{}/{}", host, segments.join("/")))
+}
+
+impl ModuleResolver for HttpResolver {
+    /// Resolves `host/path` ids from `https://host/path` requires,
+    /// and paths relative to a parent url
+    fn resolve(&self, id: &str, parent: &str, _extensions: &[String]) -> Result<String> {
+        if id.starts_with("./") || id.starts_with("../") || id.starts_with('/') {
+            join_url(id, parent)
+        } else {
+            let (host, path) = match id.find('/') {
+                Some(idx) => (&id[..idx], &id[idx..]),
+                None => (id, "/"),
+            };
+            join_url(path, &format!("https://{}/", host))
+        }
+    }
+
+    fn read(&self, id: &str) -> Result<Vec<u8>> {
+        match self.locked(id)? {
+            Some(locked) => {
+                let path = self.cache.join(&locked);
+                let content = if path.exists() {
+                    fs::read(path)?
+                } else {
+                    self.fetch(id)?.1
+                };
+                if hash(&content) != locked {
+                    return Err(ErrorKind::Integrity(id.to_owned()).into());
+                }
+                Ok(content)
+            }
+            None => {
+                let (hash, content) = self.fetch(id)?;
+                self.lock(id, &hash)?;
+                Ok(content)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::error::{ErrorKind, Result};
+    use super::super::{register, Builder};
+    use super::{join_url, HttpResolver};
+    use duktape::prelude::*;
+    use std::cell::Cell;
+    use std::env;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn context(resolver: HttpResolver) -> Context {
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("https", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+        ctx
+    }
+
+    #[test]
+    fn join_urls() {
+        assert_eq!(
+            join_url("./b.js", "https://example.com/lib/a.js").unwrap(),
+            "https://example.com/lib/b.js"
+        );
+        assert_eq!(
+            join_url("../b.js", "https://example.com/lib/a.js").unwrap(),
+            "https://example.com/b.js"
+        );
+        assert_eq!(
+            join_url("/b.js", "https://example.com/lib/a.js").unwrap(),
+            "https://example.com/b.js"
+        );
+    }
+
+    #[test]
+    fn fetch_and_cache() {
+        let cache = env::temp_dir().join("duktape_modules_http");
+        fs::remove_dir_all(&cache).unwrap_or(());
+
+        let fetches = Rc::new(Cell::new(0));
+        let counter = fetches.clone();
+        let fetcher = move |url: &str| -> Result<Vec<u8>> {
+            counter.set(counter.get() + 1);
+            match url {
+                "https://example.com/lib/main.js" => {
+                    Ok(b"module.exports = require('./dep.js') + 1;".to_vec())
+                }
+                "https://example.com/lib/dep.js" => Ok(b"module.exports = 41;".to_vec()),
+                _ => Err(ErrorKind::Fetch(url.to_owned(), "404".to_owned()).into()),
+            }
+        };
+
+        let ctx = context(HttpResolver::new(Box::new(fetcher), &cache));
+        let result: i32 = ctx
+            .eval("require('https://example.com/lib/main.js')")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, 42);
+        assert_eq!(fetches.get(), 2);
+
+        let lock = fs::read_to_string(cache.join("lock.json")).unwrap();
+        assert!(lock.contains("https://example.com/lib/dep.js"));
+
+        // Offline reads the cache
+        let offline = |url: &str| -> Result<Vec<u8>> { Err(ErrorKind::Fetch(url.to_owned(), "offline".to_owned()).into()) };
+        let ctx = context(HttpResolver::new(Box::new(offline), &cache).offline(true));
+        let result: i32 = ctx
+            .eval("require('https://example.com/lib/main.js')")
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, 42);
+        assert!(ctx.eval("require('https://example.com/other.js')").is_err());
+
+        // Tampered cache entries are rejected
+        for entry in fs::read_dir(&cache).unwrap() {
+            let path = entry.unwrap().path();
+            if path.file_name().unwrap() != "lock.json" {
+                fs::write(path, "module.exports = 0;").unwrap();
+            }
+        }
+        let ctx = context(HttpResolver::new(Box::new(offline), &cache).offline(true));
+        assert!(ctx.eval("require('https://example.com/lib/main.js')").is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use super::commonjs::build_require;
+#[cfg(feature = "coverage")]
+use super::coverage;
 use super::error::Result;
+use super::types::PROTOCOL_KEY;
 use duktape::prelude::*;
 use std::path::Path;
 use std::str;
@@ -37,15 +40,36 @@ pub fn push_module_object<'a, T: AsRef<Path>>(
 pub fn eval_module<'a>(ctx: &'a duktape::Context, script: &[u8], module: &Object) -> Result<()> {
     let s = str::from_utf8(script)?;
 
-    ctx.push_string("(function(exports,require,module,__filename,__dirname) {")
-        .push_string(s)
-        .push_string("\n})")
-        .concat(3)?;
+    #[cfg(feature = "coverage")]
+    let covered = coverage::instrument_module(ctx, s, module)?;
+    #[cfg(not(feature = "coverage"))]
+    let covered: Option<(String, Object)> = None;
+
+    match covered {
+        Some((ref wrapped, _)) => {
+            ctx.push_string(wrapped);
+        }
+        None => ctx
+            .push_string("(function(exports,require,module,__filename,__dirname) {")
+            .push_string(s)
+            .push_string("\n})")
+            .concat(3)?,
+    }
 
     ctx.push(module.get::<_, Ref>("fileName")?)?;
     ctx.compile(Compile::EVAL)?.call(0)?;
+    // The hits of covered modules are bound by the function around the wrapper
+    if let Some((_, hits)) = covered {
+        ctx.push(hits)?.call(1)?;
+    }
+
+    let protocol = if module.has(PROTOCOL_KEY) {
+        module.get::<_, String>(PROTOCOL_KEY)?
+    } else {
+        "file".to_string()
+    };
 
-    let require = build_require(ctx, &module.get::<_, String>("id")?)?;
+    let require = build_require(ctx, &module.get::<_, String>("id")?, &protocol)?;
 
     Ok(ctx.getp::<Function>()?.call::<_, ()>((
         module.get::<_, Ref>("exports")?,
@@ -4,19 +4,35 @@ extern crate duktape;
 #[macro_use]
 extern crate lazy_static;
 extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha2;
+extern crate toml;
 
+pub mod bundle;
 mod commonjs;
+#[cfg(feature = "coverage")]
+pub mod coverage;
 pub mod error;
+pub mod esm;
 mod eval;
 mod file_resolver;
+mod http_resolver;
 mod internal;
 pub mod loaders;
+mod memory_resolver;
+mod policy;
+mod source_map;
 mod types;
 pub mod utils;
+pub mod watcher;
 
-pub use self::commonjs::{Builder, CommonJS};
+pub use self::commonjs::{cache_key, Builder, CommonJS};
 pub use self::eval::*;
-pub use self::types::{ModuleLoader, ModuleResolver};
+pub use self::policy::FsPolicy;
+pub use self::source_map::SourceMap;
+pub use self::types::{ModuleInfo, ModuleLoader, ModuleRecord, ModuleResolver, NativeModule};
 
 static POLYFILLS: &'static [u8] = include_bytes!("polyfills.js");
 
@@ -26,6 +42,8 @@ pub mod require {
 
 pub mod resolvers {
     pub use super::file_resolver::*;
+    pub use super::http_resolver::*;
+    pub use super::memory_resolver::*;
 }
 
 pub fn register(ctx: &duktape::Context, mut builder: Builder) -> duktape::error::Result<bool> {
@@ -37,6 +55,10 @@ pub fn register(ctx: &duktape::Context, mut builder: Builder) -> duktape::error:
     ctx.push_bare_object()
         .push_bare_object()
         .put_prop_string(-2, "cache")
+        .push_bare_object()
+        .put_prop_string(-2, "extensions")
+        .push_bare_object()
+        .put_prop_string(-2, "resolvers")
         .put_prop_string(-2, types::KEY);
 
     ctx.pop(1);
@@ -46,18 +68,40 @@ pub fn register(ctx: &duktape::Context, mut builder: Builder) -> duktape::error:
     if builder.file_loader {
         builder.resolver(
             "file",
-            Box::new(file_resolver::FileResolver {}) as Box<dyn ModuleResolver>,
+            Box::new(file_resolver::FileResolver::with_policy(
+                builder.policy.clone(),
+            )) as Box<dyn ModuleResolver>,
         );
     }
 
     builder
         .loader("js", loaders::javascript())
-        .loader("json", loaders::json());
+        .loader("json", loaders::json())
+        .loader("mjs", loaders::esm())
+        .loader("toml", loaders::toml())
+        .loader("yaml", loaders::yaml())
+        .loader("yml", loaders::yaml())
+        .loader("txt", loaders::text())
+        .loader("bin", loaders::binary());
 
     ctx.data_mut()?.insert::<CommonJS>(builder.build());
 
+    // A hook installed before, eg. by the host, is called after remapping
+    let duktape: duktape::types::Object = ctx.get_global_string("Duktape").getp()?;
+    let previous = if duktape.has("errCreate") {
+        Some(duktape.get::<_, duktape::types::Ref>("errCreate")?)
+    } else {
+        None
+    };
+    duktape.set("errCreate", (1, source_map::remap_error));
+    if let Some(previous) = previous {
+        duktape
+            .get::<_, duktape::types::Object>("errCreate")?
+            .set(source_map::PREVIOUS_HOOK_KEY, previous);
+    }
+
     ctx.push_global_object()
-        .push(commonjs::build_require(ctx, "")?)?
+        .push(commonjs::build_require(ctx, "", "file")?)?
         .put_prop_string(-2, "require")
         .pop(1);
 
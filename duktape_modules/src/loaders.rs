@@ -1,6 +1,7 @@
-use super::error::Result;
+use super::error::{ErrorKind, Result};
+use super::esm;
 use super::internal;
-use super::types::ModuleLoader;
+use super::types::{ModuleInfo, ModuleLoader};
 use duktape::{
     types::{Object, Ref},
     Context,
@@ -13,17 +14,95 @@ impl ModuleLoader for JavascriptLoader {
         internal::eval_module(ctx, buffer, module)?;
         Ok(())
     }
+
+    fn compile(&self, _ctx: &Context, _info: &ModuleInfo, buffer: &[u8]) -> Result<Option<String>> {
+        Ok(Some(str::from_utf8(buffer)?.to_owned()))
+    }
+}
+
+/// Loads ES modules by rewriting `import` and `export` onto CommonJS
+pub struct EsmLoader;
+
+impl ModuleLoader for EsmLoader {
+    fn load(&self, ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()> {
+        let source = esm::transform(str::from_utf8(buffer)?)?;
+        internal::eval_module(ctx, source.as_bytes(), module)?;
+        Ok(())
+    }
+
+    fn compile(&self, _ctx: &Context, _info: &ModuleInfo, buffer: &[u8]) -> Result<Option<String>> {
+        Ok(Some(esm::transform(str::from_utf8(buffer)?)?))
+    }
 }
 
 pub struct JsonLoader;
 
 impl ModuleLoader for JsonLoader {
-    fn load(&self, ctx: &Context, _module: &Object, buffer: &[u8]) -> Result<()> {
-        let o = ctx.get_global_string("JSON").getp::<Object>()?;
-        let json = str::from_utf8(buffer)?;
-        o.call::<_, _, Ref>("parse", json)?.push();
+    fn load(&self, ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()> {
+        set_json(ctx, module, str::from_utf8(buffer)?)
+    }
+}
+
+pub struct TomlLoader;
+
+impl ModuleLoader for TomlLoader {
+    fn load(&self, ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()> {
+        let value: toml::Value = match toml::from_slice(buffer) {
+            Ok(value) => value,
+            Err(e) => return Err(ErrorKind::Syntax(e.to_string()).into()),
+        };
+        set_json(ctx, module, &to_json(&value)?)
+    }
+}
+
+pub struct YamlLoader;
+
+impl ModuleLoader for YamlLoader {
+    fn load(&self, ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()> {
+        let value: serde_yaml::Value = match serde_yaml::from_slice(buffer) {
+            Ok(value) => value,
+            Err(e) => return Err(ErrorKind::Syntax(e.to_string()).into()),
+        };
+        set_json(ctx, module, &to_json(&value)?)
+    }
+}
+
+/// Exports the content as a string
+pub struct TextLoader;
+
+impl ModuleLoader for TextLoader {
+    fn load(&self, _ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()> {
+        module.set("exports", str::from_utf8(buffer)?);
+        Ok(())
+    }
+}
+
+/// Exports the content as a `Buffer`
+pub struct BinaryLoader;
+
+impl ModuleLoader for BinaryLoader {
+    fn load(&self, ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()> {
+        module.set("exports", ctx.push_node_buffer(buffer).getp::<Ref>()?);
         Ok(())
     }
+
+    fn compile(&self, _ctx: &Context, _info: &ModuleInfo, buffer: &[u8]) -> Result<Option<String>> {
+        let bytes: Vec<String> = buffer.iter().map(|b| b.to_string()).collect();
+        Ok(Some(format!("module.exports = new Buffer([{}]);", bytes.join(","))))
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_string(value) {
+        Ok(json) => Ok(json),
+        Err(e) => Err(ErrorKind::Syntax(e.to_string()).into()),
+    }
+}
+
+fn set_json(ctx: &Context, module: &Object, json: &str) -> Result<()> {
+    let o = ctx.get_global_string("JSON").getp::<Object>()?;
+    module.set("exports", o.call::<_, _, Ref>("parse", json)?);
+    Ok(())
 }
 
 pub fn javascript() -> Box<dyn ModuleLoader> {
@@ -33,3 +112,63 @@ pub fn javascript() -> Box<dyn ModuleLoader> {
 pub fn json() -> Box<dyn ModuleLoader> {
     Box::new(JsonLoader {})
 }
+
+pub fn esm() -> Box<dyn ModuleLoader> {
+    Box::new(EsmLoader {})
+}
+
+pub fn toml() -> Box<dyn ModuleLoader> {
+    Box::new(TomlLoader {})
+}
+
+pub fn yaml() -> Box<dyn ModuleLoader> {
+    Box::new(YamlLoader {})
+}
+
+pub fn text() -> Box<dyn ModuleLoader> {
+    Box::new(TextLoader {})
+}
+
+pub fn binary() -> Box<dyn ModuleLoader> {
+    Box::new(BinaryLoader {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::resolvers::MemoryResolver;
+    use super::super::{register, Builder};
+    use duktape::prelude::*;
+
+    #[test]
+    fn data_loaders() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/config.json", r#"{"name": "json"}"#)
+            .add("/config.toml", "name = \"toml\"\n[server]\nport = 8080\n")
+            .add("/config.yml", "name: yaml\nlist:\n  - 1\n  - 2\n")
+            .add("/readme.txt", "text")
+            .add("/data.bin", vec![1u8, 2, 3]);
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("mem", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        let result: String = ctx
+            .eval(
+                "var bin = require('mem:///data.bin');
+                [
+                    require('mem:///config.json').name,
+                    require('mem:///config').name,
+                    require('mem:///config.toml').server.port,
+                    require('mem:///config.yml').list.length,
+                    require('mem:///readme.txt'),
+                    Buffer.isBuffer(bin) && bin[2]
+                ].join(',')",
+            )
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "json,json,8080,2,text,3");
+    }
+}
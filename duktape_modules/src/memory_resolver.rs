@@ -0,0 +1,223 @@
+use super::error::{ErrorKind, Result};
+use super::ModuleResolver;
+use std::collections::HashMap;
+
+/// Resolves modules from a map of path to content.
+/// Paths are absolute and `/` separated, eg. `/lib/index.js`
+pub struct MemoryResolver {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> MemoryResolver {
+        MemoryResolver {
+            files: HashMap::new(),
+        }
+    }
+
+    /// Add a file
+    pub fn add<T: AsRef<str>, S: Into<Vec<u8>>>(&mut self, path: T, content: S) -> &mut Self {
+        self.files.insert(normalize("/", path.as_ref()), content.into());
+        self
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, id: &str, parent: &str, extensions: &[String]) -> Result<String> {
+        resolve(id, parent, extensions, |path| self.files.contains_key(path))
+    }
+
+    fn read(&self, id: &str) -> Result<Vec<u8>> {
+        match self.files.get(id) {
+            Some(content) => Ok(content.clone()),
+            None => Err(ErrorKind::Resolve(id.to_owned()).into()),
+        }
+    }
+}
+
+/// Resolves modules from a static table, usually built with `embed!`
+pub struct EmbeddedResolver {
+    files: &'static [(&'static str, &'static [u8])],
+}
+
+impl EmbeddedResolver {
+    pub fn new(files: &'static [(&'static str, &'static [u8])]) -> EmbeddedResolver {
+        EmbeddedResolver { files }
+    }
+
+    fn find(&self, path: &str) -> Option<&'static [u8]> {
+        self.files
+            .iter()
+            .find(|(p, _)| normalize("/", p) == path)
+            .map(|(_, content)| *content)
+    }
+}
+
+impl ModuleResolver for EmbeddedResolver {
+    fn resolve(&self, id: &str, parent: &str, extensions: &[String]) -> Result<String> {
+        resolve(id, parent, extensions, |path| self.find(path).is_some())
+    }
+
+    fn read(&self, id: &str) -> Result<Vec<u8>> {
+        match self.find(id) {
+            Some(content) => Ok(content.to_vec()),
+            None => Err(ErrorKind::Resolve(id.to_owned()).into()),
+        }
+    }
+}
+
+/// Build a table for `EmbeddedResolver`.
+/// The files are included with `include_bytes!`, so the source path is relative
+/// to the file invoking the macro.
+///
+/// ```ignore
+/// static FILES: &[(&str, &[u8])] = embed!("/index.js" => "../js/index.js");
+/// ```
+#[macro_export]
+macro_rules! embed {
+    ($($path: expr => $source: expr),* $(,)*) => {
+        &[$(($path, include_bytes!($source) as &'static [u8])),*]
+    };
+}
+
+/// Join `id` onto the directory `base` and remove `.` and `..` segments
+fn normalize(base: &str, id: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+
+    let joined = if id.starts_with('/') {
+        id.to_owned()
+    } else {
+        format!("{}/{}", base, id)
+    };
+
+    for seg in joined.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+fn resolve<F: Fn(&str) -> bool>(
+    id: &str,
+    parent: &str,
+    extensions: &[String],
+    exists: F,
+) -> Result<String> {
+    let dir = match parent.rfind('/') {
+        Some(idx) => &parent[..idx],
+        None => "/",
+    };
+
+    let path = normalize(dir, id);
+
+    if exists(&path) {
+        return Ok(path);
+    }
+
+    for ext in extensions {
+        let file = format!("{}.{}", path, ext);
+        if exists(&file) {
+            return Ok(file);
+        }
+    }
+
+    for ext in extensions {
+        let index = format!("{}/index.{}", path, ext);
+        if exists(&index) {
+            return Ok(index);
+        }
+    }
+
+    Err(ErrorKind::Resolve(path).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{register, Builder, CJSContext, CommonJS};
+    use super::{MemoryResolver, ModuleResolver};
+    use duktape::prelude::*;
+
+    #[test]
+    fn memory_resolve() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/lib/index.js", "")
+            .add("/lib/util.js", "")
+            .add("data.json", "{}");
+
+        let exts = vec!["js".to_string(), "json".to_string()];
+
+        assert_eq!(resolver.resolve("./lib", "", &exts).unwrap(), "/lib/index.js");
+        assert_eq!(
+            resolver.resolve("./util", "/lib/index.js", &exts).unwrap(),
+            "/lib/util.js"
+        );
+        assert_eq!(
+            resolver.resolve("../data", "/lib/index.js", &exts).unwrap(),
+            "/data.json"
+        );
+        assert!(resolver.resolve("./missing", "/lib/index.js", &exts).is_err());
+    }
+
+    #[test]
+    fn memory_module_graph() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/main.js", "module.exports = require('./lib').name;")
+            .add("/lib/index.js", "exports.name = require('./name');")
+            .add("/lib/name.js", "module.exports = 'memory';");
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("mem", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        let name: String = ctx
+            .require("mem:///main.js")
+            .unwrap()
+            .as_ref()
+            .get()
+            .unwrap();
+        assert_eq!(name, "memory");
+    }
+
+    #[test]
+    fn resolve_and_delete_cache() {
+        let mut resolver = MemoryResolver::new();
+        resolver
+            .add("/counter.js", "global.loads = (global.loads || 0) + 1;")
+            .add(
+                "/main.js",
+                "module.exports = function() { return require('./counter'); };",
+            );
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("mem", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        ctx.require("mem:///main.js").unwrap();
+        ctx.eval(
+            "var main = require('mem:///main.js');
+             main(); main();
+             var id = require.resolve('mem:///counter.js');
+             delete require.cache[id];
+             main();",
+        )
+        .unwrap();
+
+        let id: String = ctx.eval("id").unwrap().getp().unwrap();
+        assert_eq!(id, "mem:///counter.js");
+        let loads: i32 = ctx.eval("loads").unwrap().getp().unwrap();
+        assert_eq!(loads, 2);
+        assert!(!CommonJS::invalidate(&ctx, "/counter.js").unwrap());
+        assert!(CommonJS::invalidate(&ctx, "mem:///counter.js").unwrap());
+        assert!(!CommonJS::invalidate(&ctx, "mem:///counter.js").unwrap());
+    }
+}
@@ -0,0 +1,248 @@
+use super::error::{ErrorKind, Result};
+use duktape::Key;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Restricts which parts of the file system scripts may touch.
+///
+/// A default policy allows everything the process can reach.
+/// Once a root is allowed, every path must resolve (after `..` and
+/// symlinks have been followed) to a location inside one of the roots.
+/// Reads and writes have roots of their own, `allow` adds a root to both.
+#[derive(Clone, Debug, Default)]
+pub struct FsPolicy {
+    roots: Option<Vec<PathBuf>>,
+    write_roots: Option<Vec<PathBuf>>,
+    read_only: bool,
+}
+
+impl FsPolicy {
+    /// Creates an unrestricted policy
+    pub fn new() -> FsPolicy {
+        FsPolicy::default()
+    }
+
+    /// Creates a policy which denies everything until a root is allowed
+    pub fn restricted() -> FsPolicy {
+        FsPolicy {
+            roots: Some(Vec::new()),
+            write_roots: Some(Vec::new()),
+            read_only: false,
+        }
+    }
+
+    /// Allow access to `root` and everything below it
+    pub fn allow<T: AsRef<Path>>(&mut self, root: T) -> &mut Self {
+        self.allow_read(root.as_ref()).allow_write(root.as_ref())
+    }
+
+    /// Allow reading `root` and everything below it
+    pub fn allow_read<T: AsRef<Path>>(&mut self, root: T) -> &mut Self {
+        self.roots.get_or_insert_with(Vec::new).push(resolve_root(root.as_ref()));
+        self
+    }
+
+    /// Allow writing `root` and everything below it
+    pub fn allow_write<T: AsRef<Path>>(&mut self, root: T) -> &mut Self {
+        self.write_roots
+            .get_or_insert_with(Vec::new)
+            .push(resolve_root(root.as_ref()));
+        self
+    }
+
+    /// Allow reading everything, dropping the read roots
+    pub fn allow_read_all(&mut self) -> &mut Self {
+        self.roots = None;
+        self
+    }
+
+    /// Allow writing everything, dropping the write roots
+    pub fn allow_write_all(&mut self) -> &mut Self {
+        self.write_roots = None;
+        self
+    }
+
+    /// Deny all writes
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether reads are limited to the roots
+    pub fn is_restricted(&self) -> bool {
+        self.roots.is_some()
+    }
+
+    /// The roots of reads
+    pub fn roots(&self) -> &[PathBuf] {
+        match &self.roots {
+            Some(roots) => roots.as_slice(),
+            None => &[],
+        }
+    }
+
+    /// Check that `path` may be read, returning the resolved path
+    pub fn check_read<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        check(&self.roots, path.as_ref())
+    }
+
+    /// Check that `path` may be written, returning the resolved path
+    pub fn check_write<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        if self.read_only {
+            return Err(ErrorKind::PermissionDenied(to_string(path.as_ref())).into());
+        }
+        check(&self.write_roots, path.as_ref())
+    }
+}
+
+fn check(roots: &Option<Vec<PathBuf>>, path: &Path) -> Result<PathBuf> {
+    let roots = match roots {
+        Some(roots) => roots,
+        None => return Ok(path.to_path_buf()),
+    };
+
+    let resolved = match normalize(&absolute(path)?) {
+        Some(p) => resolve_links(&p),
+        None => return Err(ErrorKind::PermissionDenied(to_string(path)).into()),
+    };
+
+    if roots.iter().any(|root| resolved.starts_with(root)) {
+        Ok(resolved)
+    } else {
+        Err(ErrorKind::PermissionDenied(to_string(path)).into())
+    }
+}
+
+fn resolve_root(root: &Path) -> PathBuf {
+    match absolute(root) {
+        Ok(root) => resolve_links(&root),
+        Err(_) => root.to_path_buf(),
+    }
+}
+
+impl Key for FsPolicy {
+    type Value = Self;
+}
+
+fn to_string(path: &Path) -> String {
+    path.to_str().unwrap_or("").to_owned()
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(env::current_dir()?.join(path))
+    }
+}
+
+/// Lexically remove `.` and `..` components.
+/// Returns None if `..` would climb above the file system root
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for cmp in path.components() {
+        match cmp {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            c => out.push(c.as_os_str()),
+        }
+    }
+    Some(out)
+}
+
+/// Follow symlinks for the longest existing prefix of `path`,
+/// so a link inside a root can not point outside of it
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut existing = path.to_path_buf();
+    let mut rest = Vec::new();
+
+    loop {
+        if let Ok(real) = existing.canonicalize() {
+            let mut out = real;
+            for cmp in rest.iter().rev() {
+                out.push(cmp);
+            }
+            return out;
+        }
+
+        match (existing.file_name(), existing.parent()) {
+            (Some(name), Some(parent)) => {
+                rest.push(name.to_os_string());
+                existing = parent.to_path_buf();
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FsPolicy;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn unrestricted() {
+        let policy = FsPolicy::new();
+        assert!(policy.check_read("/etc/passwd").is_ok());
+        assert!(policy.check_write("/tmp/file").is_ok());
+    }
+
+    #[test]
+    fn roots_and_escapes() {
+        let root = env::temp_dir().join("duktape_modules_policy_roots");
+        fs::create_dir_all(root.join("sub")).unwrap();
+
+        let mut policy = FsPolicy::restricted();
+        policy.allow(&root);
+
+        assert!(policy.check_read(root.join("sub/file.js")).is_ok());
+        assert!(policy.check_read(root.join("sub/../file.js")).is_ok());
+        assert!(policy.check_read(root.join("../file.js")).is_err());
+        assert!(policy.check_read("/etc/passwd").is_err());
+
+        policy.read_only(true);
+        assert!(policy.check_write(root.join("sub/file.js")).is_err());
+    }
+
+    #[test]
+    fn separate_write_roots() {
+        let root = env::temp_dir().join("duktape_modules_policy_write");
+        fs::create_dir_all(root.join("out")).unwrap();
+
+        let mut policy = FsPolicy::restricted();
+        policy.allow_read(&root).allow_write(root.join("out"));
+        assert!(policy.check_read(root.join("in.txt")).is_ok());
+        assert!(policy.check_write(root.join("in.txt")).is_err());
+        assert!(policy.check_write(root.join("out/file.txt")).is_ok());
+        assert!(policy.check_read("/etc/passwd").is_err());
+
+        let mut policy = FsPolicy::restricted();
+        policy.allow_write_all();
+        assert!(policy.check_write(root.join("in.txt")).is_ok());
+        assert!(policy.check_read(root.join("in.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escape() {
+        let root = env::temp_dir().join("duktape_modules_policy_links");
+        fs::create_dir_all(&root).unwrap();
+        let link = root.join("escape");
+        fs::remove_file(&link).unwrap_or(());
+        std::os::unix::fs::symlink("/", &link).unwrap();
+
+        let mut policy = FsPolicy::restricted();
+        policy.allow(&root);
+
+        assert!(policy.check_read(link.join("etc/passwd")).is_err());
+    }
+}
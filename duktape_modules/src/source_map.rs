@@ -0,0 +1,286 @@
+use super::commonjs::CommonJS;
+use super::error::{ErrorKind, Result};
+use duktape::prelude::*;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+lazy_static! {
+    // Duktape reports the line of stack frames, but no column, so frames are
+    // remapped through the first segment of the generated line
+    static ref FRAME_RE: Regex = Regex::new(r"\(([^()]+):(\d+)\)").unwrap();
+}
+
+/// The `Duktape.errCreate` hook replaced by `remap_error`, stored on the new hook
+pub(crate) static PREVIOUS_HOOK_KEY: &'static [u8] = b"\xFFpreviousHook";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    column: u32,
+    source: usize,
+    line: u32,
+}
+
+/// A decoded (version 3) source map
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    sources: Vec<String>,
+    lines: Vec<Vec<Segment>>,
+}
+
+impl SourceMap {
+    /// Parse the JSON representation of a source map
+    pub fn from_json(json: &str) -> Result<SourceMap> {
+        let value: Value = match serde_json::from_str(json) {
+            Ok(value) => value,
+            Err(e) => return Err(ErrorKind::Syntax(format!("invalid source map: {}", e)).into()),
+        };
+
+        let sources = match value.get("sources").and_then(|s| s.as_array()) {
+            Some(sources) => sources
+                .iter()
+                .map(|s| s.as_str().unwrap_or("").to_owned())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mappings = value.get("mappings").and_then(|m| m.as_str()).unwrap_or("");
+
+        Ok(SourceMap {
+            sources,
+            lines: decode_mappings(mappings)?,
+        })
+    }
+
+    /// Find the original source and line of the (1-based) generated `line` and `column`
+    pub fn lookup(&self, line: u32, column: u32) -> Option<(&str, u32)> {
+        if line == 0 {
+            return None;
+        }
+        let segments = self.lines.get(line as usize - 1)?;
+        let segment = segments
+            .iter()
+            .rev()
+            .find(|s| s.column <= column)
+            .or_else(|| segments.first())?;
+        let source = self.sources.get(segment.source)?;
+        Some((source.as_str(), segment.line.checked_add(1)?))
+    }
+}
+
+fn invalid_mappings() -> super::error::Error {
+    ErrorKind::Syntax("invalid source map mappings".to_owned()).into()
+}
+
+/// Add the decoded `delta` to `value`, rejecting overflows and values out of the range of `T`
+fn add_delta<T: TryFrom<i64>>(value: &mut i64, delta: i64) -> Result<T> {
+    *value = value.checked_add(delta).ok_or_else(invalid_mappings)?;
+    T::try_from(*value).map_err(|_| invalid_mappings())
+}
+
+fn decode_vlq(chars: &mut ::std::iter::Peekable<::std::str::Chars>) -> Result<i64> {
+    let mut value: i64 = 0;
+    let mut shift = 0;
+    loop {
+        let digit = match chars.next() {
+            Some(c @ 'A'..='Z') => c as i64 - 'A' as i64,
+            Some(c @ 'a'..='z') => c as i64 - 'a' as i64 + 26,
+            Some(c @ '0'..='9') => c as i64 - '0' as i64 + 52,
+            Some('+') => 62,
+            Some('/') => 63,
+            _ => return Err(invalid_mappings()),
+        };
+        // More digits would overflow the value
+        if shift >= 60 {
+            return Err(invalid_mappings());
+        }
+        value += (digit & 31) << shift;
+        shift += 5;
+        if digit & 32 == 0 {
+            break;
+        }
+    }
+    Ok(if value & 1 == 1 { -(value >> 1) } else { value >> 1 })
+}
+
+fn decode_mappings(mappings: &str) -> Result<Vec<Vec<Segment>>> {
+    let mut lines = Vec::new();
+    let (mut source, mut line) = (0i64, 0i64);
+
+    for encoded in mappings.split(';') {
+        let mut segments = Vec::new();
+        let mut column = 0i64;
+
+        for segment in encoded.split(',').filter(|s| !s.is_empty()) {
+            let mut chars = segment.chars().peekable();
+            let column_value = add_delta(&mut column, decode_vlq(&mut chars)?)?;
+            if chars.peek().is_none() {
+                continue;
+            }
+            let source_value = add_delta(&mut source, decode_vlq(&mut chars)?)?;
+            let line_value = add_delta(&mut line, decode_vlq(&mut chars)?)?;
+            // Original column and name are not used
+            while chars.peek().is_some() {
+                decode_vlq(&mut chars)?;
+            }
+            segments.push(Segment {
+                column: column_value,
+                source: source_value,
+                line: line_value,
+            });
+        }
+
+        lines.push(segments);
+    }
+
+    Ok(lines)
+}
+
+fn remap(ctx: &Context, err: &Object) -> duktape::error::Result<()> {
+    let common = ctx.data()?.get::<CommonJS>().unwrap();
+    if !common.has_source_maps() {
+        return Ok(());
+    }
+
+    let define = |name: &str, value: Ref| -> duktape::error::Result<()> {
+        let descriptor: Object = ctx.create()?;
+        descriptor
+            .set("value", value)
+            .set("writable", true)
+            .set("configurable", true);
+        ctx.get_global_string("Object")
+            .getp::<Object>()?
+            .call::<_, _, ()>("defineProperty", (err.clone(), name, descriptor))
+    };
+
+    if let (Ok(file), Ok(line)) = (err.get::<_, String>("fileName"), err.get::<_, u32>("lineNumber")) {
+        if let Some((file, line)) = common.original_position(&file, line) {
+            ctx.push(file)?;
+            define("fileName", ctx.getp()?)?;
+            ctx.push(line)?;
+            define("lineNumber", ctx.getp()?)?;
+        }
+    }
+
+    if let Ok(stack) = err.get::<_, String>("stack") {
+        let stack = FRAME_RE.replace_all(&stack, |caps: &Captures| {
+            let line = caps[2].parse().unwrap_or(0);
+            match common.original_position(&caps[1], line) {
+                Some((file, line)) => format!("({}:{})", file, line),
+                None => caps[0].to_owned(),
+            }
+        });
+        ctx.push(stack.as_ref())?;
+        define("stack", ctx.getp()?)?;
+    }
+
+    Ok(())
+}
+
+/// `Duktape.errCreate` hook, remapping `fileName`, `lineNumber` and `stack`
+/// of errors through the source maps registered with `CommonJS`.
+/// Lines are remapped, columns are left as they are
+pub(crate) fn remap_error(ctx: &Context) -> duktape::error::Result<i32> {
+    if ctx.is(Type::Object, 0) {
+        let err: Object = ctx.get(0)?;
+        // The original error is better than an error from remapping
+        remap(ctx, &err).unwrap_or(());
+    }
+
+    let hook: Object = ctx.push_current_function().getp()?;
+    if hook.has(PREVIOUS_HOOK_KEY) {
+        hook.get::<_, Ref>(PREVIOUS_HOOK_KEY)?.push();
+        ctx.dup(0).call(1)?;
+    } else {
+        ctx.dup(0);
+    }
+    Ok(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::resolvers::MemoryResolver;
+    use super::super::{register, Builder, CommonJS};
+    use super::{Segment, SourceMap};
+    use duktape::prelude::*;
+
+    static MAP: &str = r#"{
+        "version": 3,
+        "sources": ["/original.es6"],
+        "mappings": ";AAAA;AAEA;AAIA"
+    }"#;
+
+    #[test]
+    fn decode() {
+        let map = SourceMap::from_json(MAP).unwrap();
+        assert_eq!(map.lookup(1, 0), None);
+        assert_eq!(map.lookup(2, 0), Some(("/original.es6", 1)));
+        assert_eq!(map.lookup(3, 0), Some(("/original.es6", 3)));
+        assert_eq!(map.lookup(4, 10), Some(("/original.es6", 7)));
+        assert_eq!(map.lookup(5, 0), None);
+    }
+
+    #[test]
+    fn vlq_overflow() {
+        let map = r#"{"version": 3, "sources": ["/a.js"], "mappings": "AAAA,gggggggggggggA"}"#;
+        assert!(SourceMap::from_json(map).is_err());
+
+        // Negative columns, sources and lines, and lines out of range
+        for mappings in ["DAAA", "ADAA", "AADA", "AAggggggIA"].iter() {
+            let map = format!(r#"{{"version": 3, "sources": ["/a.js"], "mappings": "{}"}}"#, mappings);
+            assert!(SourceMap::from_json(&map).is_err(), "{}", mappings);
+        }
+
+        let map = SourceMap {
+            sources: vec!["/a.js".to_owned()],
+            lines: vec![vec![Segment {
+                column: 0,
+                source: 0,
+                line: u32::MAX,
+            }]],
+        };
+        assert_eq!(map.lookup(1, 0), None);
+    }
+
+    #[test]
+    fn chain_err_create() {
+        let ctx = Context::new().unwrap();
+        ctx.eval("Duktape.errCreate = function (e) { e.hooked = true; return e; };")
+            .unwrap();
+        register(&ctx, Builder::new()).unwrap();
+
+        let hooked: bool = ctx.eval("new Error('fail').hooked").unwrap().getp().unwrap();
+        assert!(hooked);
+    }
+
+    #[test]
+    fn remap_errors() {
+        let mut resolver = MemoryResolver::new();
+        resolver.add(
+            "/generated.js",
+            "\nexports.fail = function () {\n\n    throw new Error('fail');\n};\n",
+        );
+
+        let ctx = Context::new().unwrap();
+        let mut builder = Builder::new();
+        builder.resolver("mem", Box::new(resolver));
+        register(&ctx, builder).unwrap();
+
+        ctx.data()
+            .unwrap()
+            .get::<CommonJS>()
+            .unwrap()
+            .add_source_map("/generated.js", SourceMap::from_json(MAP).unwrap());
+
+        let result: String = ctx
+            .eval(
+                "try { require('mem:///generated.js').fail() } catch (e) {
+                    e.fileName + ':' + e.lineNumber + ' ' + (e.stack.indexOf('(/original.es6:7)') > -1)
+                }",
+            )
+            .unwrap()
+            .getp()
+            .unwrap();
+        assert_eq!(result, "/original.es6:7 true");
+    }
+}
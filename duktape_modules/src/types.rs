@@ -1,5 +1,6 @@
 use super::error::Result;
 use duktape::{types::Object, Context};
+use std::time::Duration;
 
 pub trait ModuleResolver {
     fn resolve(&self, id: &str, parent: &str, extensions: &[String]) -> Result<String>;
@@ -8,7 +9,60 @@ pub trait ModuleResolver {
 
 pub trait ModuleLoader {
     fn load(&self, ctx: &Context, module: &Object, buffer: &[u8]) -> Result<()>;
+
+    /// The javascript module body equivalent to loading `buffer`, used by the bundler.
+    /// Modules of loaders returning `None` are loaded when bundling,
+    /// and their exports embedded as JSON
+    fn compile(&self, _ctx: &Context, _info: &ModuleInfo, _buffer: &[u8]) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// A builtin module implemented in Rust, registered with `Builder::native_module`
+pub trait NativeModule {
+    /// Add the exports of the module to `exports`
+    fn exports(&self, ctx: &Context, exports: &Object) -> duktape::error::Result<()>;
+
+    /// Javascript evaluated as the module body after `exports` is populated,
+    /// to augment the native exports
+    fn source(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl<F: Fn(&Context, &Object) -> duktape::error::Result<()>> NativeModule for F {
+    fn exports(&self, ctx: &Context, exports: &Object) -> duktape::error::Result<()> {
+        self(ctx, exports)
+    }
+}
+
+/// Describes the module passed to transforms
+#[derive(Debug, Clone)]
+pub struct ModuleInfo {
+    /// The resolved id, eg. the path for files
+    pub id: String,
+    pub protocol: String,
+}
+
+/// A module loaded by `require`, see `CommonJS::graph`
+#[derive(Debug, Clone)]
+pub struct ModuleRecord {
+    /// The key in `require.cache`, see `cache_key`
+    pub id: String,
+    pub protocol: String,
+    /// The extension of the loader, or `builtin` for builtin modules
+    pub loader: String,
+    /// The modules requiring this module, in the order they required it
+    pub parents: Vec<String>,
+    /// The modules required by this module
+    pub dependencies: Vec<String>,
+    /// Time spent loading the module, including loading its dependencies
+    pub load_time: Duration,
+    /// Size of the source in bytes
+    pub size: usize,
 }
 
 pub static KEY: &'static [u8] = b"commonjs";
 pub static MODULE_ID_KEY: &'static [u8] = b"\xFFmoduleId";
+pub static PROTOCOL_KEY: &'static [u8] = b"\xFFprotocol";
+pub static MTIME_KEY: &'static [u8] = b"\xFFmtime";
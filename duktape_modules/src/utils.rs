@@ -13,7 +13,7 @@ pub fn join<T: AsRef<Path>, S: AsRef<str>>(base: T, cmp: S) -> Result<PathBuf> {
             Some(parent) => parent.to_path_buf(),
             None => return Err(ErrorKind::Resolve("".to_string()).into()),
         };
-        path = path.trim_left_matches("..").to_string();
+        path = path["../".len()..].to_string();
     }
 
     Ok(base.join(path))
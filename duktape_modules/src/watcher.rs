@@ -0,0 +1,163 @@
+use super::commonjs::{cache_object, CommonJS};
+use super::types::{MTIME_KEY, PROTOCOL_KEY};
+use duktape::error::Result;
+use duktape::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Check the loaded file modules for changes on disk and invalidate the changed ones,
+/// with the modules requiring them, so they are loaded again on the next `require`.
+/// Returns the ids of the invalidated modules, the changed ones first.
+///
+/// Call it from the host's event loop, eg. before handling a request.
+/// Changes are detected against the mtime of the file when the module was loaded
+pub fn poll(ctx: &Context) -> Result<Vec<String>> {
+    let mut changed = Vec::new();
+
+    for id in CommonJS::cached(ctx)? {
+        let module: Object = cache_object(ctx)?.get(&id)?;
+        if !module.has(PROTOCOL_KEY) || module.get::<_, String>(PROTOCOL_KEY)? != "file" {
+            continue;
+        }
+        if !module.has(MTIME_KEY) {
+            continue;
+        }
+
+        // A removed file is invalidated too, a later require reports the error
+        if mtime(&id) != Some(module.get::<_, String>(MTIME_KEY)?) {
+            changed.push(id);
+        }
+    }
+
+    // The parents holds on to the exports of the changed modules
+    if let Some(common) = ctx.data()?.get::<CommonJS>() {
+        let graph = common.graph();
+        let mut i = 0;
+        while i < changed.len() {
+            if let Some(record) = graph.iter().find(|m| m.id == changed[i]) {
+                for parent in &record.parents {
+                    if !changed.contains(parent) {
+                        changed.push(parent.clone());
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+
+    for id in &changed {
+        CommonJS::invalidate(ctx, id)?;
+    }
+
+    Ok(changed)
+}
+
+/// The mtime of `path` in nanoseconds, as numbers would lose precision
+fn mtime(path: &str) -> Option<String> {
+    let mtime = fs::metadata(Path::new(path)).and_then(|m| m.modified()).ok()?;
+    mtime.duration_since(UNIX_EPOCH).ok().map(|d| d.as_nanos().to_string())
+}
+
+/// Remember the mtime of the file of `module`, before it is read
+pub(crate) fn record_mtime(module: &Object, id: &str) {
+    if let Some(mtime) = mtime(id) {
+        module.set(MTIME_KEY, mtime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{register, Builder, CJSContext};
+    use super::poll;
+    use duktape::prelude::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    /// Write `content` to `path` with an mtime `secs` seconds in the past,
+    /// as some file systems only stores mtime with second precision
+    fn write(path: &Path, content: &str, secs: u64) {
+        fs::write(path, content).unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(secs);
+        File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+    }
+
+    #[test]
+    fn reload_changed() {
+        let dir = env::temp_dir().join("duktape_modules_watcher");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("value.js");
+        write(&file, "module.exports = 1;", 10);
+
+        let ctx = Context::new().unwrap();
+        register(&ctx, Builder::new()).unwrap();
+
+        let id = file.to_str().unwrap();
+        let value: i32 = ctx.require(id).unwrap().as_ref().get().unwrap();
+        assert_eq!(value, 1);
+        assert!(poll(&ctx).unwrap().is_empty());
+
+        write(&file, "module.exports = 2;", 5);
+
+        assert_eq!(poll(&ctx).unwrap(), vec![id.to_string()]);
+        let value: i32 = ctx.require(id).unwrap().as_ref().get().unwrap();
+        assert_eq!(value, 2);
+        assert!(poll(&ctx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn changed_before_first_poll() {
+        let dir = env::temp_dir().join("duktape_modules_watcher_first_poll");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("value.js");
+        write(&file, "module.exports = 1;", 10);
+
+        let ctx = Context::new().unwrap();
+        register(&ctx, Builder::new()).unwrap();
+
+        let id = file.to_str().unwrap();
+        let value: i32 = ctx.require(id).unwrap().as_ref().get().unwrap();
+        assert_eq!(value, 1);
+
+        write(&file, "module.exports = 2;", 5);
+
+        assert_eq!(poll(&ctx).unwrap(), vec![id.to_string()]);
+        let value: i32 = ctx.require(id).unwrap().as_ref().get().unwrap();
+        assert_eq!(value, 2);
+    }
+
+    #[test]
+    fn reload_parents() {
+        let dir = env::temp_dir().join("duktape_modules_watcher_parents");
+        fs::create_dir_all(&dir).unwrap();
+        let (main, lib, value, other) = (
+            dir.join("main.js"),
+            dir.join("lib.js"),
+            dir.join("value.js"),
+            dir.join("other.js"),
+        );
+        write(&main, "module.exports = require('./lib') + require('./other');", 10);
+        write(&lib, "module.exports = require('./value') * 10;", 10);
+        write(&value, "module.exports = 1;", 10);
+        write(&other, "module.exports = 100;", 10);
+
+        let ctx = Context::new().unwrap();
+        register(&ctx, Builder::new()).unwrap();
+
+        let id = main.to_str().unwrap();
+        let result: i32 = ctx.require(id).unwrap().as_ref().get().unwrap();
+        assert_eq!(result, 110);
+
+        write(&value, "module.exports = 2;", 5);
+
+        let ids = [&value, &lib, &main]
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(poll(&ctx).unwrap(), ids);
+        let result: i32 = ctx.require(id).unwrap().as_ref().get().unwrap();
+        assert_eq!(result, 120);
+    }
+}
@@ -0,0 +1 @@
+var _has = Object.prototype.hasOwnProperty,_toString = Object.prototype.toString;function Suite(name,parent) {this.name = name;this.parent = parent;this.tests = [];this.suites = [];this.beforeEach = [];this.afterEach = [];}Suite.prototype.fullName = function (name) {var names = [name];for (var suite = this;suite && suite.parent;suite = suite.parent) {names.unshift(suite.name);}return names.join(' > ');};Suite.prototype.hooks = function (kind) {var hooks = this.parent ? this.parent.hooks(kind) : [];return kind === 'afterEach' ? this[kind].concat(hooks) : hooks.concat(this[kind]);};var root = new Suite('',null);var current = root;function describe(name,fn) {var suite = new Suite(name,current);current.suites.push(suite);current = suite;try {fn();}finally {current = suite.parent;}}function test(name,fn) {current.tests.push({name: name,fn: fn,skip: false});}test.skip = function skip(name,fn) {current.tests.push({name: name,fn: fn,skip: true});};function beforeEach(fn) {current.beforeEach.push(fn);}function afterEach(fn) {current.afterEach.push(fn);}function ExpectationError(message) {this.name = 'ExpectationError';this.message = message;var stack = new Error(message).stack;if (stack) this.stack = stack.replace(/^Error/,this.name);}ExpectationError.prototype = Object.create(Error.prototype);ExpectationError.prototype.constructor = ExpectationError;function inspect(value) {if (typeof value === 'string') return JSON.stringify(value);if (typeof value === 'function') return '[Function' + (value.name ? ': ' + value.name : '') + ']';if (value instanceof Error || value instanceof RegExp) return String(value);if (value === undefined || typeof value === 'number') return String(value);try {return JSON.stringify(value);}catch (e) {return _toString.call(value);}}function equals(a,b) {if (a === b || (a !== a && b !== b)) return true;if (typeof a !== 'object' || typeof b !== 'object' || a === null || b === null) return false;if (_toString.call(a) !== _toString.call(b)) return false;if (a instanceof Date) return a.getTime() === b.getTime();var keys = Object.keys(a);if (keys.length !== Object.keys(b).length) return false;for (var i = 0;i < keys.length;i++) {if (!_has.call(b,keys[i]) || !equals(a[keys[i]],b[keys[i]])) return false;}return true;}function Expectation(actual,negated) {this.actual = actual;this.negated = negated;if (!negated) this.not = new Expectation(actual,true);}Expectation.prototype.check = function (pass,matcher,expected) {if (pass === this.negated) {var message = 'expected ' + inspect(this.actual) + (this.negated ? ' not ' : ' ') + matcher;if (arguments.length > 2) message += ' ' + inspect(expected);throw new ExpectationError(message);}};Object.assign(Expectation.prototype,{toBe: function toBe(expected) {this.check(this.actual === expected || (this.actual !== this.actual && expected !== expected),'to be',expected);},toEqual: function toEqual(expected) {this.check(equals(this.actual,expected),'to equal',expected);},toBeTruthy: function toBeTruthy() {this.check(!!this.actual,'to be truthy');},toBeFalsy: function toBeFalsy() {this.check(!this.actual,'to be falsy');},toBeNull: function toBeNull() {this.check(this.actual === null,'to be null');},toBeUndefined: function toBeUndefined() {this.check(this.actual === undefined,'to be undefined');},toBeDefined: function toBeDefined() {this.check(this.actual !== undefined,'to be defined');},toBeGreaterThan: function toBeGreaterThan(expected) {this.check(this.actual > expected,'to be greater than',expected);},toBeLessThan: function toBeLessThan(expected) {this.check(this.actual < expected,'to be less than',expected);},toHaveLength: function toHaveLength(expected) {this.check(this.actual != null && this.actual.length === expected,'to have length',expected);},toContain: function toContain(expected) {this.check(this.actual != null && this.actual.indexOf(expected) > -1,'to contain',expected);},toMatch: function toMatch(expected) {var pass = typeof expected === 'string' ? this.actual.indexOf(expected) > -1 : expected.test(this.actual);this.check(pass,'to match',expected);},toThrow: function toThrow(expected) {var error,thrown = false;try {this.actual();}catch (e) {error = e;thrown = true;}var pass = thrown;if (thrown && expected !== undefined) {var message = error && error.message !== undefined ? String(error.message) : String(error);if (typeof expected === 'string') pass = message.indexOf(expected) > -1;else if (expected instanceof RegExp) pass = expected.test(message);else if (typeof expected === 'function') pass = error instanceof expected;}if (arguments.length) this.check(pass,'to throw',expected);else this.check(pass,'to throw');}});function expect(actual) {return new Expectation(actual,false);}function callAll(hooks) {for (var i = 0;i < hooks.length;i++) hooks[i]();}function describeError(e) {if (e && e.stack) return String(e.stack);return String(e);}function run(filter) {var results = [];function visit(suite) {suite.tests.forEach(function (t) {var name = suite.fullName(t.name);if (filter && name.indexOf(filter) === -1) return;var result = {name: name,ok: true,skipped: t.skip,message: '',stack: '',duration: 0};if (!t.skip) {var start = Date.now();try {callAll(suite.hooks('beforeEach'));t.fn();}catch (e) {result.ok = false;result.message = String(e);result.stack = describeError(e);}try {callAll(suite.hooks('afterEach'));}catch (e) {if (result.ok) {result.ok = false;result.message = String(e);result.stack = describeError(e);}}result.duration = Date.now() - start;}results.push(result);});suite.suites.forEach(visit);}visit(root);return results;}exports.describe = describe;exports.test = test;exports.it = test;exports.beforeEach = beforeEach;exports.afterEach = afterEach;exports.expect = expect;exports.equals = equals;exports.ExpectationError = ExpectationError;exports.run = run;
\ No newline at end of file
@@ -0,0 +1,202 @@
+const native = exports,
+    isDeepEqual = native.isDeepEqual,
+    inspect = native.inspect,
+    diff = native.diff;
+
+function is(a, b) {
+    return a === b ? (a !== 0 || 1 / a === 1 / b) : (a !== a && b !== b);
+}
+
+const headers = {
+    strictEqual: 'Expected values to be strictly equal:',
+    deepEqual: 'Expected values to be loosely deep-equal:',
+    deepStrictEqual: 'Expected values to be strictly deep-equal:'
+};
+
+const negations = {
+    '!=': 'Expected "actual" to be loosely unequal to:',
+    notStrictEqual: 'Expected "actual" to be strictly unequal to:',
+    notDeepEqual: 'Expected "actual" not to be loosely deep-equal to:',
+    notDeepStrictEqual: 'Expected "actual" not to be strictly deep-equal to:'
+};
+
+function generateMessage(actual, expected, operator) {
+    if (headers[operator]) {
+        return headers[operator] + '\n\n' + diff(actual, expected) + '\n';
+    }
+    if (negations[operator]) {
+        return negations[operator] + '\n\n' + inspect(expected) + '\n';
+    }
+    if (operator === 'fail') {
+        return 'Failed';
+    }
+    return inspect(actual) + ' ' + operator + ' ' + inspect(expected);
+}
+
+function AssertionError(options) {
+    this.name = 'AssertionError';
+    this.code = 'ERR_ASSERTION';
+    this.actual = options.actual;
+    this.expected = options.expected;
+    this.operator = options.operator;
+    this.generatedMessage = !options.message;
+    this.message = options.message || generateMessage(options.actual, options.expected, options.operator);
+    var stack = new Error(this.message).stack;
+    if (stack) this.stack = stack.replace(/^Error/, this.name + ' [' + this.code + ']');
+}
+
+AssertionError.prototype = Object.create(Error.prototype);
+AssertionError.prototype.constructor = AssertionError;
+
+function innerFail(actual, expected, message, operator) {
+    if (message instanceof Error) throw message;
+    throw new AssertionError({
+        actual: actual,
+        expected: expected,
+        message: message,
+        operator: operator
+    });
+}
+
+function ok(value, message) {
+    if (!arguments.length) {
+        innerFail(undefined, true, 'No value argument passed to `assert.ok()`', '==');
+    }
+    if (!value) {
+        innerFail(value, true, message || 'The expression evaluated to a falsy value:\n\n  assert.ok(' + inspect(value) + ')\n', '==');
+    }
+}
+
+const assert = function assert(value, message) {
+    ok.apply(this, arguments);
+};
+
+// Match the error thrown by `throws` or `rejects` against `expected`,
+// which is a constructor, a validation function, a RegExp or an object of properties
+function match(actual, expected, message) {
+    if (expected === undefined) return;
+
+    if (expected instanceof RegExp) {
+        if (!expected.test(String(actual))) {
+            innerFail(actual, expected, message || 'The input did not match the regular expression ' + inspect(expected) +
+                '. Input:\n\n' + inspect(String(actual)) + '\n', 'throws');
+        }
+        return;
+    }
+
+    if (typeof expected === 'function') {
+        if (expected.prototype !== undefined && actual instanceof expected) return;
+        if (expected === Error || expected.prototype instanceof Error) throw actual;
+        if (expected.call({}, actual) !== true) {
+            innerFail(actual, expected, message || 'The validation function is expected to return "true". Received ' +
+                inspect(actual), 'throws');
+        }
+        return;
+    }
+
+    if (typeof expected !== 'object' || expected === null) {
+        throw new TypeError('The "expected" argument must be a function, a RegExp or an object');
+    }
+
+    Object.keys(expected).forEach(function (key) {
+        var value = actual != null ? actual[key] : undefined;
+        var pass = expected[key] instanceof RegExp && typeof value === 'string'
+            ? expected[key].test(value)
+            : isDeepEqual(value, expected[key], true);
+        if (!pass) {
+            var picked = {}, wanted = {};
+            Object.keys(expected).forEach(function (k) {
+                picked[k] = actual != null ? actual[k] : undefined;
+                wanted[k] = expected[k];
+            });
+            innerFail(picked, wanted, message, 'deepStrictEqual');
+        }
+    });
+}
+
+function missing(expected, message, what) {
+    var name = typeof expected === 'function' && expected.name ? ' (' + expected.name + ')' : '';
+    innerFail(undefined, expected, 'Missing expected ' + what + name + (message ? ': ' + message : '.'), 'throws');
+}
+
+Object.assign(assert, {
+    AssertionError: AssertionError,
+    ok: ok,
+    fail(message) {
+        innerFail(undefined, undefined, message, 'fail');
+    },
+    equal(actual, expected, message) {
+        if (!(actual == expected || (actual !== actual && expected !== expected))) {
+            innerFail(actual, expected, message, '==');
+        }
+    },
+    notEqual(actual, expected, message) {
+        if (actual == expected || (actual !== actual && expected !== expected)) {
+            innerFail(actual, expected, message, '!=');
+        }
+    },
+    strictEqual(actual, expected, message) {
+        if (!is(actual, expected)) innerFail(actual, expected, message, 'strictEqual');
+    },
+    notStrictEqual(actual, expected, message) {
+        if (is(actual, expected)) innerFail(actual, expected, message, 'notStrictEqual');
+    },
+    deepEqual(actual, expected, message) {
+        if (!isDeepEqual(actual, expected, false)) innerFail(actual, expected, message, 'deepEqual');
+    },
+    notDeepEqual(actual, expected, message) {
+        if (isDeepEqual(actual, expected, false)) innerFail(actual, expected, message, 'notDeepEqual');
+    },
+    deepStrictEqual(actual, expected, message) {
+        if (!isDeepEqual(actual, expected, true)) innerFail(actual, expected, message, 'deepStrictEqual');
+    },
+    notDeepStrictEqual(actual, expected, message) {
+        if (isDeepEqual(actual, expected, true)) innerFail(actual, expected, message, 'notDeepStrictEqual');
+    },
+    throws(fn, expected, message) {
+        if (typeof fn !== 'function') throw new TypeError('The "fn" argument must be a function');
+        if (typeof expected === 'string') {
+            message = expected;
+            expected = undefined;
+        }
+        try {
+            fn();
+        } catch (e) {
+            match(e, expected, message);
+            return;
+        }
+        missing(expected, message, 'exception');
+    },
+    doesNotThrow(fn, message) {
+        try {
+            fn();
+        } catch (e) {
+            innerFail(e, undefined, 'Got unwanted exception' + (typeof message === 'string' ? ': ' + message : '.') +
+                '\nActual message: "' + (e && e.message) + '"', 'doesNotThrow');
+        }
+    },
+    // Resolves, when `promise` or the promise returned by it rejects with an error matching `expected`
+    // Needs a Promise implementation. The es2015 shim (`duk -z`) only installs one when the host provides setTimeout
+    rejects(promise, expected, message) {
+        if (typeof Promise !== 'function') throw new TypeError('assert.rejects requires Promise');
+        if (typeof expected === 'string') {
+            message = expected;
+            expected = undefined;
+        }
+        return Promise.resolve().then(function () {
+            var p = typeof promise === 'function' ? promise() : promise;
+            if (!p || typeof p.then !== 'function') {
+                throw new TypeError('The "promise" argument must be a promise or return one');
+            }
+            return p.then(function () {
+                missing(expected, message, 'rejection');
+            }, function (e) {
+                match(e, expected, message);
+            });
+        });
+    },
+    isDeepEqual: isDeepEqual,
+    inspect: inspect
+});
+
+module.exports = assert;
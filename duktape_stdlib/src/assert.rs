@@ -0,0 +1,429 @@
+use super::inspect::{class_of, is_identifier, own_keys, prop, quote};
+use super::sources::ASSERT;
+use duktape::error::Result;
+use duktape::prelude::*;
+use duktape_modules::NativeModule;
+
+fn compare(ctx: &Context, a: &Ref, b: &Ref, cmp: fn(&Context, Idx, Idx) -> bool) -> bool {
+    a.push();
+    b.push();
+    let ret = cmp(ctx, -2, -1);
+    ctx.pop(2);
+    ret
+}
+
+fn is_object(value: &Ref) -> bool {
+    match value.get_type() {
+        Type::Object | Type::Array | Type::Function | Type::Buffer => true,
+        _ => false,
+    }
+}
+
+/// Deep comparison like `assert.deepEqual` and `assert.deepStrictEqual`
+struct DeepEqual<'a> {
+    ctx: &'a Context,
+    strict: bool,
+    // Pairs of objects being compared, to stop at circular references
+    seen: Vec<(Ref<'a>, Ref<'a>)>,
+}
+
+impl<'a> DeepEqual<'a> {
+    fn equal(&mut self, a: &Ref<'a>, b: &Ref<'a>) -> Result<bool> {
+        let ctx = self.ctx;
+        if compare(ctx, a, b, Context::strict_equals) {
+            return Ok(!self.strict || compare(ctx, a, b, Context::same_value));
+        }
+
+        if !is_object(a) || !is_object(b) {
+            if self.strict {
+                return Ok(compare(ctx, a, b, Context::same_value));
+            }
+            // NaN is loosely equal to NaN
+            let nan = |v: &Ref| v.is(Type::Number) && !compare(ctx, v, v, Context::equals);
+            return Ok(!is_object(a)
+                && !is_object(b)
+                && (compare(ctx, a, b, Context::equals) || (nan(a) && nan(b))));
+        }
+
+        if a.get_type() != b.get_type() || a.is(Type::Function) {
+            return Ok(false);
+        }
+
+        if a.is(Type::Buffer) {
+            return Ok(a.get::<&[u8]>()? == b.get::<&[u8]>()?);
+        }
+
+        if self.strict {
+            a.push();
+            ctx.get_prototype(-1);
+            b.push();
+            ctx.get_prototype(-1);
+            let same = ctx.strict_equals(-3, -1);
+            ctx.pop(4);
+            if !same {
+                return Ok(false);
+            }
+        }
+
+        let class = class_of(ctx, a)?;
+        if class != class_of(ctx, b)? {
+            return Ok(false);
+        }
+        match class.as_str() {
+            "[object Date]" => {
+                let time = |v: &Ref| -> Result<Ref> {
+                    v.push();
+                    ctx.get_prop_string(-1, "getTime").dup(-2).call_method(0)?;
+                    ctx.remove(-2);
+                    ctx.getp()
+                };
+                if !compare(ctx, &time(a)?, &time(b)?, Context::same_value) {
+                    return Ok(false);
+                }
+            }
+            "[object Number]" | "[object String]" | "[object Boolean]" => {
+                let value = |v: &Ref| -> Result<Ref> {
+                    v.push();
+                    ctx.get_prop_string(-1, "valueOf").dup(-2).call_method(0)?;
+                    ctx.remove(-2);
+                    ctx.getp()
+                };
+                if !compare(ctx, &value(a)?, &value(b)?, Context::same_value) {
+                    return Ok(false);
+                }
+            }
+            "[object RegExp]" | "[object Error]" => {
+                if a.to_string() != b.to_string() {
+                    return Ok(false);
+                }
+            }
+            _ => {}
+        }
+
+        let circular = self.seen.iter().any(|(x, y)| {
+            compare(ctx, x, a, Context::strict_equals) && compare(ctx, y, b, Context::strict_equals)
+        });
+        if circular {
+            return Ok(true);
+        }
+
+        let keys = own_keys(ctx, a)?;
+        let other = own_keys(ctx, b)?;
+        if keys.len() != other.len() || !keys.iter().all(|k| other.contains(k)) {
+            return Ok(false);
+        }
+
+        self.seen.push((a.clone(), b.clone()));
+        let mut equal = true;
+        for key in &keys {
+            if !self.equal(&prop(ctx, a, key)?, &prop(ctx, b, key)?)? {
+                equal = false;
+                break;
+            }
+        }
+        self.seen.pop();
+
+        Ok(equal)
+    }
+}
+
+/// Formats values over multiple lines, so they can be diffed line by line
+struct Inspect<'a> {
+    ctx: &'a Context,
+    lines: Vec<String>,
+    parents: Vec<Ref<'a>>,
+}
+
+impl<'a> Inspect<'a> {
+    fn push(&mut self, depth: usize, line: String) {
+        self.lines.push(format!("{}{}", "  ".repeat(depth), line));
+    }
+
+    /// Format `value` onto the last line, after `prefix`
+    fn value(&mut self, value: &Ref<'a>, prefix: String, suffix: &str, depth: usize) -> Result<()> {
+        let ctx = self.ctx;
+        let inline = match value.get_type() {
+            Type::String => Some(quote(&value.get::<String>()?)),
+            Type::Function => {
+                let name = prop(ctx, value, "name")?.to_string();
+                Some(if name.is_empty() {
+                    "[Function]".to_owned()
+                } else {
+                    format!("[Function: {}]", name)
+                })
+            }
+            Type::Buffer => {
+                let bytes = value.get::<&[u8]>()?;
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                Some(format!("<Buffer {}>", hex.join(" ")))
+            }
+            Type::Object | Type::Array => {
+                if self
+                    .parents
+                    .iter()
+                    .any(|p| compare(ctx, p, value, Context::strict_equals))
+                {
+                    Some("[Circular]".to_owned())
+                } else {
+                    match class_of(ctx, value)?.as_str() {
+                        "[object Date]" | "[object RegExp]" | "[object Error]" => {
+                            Some(value.to_string())
+                        }
+                        _ => None,
+                    }
+                }
+            }
+            _ => Some(value.to_string()),
+        };
+
+        if let Some(inline) = inline {
+            self.push(depth, format!("{}{}{}", prefix, inline, suffix));
+            return Ok(());
+        }
+
+        let array = value.is(Type::Array);
+        let keys = own_keys(ctx, value)?;
+        let (open, close) = if array { ("[", "]") } else { ("{", "}") };
+        if keys.is_empty() {
+            self.push(depth, format!("{}{}{}{}", prefix, open, close, suffix));
+            return Ok(());
+        }
+
+        self.push(depth, format!("{}{}", prefix, open));
+        self.parents.push(value.clone());
+        for key in &keys {
+            let prefix = if array && key.parse::<u32>().is_ok() {
+                String::new()
+            } else if is_identifier(key) {
+                format!("{}: ", key)
+            } else {
+                format!("{}: ", quote(key))
+            };
+            self.value(&prop(ctx, value, key)?, prefix, ",", depth + 1)?;
+        }
+        self.parents.pop();
+        self.push(depth, format!("{}{}", close, suffix));
+        Ok(())
+    }
+}
+
+fn inspect_lines<'a>(ctx: &'a Context, value: &Ref<'a>) -> Result<Vec<String>> {
+    let mut inspect = Inspect {
+        ctx,
+        lines: Vec::new(),
+        parents: Vec::new(),
+    };
+    inspect.value(value, String::new(), "", 0)?;
+    Ok(inspect.lines)
+}
+
+// Largest table of common subsequence lengths built for a diff, in cells
+const MAX_LCS: usize = 1 << 20;
+
+/// A line diff of `actual` and `expected`, from their longest common subsequence
+///
+/// Common leading and trailing lines are matched first. When what remains is too
+/// large to compare, it is listed as removed and added wholesale.
+fn diff_lines(actual: &[String], expected: &[String]) -> Vec<String> {
+    let prefix = actual
+        .iter()
+        .zip(expected)
+        .take_while(|(a, e)| a == e)
+        .count();
+    let suffix = actual[prefix..]
+        .iter()
+        .rev()
+        .zip(expected[prefix..].iter().rev())
+        .take_while(|(a, e)| a == e)
+        .count();
+
+    let mut out: Vec<String> = actual[..prefix]
+        .iter()
+        .map(|line| format!("  {}", line))
+        .collect();
+    let same = &actual[actual.len() - suffix..];
+    let actual = &actual[prefix..actual.len() - suffix];
+    let expected = &expected[prefix..expected.len() - suffix];
+
+    let (n, m) = (actual.len(), expected.len());
+    if n.saturating_mul(m) > MAX_LCS {
+        out.extend(actual.iter().map(|line| format!("+ {}", line)));
+        out.extend(expected.iter().map(|line| format!("- {}", line)));
+        out.extend(same.iter().map(|line| format!("  {}", line)));
+        return out;
+    }
+
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if actual[i] == expected[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && actual[i] == expected[j] {
+            out.push(format!("  {}", actual[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("+ {}", actual[i]));
+            i += 1;
+        } else {
+            out.push(format!("- {}", expected[j]));
+            j += 1;
+        }
+    }
+    out.extend(same.iter().map(|line| format!("  {}", line)));
+    out
+}
+
+fn is_deep_equal(ctx: &Context) -> Result<i32> {
+    let a: Ref = ctx.get(0)?;
+    let b: Ref = ctx.get(1)?;
+    let mut deep = DeepEqual {
+        ctx,
+        strict: ctx.get_boolean(2)?,
+        seen: Vec::new(),
+    };
+    let equal = deep.equal(&a, &b)?;
+    ctx.push(equal)?;
+    Ok(1)
+}
+
+fn inspect(ctx: &Context) -> Result<i32> {
+    let value: Ref = ctx.get(0)?;
+    let lines = inspect_lines(ctx, &value)?;
+    ctx.push(lines.join("\n"))?;
+    Ok(1)
+}
+
+fn diff(ctx: &Context) -> Result<i32> {
+    let actual = inspect_lines(ctx, &ctx.get(0)?)?;
+    let expected = inspect_lines(ctx, &ctx.get(1)?)?;
+    let mut out = vec!["+ actual - expected".to_owned(), String::new()];
+    out.extend(diff_lines(&actual, &expected));
+    ctx.push(out.join("\n"))?;
+    Ok(1)
+}
+
+/// The assert module, with the deep comparison and diffs implemented natively
+pub(crate) struct Assert;
+
+impl NativeModule for Assert {
+    fn exports(&self, _ctx: &Context, exports: &Object) -> Result<()> {
+        exports
+            .set("isDeepEqual", (3, is_deep_equal))
+            .set("inspect", (1, inspect))
+            .set("diff", (2, diff));
+        Ok(())
+    }
+
+    fn source(&self) -> Option<&[u8]> {
+        Some(ASSERT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_lines;
+    use crate::{Modules, Permissions};
+    use duktape::prelude::*;
+
+    fn context(setup: impl FnOnce(&Context, &mut duktape_modules::Builder)) -> Context {
+        let ctx = Context::new().unwrap();
+        let mut require = duktape_modules::Builder::new();
+        crate::register(&ctx, &mut require, Modules::Assert, Permissions::new());
+        setup(&ctx, &mut require);
+        duktape_modules::register(&ctx, require).unwrap();
+        ctx.eval("var assert = require('assert')").unwrap();
+        ctx
+    }
+
+    fn eval(ctx: &Context, script: &str) -> String {
+        ctx.eval(script).unwrap().getp().unwrap()
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| (*line).to_owned()).collect()
+    }
+
+    #[test]
+    fn deep_equal_boxed_primitives() {
+        let ctx = context(|_, _| {});
+        for (script, expected) in &[
+            ("assert.isDeepEqual(new Number(1), new Number(1), true)", true),
+            ("assert.isDeepEqual(new Number(1), new Number(2), false)", false),
+            ("assert.isDeepEqual(new Number(NaN), new Number(NaN), true)", true),
+            ("assert.isDeepEqual(new Number(0), new Number(-0), true)", false),
+            ("assert.isDeepEqual(new String('a'), new String('b'), false)", false),
+            ("assert.isDeepEqual(new Boolean(true), new Boolean(false), false)", false),
+            ("assert.isDeepEqual(new Boolean(true), new Boolean(true), true)", true),
+        ] {
+            let equal: bool = ctx.eval(script).unwrap().getp().unwrap();
+            assert_eq!(equal, *expected, "{}", script);
+        }
+    }
+
+    #[test]
+    fn diff_output() {
+        let ctx = context(|_, _| {});
+        let message = eval(
+            &ctx,
+            "try { assert.deepStrictEqual({ a: 1, b: [1, 2] }, { a: 1, b: [1, 3] }); } catch (e) { e.message }",
+        );
+        assert_eq!(
+            message,
+            "Expected values to be strictly deep-equal:\n\n+ actual - expected\n\n  \
+             {\n    a: 1,\n    b: [\n      1,\n+     2,\n-     3,\n    ],\n  }\n"
+        );
+    }
+
+    #[test]
+    fn diff_lines_trims_and_caps() {
+        let diff = diff_lines(&lines(&["a", "b", "c"]), &lines(&["a", "x", "c"]));
+        assert_eq!(diff, lines(&["  a", "+ b", "- x", "  c"]));
+
+        let actual: Vec<String> = (0..2000).map(|i| i.to_string()).collect();
+        let mut expected = actual.clone();
+        expected.reverse();
+        let diff = diff_lines(&actual, &expected);
+        assert_eq!(diff.len(), 4000);
+        assert_eq!(diff[0], "+ 0");
+        assert_eq!(diff[2000], "- 1999");
+    }
+
+    #[test]
+    fn rejects_requires_promise() {
+        let ctx = context(|_, _| {});
+        let message = eval(
+            &ctx,
+            "try { assert.rejects(function () {}); } catch (e) { e.message }",
+        );
+        assert_eq!(message, "assert.rejects requires Promise");
+    }
+
+    #[test]
+    fn rejects() {
+        // The es2015 shim only installs Promise when there is a setTimeout
+        let ctx = context(|ctx, require| {
+            ctx.eval("var timers = []; function setTimeout(fn) { timers.push(fn); }")
+                .unwrap();
+            duktape_es2015::register(ctx, require);
+        });
+        let result = eval(
+            &ctx,
+            "var result = [];
+            function settle(p) { p.then(function () { result.push('ok'); }, function (e) { result.push(e.message); }); }
+            settle(assert.rejects(function () { return Promise.reject(new TypeError('bad')); }, TypeError));
+            settle(assert.rejects(Promise.resolve(1), 'no rejection'));
+            while (timers.length) timers.shift()();
+            result.join(',')",
+        );
+        assert_eq!(result, "ok,Missing expected rejection: no rejection");
+    }
+}
@@ -5,6 +5,9 @@ bitflags! {
         const Utils = 1 << 2;
         #[cfg(feature = "http")]
         const Http = 1 << 3;
+        const ChildProcess = 1 << 4;
+        const Test = 1 << 5;
+        const Assert = 1 << 6;
     }
 }
 
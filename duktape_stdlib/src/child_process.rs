@@ -0,0 +1,63 @@
+use super::permissions;
+use duktape::error::Result;
+use duktape::prelude::*;
+use std::process::Command;
+
+fn spawn_sync(ctx: &Context) -> Result<i32> {
+    let command: String = ctx.get(0)?;
+
+    permissions::check(ctx, |p| p.check_run(&command))?;
+
+    let mut cmd = Command::new(&command);
+
+    if ctx.is(Type::Array, 1) {
+        let args: Array = ctx.get(1)?;
+        for arg in args.iter() {
+            cmd.arg(arg.to_string());
+        }
+    }
+
+    let output = cmd.output()?;
+
+    let result: Object = ctx.create()?;
+    result
+        .set("status", output.status.code())
+        .set("stdout", String::from_utf8_lossy(&output.stdout).to_string())
+        .set("stderr", String::from_utf8_lossy(&output.stderr).to_string());
+
+    ctx.push(result)?;
+
+    Ok(1)
+}
+
+pub fn init_child_process(_ctx: &Context, exports: &Object) -> Result<()> {
+    exports.set("spawnSync", (2, spawn_sync));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::init_child_process;
+    use crate::Permissions;
+    use duktape::error::Result;
+    use duktape::prelude::*;
+
+    fn spawn(permissions: Permissions) -> Result<i32> {
+        let ctx = Context::new().unwrap();
+        ctx.data_mut().unwrap().insert::<Permissions>(permissions);
+        let exports: Object = ctx.create().unwrap();
+        init_child_process(&ctx, &exports).unwrap();
+        ctx.push_global_object().getp::<Object>()?.set("child_process", exports);
+        ctx.eval("child_process.spawnSync('true', []).status")?.getp()
+    }
+
+    #[test]
+    fn run_permission() {
+        let err = spawn(Permissions::none()).err().unwrap();
+        assert!(err.to_string().contains("running 'true'"), "{}", err);
+
+        let mut permissions = Permissions::none();
+        permissions.allow_run(true);
+        assert_eq!(spawn(permissions).unwrap(), 0);
+    }
+}
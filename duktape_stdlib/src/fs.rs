@@ -1,10 +1,16 @@
 use super::io as dukio;
+use super::permissions::Permissions;
 use super::sources::FS;
 use duktape::prelude::*;
-use duktape::{self, error::Result};
-use duktape_modules::require;
+use duktape::{
+    self,
+    error::{ErrorKind, Result},
+};
+use duktape_modules::error::{Error, ErrorKind as ModuleErrorKind};
+use duktape_modules::NativeModule;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufRead};
+use std::path::PathBuf;
 
 // impl dukio::Reader for File {
 //     fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
@@ -17,19 +23,59 @@ use std::io::{self, BufRead};
 
 //impl dukio::ReadWriter for File {}
 
-fn get_file_options(input: &str) -> OpenOptions {
+fn get_file_options(input: &str) -> (OpenOptions, bool) {
     let mut o = OpenOptions::new();
 
-    match input {
-        "r" => o.read(true),
-        "w" => o.write(true).create(true).truncate(true),
-        "rw" | "wr" => o.write(true).read(true).truncate(true),
-        "w+" => o.write(true).create(true),
-        "wr+" | "rw+" => o.write(true).read(true).create(true),
-        _ => o.read(true),
+    let write = match input {
+        "r" => {
+            o.read(true);
+            false
+        }
+        "w" => {
+            o.write(true).create(true).truncate(true);
+            true
+        }
+        "rw" | "wr" => {
+            o.write(true).read(true).truncate(true);
+            true
+        }
+        "w+" => {
+            o.write(true).create(true);
+            true
+        }
+        "wr+" | "rw+" => {
+            o.write(true).read(true).create(true);
+            true
+        }
+        _ => {
+            o.read(true);
+            false
+        }
     };
 
-    o
+    (o, write)
+}
+
+/// Check `path` against the policy registered with the context
+fn check_path(ctx: &Context, path: &str, write: bool) -> Result<PathBuf> {
+    let policy = match ctx.data()?.get::<Permissions>() {
+        Some(permissions) => permissions.fs_policy(),
+        None => return Ok(PathBuf::from(path)),
+    };
+
+    let ret = if write {
+        policy.check_write(path)
+    } else {
+        policy.check_read(path)
+    };
+
+    match ret {
+        Ok(path) => Ok(path),
+        Err(Error(ModuleErrorKind::PermissionDenied(path), _)) => {
+            Err(ErrorKind::PermissionDenied(path).into())
+        }
+        Err(e) => Err(ErrorKind::Error(format!("{}", e)).into()),
+    }
 }
 
 pub fn init_file<'a>(ctx: &'a Context) -> DukResult<class::Builder<'a>> {
@@ -37,16 +83,14 @@ pub fn init_file<'a>(ctx: &'a Context) -> DukResult<class::Builder<'a>> {
 
     file.constructor((2, |ctx: &Context, this: &mut class::Instance| {
         let path: String = ctx.get(0)?;
-        let options: OpenOptions;
-        if ctx.is(Type::String, 1) {
-            options = get_file_options(ctx.get::<&str>(1)?);
+        let (options, write) = if ctx.is(Type::String, 1) {
+            get_file_options(ctx.get::<&str>(1)?)
         } else {
-            let mut o = OpenOptions::new();
-            o.read(true);
-            options = o;
-        }
+            get_file_options("r")
+        };
 
-        let file = options.open(path).unwrap();
+        let path = check_path(ctx, &path, write)?;
+        let file = options.open(path)?;
 
         this.data_mut()
             .insert::<dukio::ReadWriterKey>(dukio::IOReadWriter::new(file));
@@ -70,37 +114,37 @@ pub fn init_file<'a>(ctx: &'a Context) -> DukResult<class::Builder<'a>> {
 }
 
 fn mkdir(ctx: &Context) -> Result<i32> {
-    let path = ctx.get::<&str>(0)?;
+    let path = check_path(ctx, ctx.get::<&str>(0)?, true)?;
     fs::create_dir(path)?;
     Ok(0)
 }
 
 fn mkdir_all(ctx: &Context) -> Result<i32> {
-    let path = ctx.get::<&str>(0)?;
+    let path = check_path(ctx, ctx.get::<&str>(0)?, true)?;
     fs::create_dir_all(path)?;
     Ok(0)
 }
 
 fn rmdir(ctx: &Context) -> Result<i32> {
-    let path = ctx.get::<&str>(0)?;
+    let path = check_path(ctx, ctx.get::<&str>(0)?, true)?;
     fs::remove_dir(path)?;
     Ok(0)
 }
 
 fn rmdir_all(ctx: &Context) -> Result<i32> {
-    let path = ctx.get::<&str>(0)?;
+    let path = check_path(ctx, ctx.get::<&str>(0)?, true)?;
     fs::remove_dir_all(path)?;
     Ok(0)
 }
 
 fn rmfile(ctx: &Context) -> Result<i32> {
-    let path = ctx.get::<&str>(0)?;
+    let path = check_path(ctx, ctx.get::<&str>(0)?, true)?;
     fs::remove_file(path)?;
     Ok(0)
 }
 
 fn readdir(ctx: &Context) -> Result<i32> {
-    let path = ctx.get::<&str>(0)?;
+    let path = check_path(ctx, ctx.get::<&str>(0)?, false)?;
     let files = fs::read_dir(path)?
         .filter_map(|file| {
             if file.is_err() {
@@ -118,25 +162,54 @@ fn readdir(ctx: &Context) -> Result<i32> {
     Ok(1)
 }
 
-pub fn init_fs(ctx: &Context) -> Result<i32> {
-    let exports = ctx.create::<Object>()?;
-
-    exports
-        .set("File", init_file(ctx)?)
-        .set("mkdir", (1, mkdir))
-        .set("mkdirAll", (1, mkdir_all))
-        .set("rmdir", (1, rmdir))
-        .set("rmdirAll", (1, rmdir_all))
-        .set("unlink", (1, rmfile))
-        .set("readdir", (1, readdir));
-
-    let module: Object = ctx.get(-1)?;
-    module.set("exports", exports);
+pub(crate) struct Fs;
+
+impl NativeModule for Fs {
+    fn exports(&self, ctx: &Context, exports: &Object) -> Result<()> {
+        exports
+            .set("File", init_file(ctx)?)
+            .set("mkdir", (1, mkdir))
+            .set("mkdirAll", (1, mkdir_all))
+            .set("rmdir", (1, rmdir))
+            .set("rmdirAll", (1, rmdir_all))
+            .set("unlink", (1, rmfile))
+            .set("readdir", (1, readdir));
+        Ok(())
+    }
 
     // Enchance the export with some js goodies
-    require::eval_module(ctx, FS, &module).unwrap();
-
-    module.get::<_, Ref>("exports")?.push();
+    fn source(&self) -> Option<&[u8]> {
+        Some(FS)
+    }
+}
 
-    Ok(1)
+#[cfg(test)]
+mod tests {
+    use super::check_path;
+    use crate::{FsPolicy, Permissions};
+    use duktape::prelude::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn check_path_policy() {
+        let root = env::temp_dir().join("duktape_stdlib_check_path");
+        fs::create_dir_all(&root).unwrap();
+        let inside = root.join("file.txt");
+        let inside = inside.to_str().unwrap();
+
+        let ctx = Context::new().unwrap();
+        assert!(check_path(&ctx, "/etc/hosts", true).is_ok());
+
+        let mut policy = FsPolicy::restricted();
+        policy.allow(&root).read_only(true);
+        let mut permissions = Permissions::none();
+        permissions.fs(policy);
+        ctx.data_mut().unwrap().insert::<Permissions>(permissions);
+
+        assert!(check_path(&ctx, inside, false).is_ok());
+        assert!(check_path(&ctx, "/etc/hosts", false).is_err());
+        let err = check_path(&ctx, inside, true).err().unwrap();
+        assert!(err.to_string().contains("Permission denied"), "{}", err);
+    }
 }
@@ -1,4 +1,5 @@
 use super::io::{inherit_reader, IOReader, ReaderKey};
+use super::permissions::{self, Permissions};
 use super::sources::HTTP;
 use duktape::prelude::*;
 use duktape::{
@@ -6,8 +7,10 @@ use duktape::{
     error::{ErrorKind, Result, ResultExt},
     Key,
 };
-use duktape_modules::{require, CJSContext};
-use reqwest::{header::HeaderMap, header::HeaderName, Client, Method, Response, Url};
+use duktape_modules::{error as module_error, resolvers::Fetcher, CJSContext, NativeModule};
+use reqwest::{
+    header::HeaderMap, header::HeaderName, header::LOCATION, Client, Method, RedirectPolicy, Response, Url,
+};
 use std::str::FromStr;
 
 struct ClientKey;
@@ -16,8 +19,29 @@ impl Key for ClientKey {
     type Value = Client;
 }
 
-fn options_to_request(options: &Object, client: &Client) -> Result<Response> {
+fn check_url(permissions: &Permissions, url: &Url) -> Result<()> {
+    permissions.check_net(url.host_str().unwrap_or(""), url.port_or_known_default())
+}
+
+/// A client, which only follows redirects to the hosts granted by `permissions`
+fn build_client(permissions: Option<Permissions>) -> Client {
+    let policy = RedirectPolicy::custom(move |attempt| {
+        let allowed = permissions
+            .as_ref()
+            .map_or(true, |p| check_url(p, attempt.url()).is_ok());
+        if allowed {
+            RedirectPolicy::default().redirect(attempt)
+        } else {
+            attempt.stop()
+        }
+    });
+    Client::builder().redirect(policy).build().unwrap()
+}
+
+fn options_to_request(ctx: &Context, options: &Object, client: &Client) -> Result<Response> {
     let (method, url) = get_method(options)?;
+
+    permissions::check(ctx, |p| check_url(p, &url))?;
     let can_have_body = method == Method::POST || method == Method::PATCH || method == Method::PUT;
     let mut req = client.request(method, url);
 
@@ -39,8 +63,23 @@ fn options_to_request(options: &Object, client: &Client) -> Result<Response> {
         };
     }
 
-    req.send()
-        .chain_err(|| ErrorKind::Error("could not connect".to_string()))
+    let resp = req
+        .send()
+        .chain_err(|| ErrorKind::Error("could not connect".to_string()))?;
+
+    // The client stops at redirects to hosts without a grant
+    if resp.status().is_redirection() {
+        let location = resp
+            .headers()
+            .get(LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| resp.url().join(l).ok());
+        if let Some(location) = location {
+            permissions::check(ctx, |p| check_url(p, &location))?;
+        }
+    }
+
+    Ok(resp)
 }
 
 fn get_method(o: &Object) -> Result<(Method, Url)> {
@@ -104,8 +143,8 @@ fn push_response(ctx: &Context, resp: Response) -> Result<Object> {
 
 fn build_client_class<'a>() -> class::Builder<'a> {
     let mut b = class::build();
-    b.constructor((1, |_ctx: &Context, instance: &mut class::Instance| {
-        let client = Client::new();
+    b.constructor((1, |ctx: &Context, instance: &mut class::Instance| {
+        let client = build_client(ctx.data()?.get::<Permissions>().cloned());
         instance.data_mut().insert::<ClientKey>(client);
         Ok(0)
     }))
@@ -114,7 +153,7 @@ fn build_client_class<'a>() -> class::Builder<'a> {
         (1, |ctx: &Context, instance: &mut class::Instance| {
             let options: Object = ctx.get(0)?;
             let client = instance.data().get::<ClientKey>().unwrap();
-            let resp = options_to_request(&options, client)?;
+            let resp = options_to_request(ctx, &options, client)?;
             ctx.push(push_response(ctx, resp)?)?;
             Ok(1)
         }),
@@ -140,18 +179,99 @@ fn build_body_class(ctx: &Context) -> Result<class::Builder> {
     Ok(b)
 }
 
-pub fn init_http(ctx: &Context) -> Result<i32> {
-    let exports: Object = ctx.create()?;
+pub(crate) struct Http;
+
+impl NativeModule for Http {
+    fn exports(&self, ctx: &Context, exports: &Object) -> Result<()> {
+        exports
+            .set("Client", build_client_class())
+            .set("ResponseReader", build_body_class(ctx)?);
+        Ok(())
+    }
+
+    fn source(&self) -> Option<&[u8]> {
+        Some(HTTP)
+    }
+}
+
+/// Fetches remote modules for `duktape_modules::resolvers::HttpResolver`
+pub struct HttpFetcher {
+    client: Client,
+}
 
-    exports.set("Client", build_client_class());
-    exports.set("ResponseReader", build_body_class(ctx)?);
+impl HttpFetcher {
+    pub fn new() -> HttpFetcher {
+        HttpFetcher {
+            client: Client::new(),
+        }
+    }
+}
 
-    let module: Object = ctx.get(-1)?;
-    module.set("exports", exports);
+impl Fetcher for HttpFetcher {
+    fn fetch(&self, url: &str) -> module_error::Result<Vec<u8>> {
+        let fetch_error = |message: String| -> module_error::Error {
+            module_error::ErrorKind::Fetch(url.to_owned(), message).into()
+        };
 
-    require::eval_module(ctx, HTTP, &module).unwrap();
+        let mut resp = self
+            .client
+            .get(url)
+            .send()
+            .map_err(|e| fetch_error(e.to_string()))?;
 
-    module.get::<_, Ref>("exports")?.push();
+        if !resp.status().is_success() {
+            return Err(fetch_error(format!("status {}", resp.status())));
+        }
 
-    Ok(1)
+        let mut content = Vec::new();
+        resp.copy_to(&mut content)
+            .map_err(|e| fetch_error(e.to_string()))?;
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{register, Modules, Permissions};
+    use duktape::prelude::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve a redirect to `location` for each of `count` connections
+    fn redirect_server(count: usize, location: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf);
+                let response = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    location
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn redirects_check_net() {
+        let port = redirect_server(1, "http://localhost:1/secret");
+
+        let ctx = Context::new().unwrap();
+        let mut require = duktape_modules::Builder::new();
+        let mut permissions = Permissions::new();
+        permissions.allow_net(format!("127.0.0.1:{}", port));
+        register(&ctx, &mut require, Modules::all(), permissions);
+        duktape_modules::register(&ctx, require).unwrap();
+
+        let err = ctx
+            .eval(format!("require('http').get('http://127.0.0.1:{}/')", port))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("network access to 'localhost'"), "{}", err);
+    }
 }
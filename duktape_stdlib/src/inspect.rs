@@ -0,0 +1,101 @@
+//! Helpers for looking into values, shared by the assert module and the duk REPL
+use duktape::error::Result;
+use duktape::prelude::*;
+
+/// The own enumerable keys of `value`
+pub fn own_keys(ctx: &Context, value: &Ref) -> Result<Vec<String>> {
+    ctx.get_global_string("Object")
+        .get_prop_string(-1, "keys")
+        .remove(-2);
+    value.push();
+    ctx.call(1)?;
+
+    let len = ctx.get_length(-1);
+    let mut keys = Vec::with_capacity(len);
+    for i in 0..len {
+        keys.push(ctx.get_prop_index(-1, i as u32).getp::<String>()?);
+    }
+    ctx.pop(1);
+    Ok(keys)
+}
+
+/// The property `key` of `value`, read in a protected call,
+/// so throwing getters and Proxy traps are returned as errors
+pub fn prop<'a>(ctx: &'a Context, value: &Ref, key: &str) -> Result<Ref<'a>> {
+    ctx.get_global_string("Reflect")
+        .get_prop_string(-1, "get")
+        .remove(-2);
+    value.push();
+    ctx.push_string(key);
+    ctx.call(2)?.getp()
+}
+
+/// An own property, see `own_property`
+pub enum Property<'a> {
+    Value(Ref<'a>),
+    /// An accessor, with whether it has a getter and a setter
+    Accessor(bool, bool),
+}
+
+/// The own property `key` of `value`, read from its descriptor in a protected call.
+/// Accessors are not called, so printing a value does not run its getters
+pub fn own_property<'a>(ctx: &'a Context, value: &Ref, key: &str) -> Result<Property<'a>> {
+    ctx.get_global_string("Object")
+        .get_prop_string(-1, "getOwnPropertyDescriptor")
+        .remove(-2);
+    value.push();
+    ctx.push_string(key);
+    ctx.call(2)?;
+
+    // The descriptor is undefined, if the property was removed meanwhile
+    if ctx.get_type(-1) != Type::Object {
+        return Ok(Property::Value(ctx.getp()?));
+    }
+    if ctx.has_prop_string(-1, "value") {
+        ctx.get_prop_string(-1, "value").remove(-2);
+        return Ok(Property::Value(ctx.getp()?));
+    }
+
+    let getter = ctx.get_prop_string(-1, "get").get_type(-1) == Type::Function;
+    let setter = ctx.get_prop_string(-2, "set").get_type(-1) == Type::Function;
+    ctx.pop(3);
+    Ok(Property::Accessor(getter, setter))
+}
+
+/// The class of `value`, as given by `Object.prototype.toString`
+pub fn class_of(ctx: &Context, value: &Ref) -> Result<String> {
+    ctx.get_global_string("Object")
+        .get_prop_string(-1, "prototype")
+        .get_prop_string(-1, "toString")
+        .remove(-2)
+        .remove(-2);
+    value.push();
+    ctx.call_method(0)?.getp()
+}
+
+/// Whether `key` can be written without quotes in an object literal
+pub fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// `s` as a single quoted string literal
+pub fn quote(s: &str) -> String {
+    let mut out = String::from("'");
+    for c in s.chars() {
+        match c {
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
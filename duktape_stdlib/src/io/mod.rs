@@ -8,14 +8,26 @@ mod linereader;
 
 pub use self::types::*;
 use super::sources::IO_JS;
-use duktape_modules::{require, CJSContext};
+use duktape_modules::{CJSContext, NativeModule};
 use std::io;
 
 pub fn register(_ctx: &Context, builder: &mut Builder) {
-    builder.module("io", build_io);
+    builder.native_module("io", Io);
 }
 
-fn build_io(ctx: &Context) -> DukResult<i32> {
+struct Io;
+
+impl NativeModule for Io {
+    fn exports(&self, ctx: &Context, exports: &Object) -> DukResult<()> {
+        build_io(ctx, exports)
+    }
+
+    fn source(&self) -> Option<&[u8]> {
+        Some(IO_JS)
+    }
+}
+
+fn build_io(ctx: &Context, exports: &Object) -> DukResult<()> {
     let (reader, writer, readwriter) = (
         readers::build_reader(ctx)?,
         writers::build_writer(ctx)?,
@@ -31,8 +43,6 @@ fn build_io(ctx: &Context) -> DukResult<i32> {
         linereader::build_linereader_class(ctx, linereader.clone())?,
     );
 
-    let exports: Object = ctx.create()?;
-
     exports
         .set(b"\xFFreader", read_builder.clone())
         .set(b"\xFFwriter", write_builder.clone())
@@ -68,15 +78,7 @@ fn build_io(ctx: &Context) -> DukResult<i32> {
         .set("stdin", stdin)
         .set("stdout", stdout);
 
-    let module: Object = ctx.get(-1)?;
-    module.set("exports", exports);
-
-    require::eval_module(ctx, IO_JS, &module).unwrap();
-
-    module.get::<_, Ref>("exports")?.push();
-
-
-    Ok(1)
+    Ok(())
 }
 
 macro_rules! inherit_impl {
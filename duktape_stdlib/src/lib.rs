@@ -6,41 +6,80 @@ extern crate bitflags;
 #[cfg(feature = "http")]
 extern crate reqwest;
 
+mod assert;
 mod builder;
+mod child_process;
 mod fs;
 #[cfg(feature = "http")]
 mod http;
+pub mod inspect;
 mod io;
+mod permissions;
 mod process;
 mod sources;
 
 use duktape::prelude::*;
-use duktape_modules::require;
+use duktape_modules::NativeModule;
 
 pub use self::builder::Modules;
+pub use self::permissions::Permissions;
+pub use duktape_modules::FsPolicy;
+
+#[cfg(feature = "http")]
+pub use self::http::HttpFetcher;
 
 #[cfg(feature = "http")]
 fn init_http(builder: &mut duktape_modules::Builder, config: &builder::Modules) {
     if config.contains(Modules::Http) {
-        builder.module("http", http::init_http);
+        builder.native_module("http", http::Http);
+    }
+}
+
+/// A module implemented in javascript
+struct Script(&'static [u8]);
+
+impl NativeModule for Script {
+    fn exports(&self, _ctx: &Context, _exports: &Object) -> duktape::error::Result<()> {
+        Ok(())
+    }
+
+    fn source(&self) -> Option<&[u8]> {
+        Some(self.0)
     }
 }
 
-pub fn register(ctx: &Context, builder: &mut duktape_modules::Builder, config: builder::Modules) {
+pub fn register(
+    ctx: &Context,
+    builder: &mut duktape_modules::Builder,
+    config: builder::Modules,
+    permissions: Permissions,
+) {
+    // The same policy guards the fs module and the file loader used by require
+    builder.fs_policy(permissions.fs_policy().clone());
+    ctx.data_mut().unwrap().insert::<Permissions>(permissions);
+
     process::init_process(ctx).unwrap();
 
     io::register(ctx, builder);
 
     if config.contains(Modules::Fs) {
-        builder.module("fs", |ctx: &Context| fs::init_fs(ctx));
+        builder.native_module("fs", fs::Fs);
     }
 
     if config.contains(Modules::Utils) {
-        builder.module("utils", |ctx: &Context| {
-            let module: Object = ctx.get(-1)?;
-            require::eval_module(ctx, sources::UTILS, &module).unwrap();
-            Ok(1)
-        });
+        builder.native_module("utils", Script(sources::UTILS));
+    }
+
+    if config.contains(Modules::Assert) {
+        builder.native_module("assert", assert::Assert);
+    }
+
+    if config.contains(Modules::Test) {
+        builder.native_module("test", Script(sources::TEST));
+    }
+
+    if config.contains(Modules::ChildProcess) {
+        builder.native_module("child_process", child_process::init_child_process);
     }
 
     #[cfg(feature = "http")]
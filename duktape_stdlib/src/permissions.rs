@@ -0,0 +1,153 @@
+use duktape::error::{ErrorKind, Result};
+use duktape::prelude::*;
+use duktape::Key;
+use duktape_modules::FsPolicy;
+
+/// The capabilities granted to scripts.
+///
+/// The default grants everything, use `Permissions::none()` to start from
+/// an empty grant and allow capabilities one by one.
+#[derive(Clone, Debug)]
+pub struct Permissions {
+    fs: FsPolicy,
+    net: Option<Vec<String>>,
+    run: bool,
+    env: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Permissions {
+        Permissions {
+            fs: FsPolicy::new(),
+            net: None,
+            run: true,
+            env: true,
+        }
+    }
+}
+
+impl Permissions {
+    /// Grant everything
+    pub fn new() -> Permissions {
+        Permissions::default()
+    }
+
+    /// Grant nothing
+    pub fn none() -> Permissions {
+        let mut fs = FsPolicy::restricted();
+        fs.read_only(true);
+        Permissions {
+            fs,
+            net: Some(Vec::new()),
+            run: false,
+            env: false,
+        }
+    }
+
+    /// Set the policy for the fs module
+    pub fn fs(&mut self, policy: FsPolicy) -> &mut Self {
+        self.fs = policy;
+        self
+    }
+
+    pub fn fs_policy(&self) -> &FsPolicy {
+        &self.fs
+    }
+
+    /// Allow network access to every host
+    pub fn allow_net_all(&mut self) -> &mut Self {
+        self.net = None;
+        self
+    }
+
+    /// Allow network access to `host`. A host may include a port (`localhost:8080`)
+    pub fn allow_net<T: AsRef<str>>(&mut self, host: T) -> &mut Self {
+        self.net
+            .get_or_insert_with(Vec::new)
+            .push(host.as_ref().to_owned());
+        self
+    }
+
+    /// Allow spawning sub processes
+    pub fn allow_run(&mut self, allow: bool) -> &mut Self {
+        self.run = allow;
+        self
+    }
+
+    /// Allow reading environment variables
+    pub fn allow_env(&mut self, allow: bool) -> &mut Self {
+        self.env = allow;
+        self
+    }
+
+    pub fn check_net(&self, host: &str, port: Option<u16>) -> Result<()> {
+        let hosts = match &self.net {
+            Some(hosts) => hosts,
+            None => return Ok(()),
+        };
+
+        let with_port = port.map(|port| format!("{}:{}", host, port));
+
+        let found = hosts
+            .iter()
+            .any(|h| h == host || Some(h) == with_port.as_ref());
+
+        if found {
+            Ok(())
+        } else {
+            Err(ErrorKind::PermissionDenied(format!("network access to '{}'", host)).into())
+        }
+    }
+
+    pub fn check_run(&self, command: &str) -> Result<()> {
+        if self.run {
+            Ok(())
+        } else {
+            Err(ErrorKind::PermissionDenied(format!("running '{}'", command)).into())
+        }
+    }
+
+    pub fn check_env(&self) -> Result<()> {
+        if self.env {
+            Ok(())
+        } else {
+            Err(ErrorKind::PermissionDenied("environment access".to_string()).into())
+        }
+    }
+}
+
+impl Key for Permissions {
+    type Value = Self;
+}
+
+/// Run `check` against the permissions registered with the context.
+/// Everything is allowed, if no permissions has been registered
+pub(crate) fn check<F: FnOnce(&Permissions) -> Result<()>>(ctx: &Context, check: F) -> Result<()> {
+    match ctx.data()?.get::<Permissions>() {
+        Some(permissions) => check(permissions),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Permissions;
+
+    #[test]
+    fn check_net() {
+        assert!(Permissions::new().check_net("example.com", Some(443)).is_ok());
+        assert!(Permissions::none().check_net("example.com", Some(443)).is_err());
+
+        let mut permissions = Permissions::none();
+        permissions.allow_net("example.com").allow_net("localhost:8080");
+        assert!(permissions.check_net("example.com", Some(443)).is_ok());
+        assert!(permissions.check_net("example.com", None).is_ok());
+        assert!(permissions.check_net("localhost", Some(8080)).is_ok());
+        assert!(permissions.check_net("localhost", Some(8081)).is_err());
+        assert!(permissions.check_net("localhost", None).is_err());
+        assert!(permissions.check_net("sub.example.com", Some(443)).is_err());
+
+        permissions.allow_net_all();
+        assert!(permissions.check_net("localhost", None).is_ok());
+    }
+}
@@ -1,5 +1,7 @@
+use super::permissions;
 use duktape::error::Result;
 use duktape::prelude::*;
+use std::collections::HashMap;
 use std::env;
 
 pub fn init_process(ctx: &Context) -> Result<()> {
@@ -23,9 +25,50 @@ pub fn init_process(ctx: &Context) -> Result<()> {
         "unknown"
     };
 
-    process.set("platform", platform);
+    process
+        .set("platform", platform)
+        .set("argv", env::args().collect::<Vec<_>>());
+
+    // process.env is a getter, so the permission is checked on access
+    let descriptor: Object = ctx.create()?;
+    descriptor.set("enumerable", true).set("get", |ctx: &Context| {
+        permissions::check(ctx, |p| p.check_env())?;
+        ctx.push(env::vars().collect::<HashMap<String, String>>())?;
+        Ok(1)
+    });
+
+    ctx.get_global_string("Object")
+        .getp::<Object>()?
+        .call::<_, _, ()>("defineProperty", (process.clone(), "env", descriptor))?;
 
     global.set("process", process);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::init_process;
+    use crate::Permissions;
+    use duktape::prelude::*;
+
+    fn context(permissions: Permissions) -> Context {
+        let ctx = Context::new().unwrap();
+        ctx.data_mut().unwrap().insert::<Permissions>(permissions);
+        init_process(&ctx).unwrap();
+        ctx
+    }
+
+    #[test]
+    fn env_permission() {
+        let ctx = context(Permissions::none());
+        let err = ctx.eval("process.env").err().unwrap();
+        assert!(err.to_string().contains("environment access"), "{}", err);
+
+        let mut permissions = Permissions::none();
+        permissions.allow_env(true);
+        let ctx = context(permissions);
+        let kind: String = ctx.eval("typeof process.env").unwrap().getp().unwrap();
+        assert_eq!(kind, "object");
+    }
+}
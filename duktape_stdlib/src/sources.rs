@@ -4,3 +4,5 @@ pub(crate) static RUNTIME: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"
 pub(crate) static FS: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/fs.js"));
 pub(crate) static IO_JS: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/io.js"));
 pub(crate) static HTTP: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/http.js"));
+pub(crate) static TEST: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/test.js"));
+pub(crate) static ASSERT: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/assert.js"));
@@ -0,0 +1,106 @@
+// Run with `duk test duktape_stdlib/tests`
+var assert = require('assert');
+
+function message(fn) {
+    try {
+        fn();
+    } catch (e) {
+        return e.message;
+    }
+}
+
+describe('assert', function () {
+    test('ok', function () {
+        assert(true);
+        assert.ok(1);
+        expect(function () { assert.ok(0); }).toThrow(assert.AssertionError);
+        expect(function () { assert.ok(false, 'custom'); }).toThrow('custom');
+    });
+
+    test('equal', function () {
+        assert.equal(1, '1');
+        assert.equal(NaN, NaN);
+        assert.notEqual(1, 2);
+        assert.strictEqual(1, 1);
+        assert.notStrictEqual(1, '1');
+        assert.notStrictEqual(0, -0);
+        expect(function () { assert.strictEqual(1, '1'); }).toThrow('strictly equal');
+    });
+
+    test('deepEqual', function () {
+        assert.deepEqual({ a: [1, 2], b: { c: '3' } }, { b: { c: 3 }, a: [1, 2] });
+        assert.deepEqual(new Date(0), new Date(0));
+        assert.notDeepEqual([1, 2], [1, 2, 3]);
+        assert.notDeepEqual({ a: 1 }, { a: 1, b: undefined });
+        assert.notDeepEqual(/a/g, /a/i);
+        assert.notDeepEqual(new Number(1), new Number(2));
+        assert.notDeepEqual(new String('a'), new String('b'));
+
+        var a = { name: 'a' }, b = { name: 'a' };
+        a.self = a;
+        b.self = b;
+        assert.deepEqual(a, b);
+    });
+
+    test('deepStrictEqual', function () {
+        assert.deepStrictEqual({ a: [1, { b: NaN }] }, { a: [1, { b: NaN }] });
+        assert.notDeepStrictEqual({ a: 1 }, { a: '1' });
+        assert.notDeepStrictEqual([0], [-0]);
+        assert.notDeepStrictEqual(Object.create(null), {});
+        assert.notDeepStrictEqual([1], { 0: 1 });
+    });
+
+    test('diffs', function () {
+        var error;
+        try {
+            assert.deepStrictEqual({ a: 1, b: [1, 2] }, { a: 2, b: [1, 2] });
+        } catch (e) {
+            error = e;
+        }
+        expect(error.code).toBe('ERR_ASSERTION');
+        expect(error.operator).toBe('deepStrictEqual');
+        expect(error.generatedMessage).toBe(true);
+        expect(error.message).toBe([
+            'Expected values to be strictly deep-equal:',
+            '',
+            '+ actual - expected',
+            '',
+            '  {',
+            '+   a: 1,',
+            '-   a: 2,',
+            '    b: [',
+            '      1,',
+            '      2,',
+            '    ],',
+            '  }',
+            ''
+        ].join('\n'));
+
+        expect(message(function () { assert.strictEqual('a', 'b'); })).toContain("+ 'a'\n- 'b'");
+    });
+
+    test('throws', function () {
+        assert.throws(function () { throw new TypeError('bad value'); });
+        assert.throws(function () { throw new TypeError('bad value'); }, TypeError);
+        assert.throws(function () { throw new TypeError('bad value'); }, /bad/);
+        assert.throws(function () { throw new TypeError('bad value'); }, { name: 'TypeError', message: /value/ });
+        assert.throws(function () { throw new TypeError('bad value'); }, function (e) { return e instanceof Error; });
+
+        expect(message(function () { assert.throws(function () {}, TypeError); }))
+            .toBe('Missing expected exception (TypeError).');
+        expect(function () {
+            assert.throws(function () { throw new RangeError('bad'); }, TypeError);
+        }).toThrow(RangeError);
+        expect(function () {
+            assert.throws(function () { throw new TypeError('bad'); }, { message: 'good' });
+        }).toThrow(assert.AssertionError);
+
+        assert.doesNotThrow(function () {});
+        expect(function () { assert.doesNotThrow(function () { throw new Error('bad'); }); }).toThrow('unwanted');
+    });
+
+    test('fail', function () {
+        expect(message(function () { assert.fail(); })).toBe('Failed');
+        expect(function () { assert.fail(new RangeError('bad')); }).toThrow(RangeError);
+    });
+});
@@ -35,7 +35,7 @@ fn main() {
     let mut builder = cc::Build::new();
 
     builder
-        .file("duktape-2.3.0/src/duktape.c")
+        .file(source_dir().join("duktape.c"))
         .flag_if_supported("-fomit-frame-pointer")
         .flag_if_supported("-fstrict-aliasing");
     // .flag_if_supported("-fprofile-generate")
@@ -47,6 +47,93 @@ fn main() {
     builder.compile("libduktape.a");
 }
 
+/// Config options enabled by the debugger feature
+#[cfg(feature = "debugger")]
+static DEBUGGER_OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("DUK_USE_DEBUGGER_SUPPORT", ""),
+    ("DUK_USE_DEBUGGER_INSPECT", ""),
+    ("DUK_USE_DEBUGGER_PAUSE_UNCAUGHT", ""),
+    ("DUK_USE_DEBUGGER_DUMPHEAP", ""),
+    ("DUK_USE_INTERRUPT_COUNTER", ""),
+];
+
+/// Config options enabled by the profiler feature.
+/// The heap udata of a profiled heap starts with a pointer to the function called on interrupts.
+/// The function gets the udata and the interrupted thread, which is passed to the check by `source_dir`
+#[cfg(feature = "profiler")]
+static PROFILER_OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("DUK_USE_INTERRUPT_COUNTER", ""),
+    (
+        "DUK_USE_EXEC_TIMEOUT_CHECK",
+        "(udata, thr) ((udata) != NULL && (*(duk_bool_t (**)(void *, duk_context *)) (udata))((udata), (duk_context *) (thr)))",
+    ),
+];
+
+/// Replace `from` in `text`, failing the build if the sources do not contain it
+fn patch(text: &str, from: &str, to: &str) -> String {
+    assert!(text.contains(from), "could not patch the Duktape sources, missing {:?}", from);
+    text.replace(from, to)
+}
+
+/// Config options, which are enabled by features, with the value they are defined to
+fn options() -> Vec<(&'static str, &'static str)> {
+    #[allow(unused_mut)]
+    let mut options = Vec::new();
+    #[cfg(feature = "debugger")]
+    options.extend_from_slice(DEBUGGER_OPTIONS);
+    #[cfg(feature = "profiler")]
+    options.extend_from_slice(PROFILER_OPTIONS);
+    // Features may share options, which are defined once
+    options.sort();
+    options.dedup();
+    options
+}
+
+/// Copy the sources to the output directory with a duk_config.h, which enables the options of the features.
+/// duktape.h includes duk_config.h from its own directory, so it is copied along
+fn source_dir() -> PathBuf {
+    use std::fs;
+
+    let options = options();
+    if options.is_empty() {
+        return PathBuf::from("duktape-2.3.0/src");
+    }
+
+    let dir = PathBuf::from(env::var("OUT_DIR").unwrap()).join("duktape");
+    fs::create_dir_all(&dir).unwrap();
+    fs::copy("duktape-2.3.0/src/duktape.h", dir.join("duktape.h")).unwrap();
+
+    #[allow(unused_mut)]
+    let mut source = fs::read_to_string("duktape-2.3.0/src/duktape.c").unwrap();
+    // Interrupt every 16k instead of 256k instructions, for samples about every millisecond
+    #[cfg(feature = "profiler")]
+    {
+        source = patch(
+            &source,
+            "#define DUK_HTHREAD_INTCTR_DEFAULT     (256L * 1024L)",
+            "#define DUK_HTHREAD_INTCTR_DEFAULT     (16L * 1024L)",
+        );
+        source = patch(
+            &source,
+            "DUK_USE_EXEC_TIMEOUT_CHECK(thr->heap->heap_udata)",
+            "DUK_USE_EXEC_TIMEOUT_CHECK(thr->heap->heap_udata, thr)",
+        );
+    }
+    fs::write(dir.join("duktape.c"), source).unwrap();
+
+    let mut config = fs::read_to_string("duktape-2.3.0/src/duk_config.h").unwrap();
+    for (option, value) in options {
+        config = patch(
+            &config,
+            &format!("#undef {}\n", option),
+            &format!("#define {}{}\n", option, value),
+        );
+    }
+    fs::write(dir.join("duk_config.h"), config).unwrap();
+
+    dir
+}
+
 
 
 #[cfg(feature = "buildtime_bindgen")]